@@ -0,0 +1,1638 @@
+//! 编译器的库接口
+//!
+//! 与命令行相同的各个编译模式, 但是错误会被作为[`Diagnostics`]返回,
+//! 而不是输出到标准错误并退出进程, 可以被编辑器与构建脚本等直接嵌入
+
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Debug, Display},
+    mem,
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use display_source::{
+    DisplaySource,
+    DisplaySourceMeta,
+};
+use syntax::{
+    CompileExit,
+    CompileMeta,
+    Compile,
+    CompileMetaExtends,
+    Expand,
+    Goto,
+    InlineBlock,
+    JumpCmp,
+    LogicLine,
+    Meta,
+    SourceMap,
+    SourcePos,
+    TestCase,
+    Value,
+};
+use parser::{import::Importer, std_lib};
+use tag_code::{
+    logic_parser::{parser as tparser, ParseLines},
+    pass::{Pass, PassManager, PassReport},
+    mdt_logic_split_unwraped,
+    TagCodes,
+};
+use logic_lint::{
+    const_fold, copy_propagation, dead_store,
+    lints::target_unsupported,
+    reg_alloc,
+    split::split_program,
+    Source,
+};
+use logic_emulator::{Emulator, Program, Status};
+use schematic::{base64, ProcessorSchematic, Schematic};
+pub use schematic::ProcessorType;
+pub use var_utils::Target;
+
+pub use diagnostic::{codes, Code, Diagnostic, Severity, Span, Stage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompileMode {
+    BangToMdtLogic,
+    BangToASTDebug,
+    BangToASTDisplay,
+    BangToMdtTagCode { tag_down: bool },
+    MdtLogicToMdtTagCode { tag_down: bool },
+    MdtLogicToBang,
+    MdtTagCodeToMdtLogic,
+    LintLogic,
+    IndentLogic,
+    RenameLabel,
+    BangToMdtLabel,
+    BuildExpr,
+    ParenToMdtLogic,
+    EmulateLogic,
+    /// 在`# split NAME`处拆分为多个处理器的逻辑, 输出为描述各处理器的清单,
+    /// 各处理器的逻辑见[`Output::processors`]
+    BangToSplitLogic,
+    /// 将逻辑语言放入处理器的蓝图, 输出为可以从剪贴板导入的文本,
+    /// 二进制的`.msch`文件见[`Output::schematic`]
+    MdtLogicToSchematic,
+    /// 读取蓝图或处理器配置的base64文本, 输出为描述各处理器的清单,
+    /// 各处理器的逻辑见[`Output::processors`]
+    SchematicToMdtLogic,
+}
+impl TryFrom<char> for CompileMode {
+    type Error = char;
+
+    fn try_from(mode: char) -> Result<Self, Self::Error> {
+        Ok(match mode {
+            'c' => Self::BangToMdtLogic,
+            'a' => Self::BangToASTDebug,
+            'A' => Self::BangToASTDisplay,
+            't' => Self::BangToMdtTagCode { tag_down: false },
+            'T' => Self::BangToMdtTagCode { tag_down: true },
+            'f' => Self::MdtLogicToMdtTagCode { tag_down: false },
+            'F' => Self::MdtLogicToMdtTagCode { tag_down: true },
+            'r' => Self::MdtLogicToBang,
+            'C' => Self::MdtTagCodeToMdtLogic,
+            'l' => Self::LintLogic,
+            'i' => Self::IndentLogic,
+            'n' => Self::RenameLabel,
+            'L' => Self::BangToMdtLabel,
+            'b' => Self::BuildExpr,
+            'p' => Self::ParenToMdtLogic,
+            'e' => Self::EmulateLogic,
+            's' => Self::BangToSplitLogic,
+            'm' => Self::MdtLogicToSchematic,
+            'M' => Self::SchematicToMdtLogic,
+            mode => return Err(mode),
+        })
+    }
+}
+impl CompileMode {
+    /// 从命令行的模式字符串中解析, 失败时返回无法识别的模式字符
+    pub fn parse_modes(modes: &str) -> Result<Vec<Self>, char> {
+        modes.chars().map(Self::try_from).collect()
+    }
+
+    /// 批量编译时输出文件的扩展名
+    pub fn output_extension(&self) -> &'static str {
+        match self {
+            Self::BangToMdtLogic
+            | Self::MdtTagCodeToMdtLogic
+            | Self::LintLogic
+            | Self::IndentLogic
+            | Self::RenameLabel
+            | Self::BangToMdtLabel
+            | Self::ParenToMdtLogic => "logic",
+            Self::BangToASTDisplay
+            | Self::MdtLogicToBang => parser::import::EXTENSION,
+            Self::BangToMdtTagCode { .. }
+            | Self::MdtLogicToMdtTagCode { .. } => "tagcode",
+            Self::BangToASTDebug
+            | Self::BuildExpr
+            | Self::EmulateLogic
+            | Self::BangToSplitLogic
+            | Self::MdtLogicToSchematic
+            | Self::SchematicToMdtLogic => "txt",
+        }
+    }
+}
+
+/// 编译失败时的所有诊断, 最后一个为导致失败的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub diagnostics: Vec<Diagnostic>,
+    /// 与命令行相同的退出码
+    pub exit_code: i32,
+}
+impl Diagnostics {
+    fn new(exit_code: i32, diagnostic: Diagnostic) -> Self {
+        Self { diagnostics: vec![diagnostic], exit_code }
+    }
+
+    /// 导致失败的错误
+    pub fn error(&self) -> &Diagnostic {
+        self.diagnostics.last().unwrap()
+    }
+}
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.error(), f)
+    }
+}
+impl std::error::Error for Diagnostics { }
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileOptions {
+    /// 依次使用的编译模式, 后一个模式的输入为前一个的输出
+    pub modes: Vec<CompileMode>,
+    /// 源码所在的文件, 用于相对导入与错误信息中的文件名
+    pub path: Option<PathBuf>,
+    /// 导入的搜索路径
+    pub search_paths: Vec<PathBuf>,
+    /// 模拟执行逻辑语言时的配置
+    pub emulate: logic_emulator::Config,
+    /// 是否生成从逻辑行到源码的映射, 仅在最后一个模式为
+    /// [`CompileMode::BangToMdtLogic`]时生成, 见[`Output::source_map`]
+    pub source_map: bool,
+    pub optimize: OptimizeOptions,
+    pub size: SizeOptions,
+    /// 拆分程序时用于处理器之间通信的内存元, 默认为[`DEFAULT_SPLIT_CELL`]
+    pub split_cell: Option<String>,
+    pub schematic: SchematicOptions,
+    /// 生成的逻辑所面向的游戏版本, 为[`None`]时不检查指令是否可用,
+    /// 并且与[`Target::World`]相同的使用所有指令
+    pub target: Option<Target>,
+    /// 使用了标准库时是否在逻辑首行加入[`std_stamp`]
+    pub std_stamp: bool,
+}
+impl CompileOptions {
+    pub fn new(modes: impl Into<Vec<CompileMode>>) -> Self {
+        Self { modes: modes.into(), ..Default::default() }
+    }
+
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// 使用[`parser::import::PATH_ENV`]中的搜索路径
+    pub fn env_search_paths(mut self) -> Self {
+        let importer = Importer::from_env();
+        self.search_paths.extend_from_slice(importer.search_paths());
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.emulate.max_steps = max_steps;
+        self
+    }
+
+    /// 模拟执行时在`end`或末尾处是否像游戏中一样回到首行继续执行,
+    /// 此时直到达到最大指令数才会停止
+    pub fn wrap_end(mut self, wrap_end: bool) -> Self {
+        self.emulate.halt_on_end = !wrap_end;
+        self
+    }
+
+    pub fn source_map(mut self, source_map: bool) -> Self {
+        self.source_map = source_map;
+        self
+    }
+
+    pub fn opt_level(mut self, level: u8) -> Self {
+        self.optimize.level = level;
+        self
+    }
+
+    /// 在优化等级之外单独启用或禁用一个优化, 可用的优化见[`passes`]
+    pub fn pass(mut self, name: impl Into<String>, enabled: bool) -> Self {
+        self.optimize.passes.push((name.into(), enabled));
+        self
+    }
+
+    /// 将生命周期不重叠的临时变量合并为同一个名称, 以减少逻辑中的变量数
+    pub fn reuse_temps(self, reuse_temps: bool) -> Self {
+        self.pass("reuse-temps", reuse_temps)
+    }
+
+    pub fn pass_report(mut self, pass_report: bool) -> Self {
+        self.optimize.report = pass_report;
+        self
+    }
+
+    /// 设置处理器的指令数限制, 为[`None`]时不进行检查
+    pub fn instruction_limit(mut self, limit: Option<usize>) -> Self {
+        self.size.limit = limit;
+        self
+    }
+
+    /// 超过指令数限制时是否作为错误而不是警告
+    pub fn deny_limit(mut self, deny: bool) -> Self {
+        self.size.deny = deny;
+        self
+    }
+
+    pub fn size_report(mut self, size_report: bool) -> Self {
+        self.size.report = size_report;
+        self
+    }
+
+    pub fn split_cell(mut self, cell: impl Into<String>) -> Self {
+        self.split_cell = Some(cell.into());
+        self
+    }
+
+    pub fn processor(mut self, processor: ProcessorType) -> Self {
+        self.schematic.processor = processor;
+        self
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn std_stamp(mut self, std_stamp: bool) -> Self {
+        self.std_stamp = std_stamp;
+        self
+    }
+
+    /// 添加一个蓝图中处理器链接的建筑, 见[`ProcessorSchematic::links`]
+    pub fn link(mut self, name: impl Into<String>) -> Self {
+        self.schematic.links.push(name.into());
+        self
+    }
+}
+
+/// 生成的蓝图中的处理器
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchematicOptions {
+    pub processor: ProcessorType,
+    pub links: Vec<String>,
+    /// 蓝图的名称, 默认为输入文件的名称
+    pub name: Option<String>,
+}
+
+pub const DEFAULT_SPLIT_CELL: &str = "bank1";
+
+/// 对生成的逻辑的指令数的检查
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeOptions {
+    /// 处理器的指令数限制, 游戏中为1000
+    pub limit: Option<usize>,
+    /// 超过限制时是否作为错误
+    pub deny: bool,
+    /// 是否生成指令数报告, 见[`Output::size_report`]
+    pub report: bool,
+}
+impl Default for SizeOptions {
+    fn default() -> Self {
+        Self { limit: Some(1000), deny: false, report: false }
+    }
+}
+
+/// 对生成的逻辑的优化
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    /// 优化等级, 默认为0, 即不进行优化
+    pub level: u8,
+    /// 依次对单个优化的启用与禁用, 优先于优化等级
+    pub passes: Vec<(String, bool)>,
+    /// 是否生成每个优化前后的行数报告, 见[`Output::pass_report`],
+    /// 并将每个优化进行的次数作为日志
+    pub report: bool,
+}
+
+/// 所有可用的优化, 以及启用它们所需的优化等级, 初始时均未启用
+pub fn passes() -> PassManager {
+    PassManager::new()
+        .with(Pass::new("const-fold", 1, |tag_codes| {
+            Ok(const_fold::fold_constants(tag_codes))
+        }))
+        .with(Pass::new(
+            "copy-propagation",
+            2,
+            copy_propagation::optimize_copies,
+        ))
+        .with(Pass::new("dead-code", 1, TagCodes::eliminate_dead_code))
+        .with(Pass::new("jump-optimize", 1, TagCodes::optimize_jumps))
+        .with(Pass::new("dead-store", 2, dead_store::eliminate_dead_stores))
+        .with(Pass::new("reuse-temps", Pass::MANUAL, reg_alloc::reuse_temps))
+}
+
+/// 一个生成的逻辑行的来源
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMapping {
+    /// 逻辑行的行号, 与跳转目标相同从0开始
+    pub line: usize,
+    /// 产生该行的语句所在位置, 例如编译器添加的标记行没有位置
+    pub source: Option<SourcePos>,
+    /// 产生该行时的const展开栈, 由外至内
+    pub expand_stack: Vec<String>,
+}
+
+/// 从生成的逻辑行到源码的映射
+///
+/// 每行一个逻辑行, 以制表符分隔行号, 来源位置与const展开栈,
+/// 没有来源的位置显示为`?`, 展开栈各层以` > `分隔
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineMap {
+    pub lines: Vec<LineMapping>,
+}
+impl LineMap {
+    /// 获取逻辑行的来源
+    pub fn get(&self, line: usize) -> Option<&LineMapping> {
+        self.lines.get(line)
+    }
+}
+impl Display for LineMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for mapping in &self.lines {
+            write!(f, "{}\t", mapping.line)?;
+            match &mapping.source {
+                Some(pos) => write!(f, "{pos}")?,
+                None => f.write_str("?")?,
+            }
+            if !mapping.expand_stack.is_empty() {
+                write!(f, "\t{}", mapping.expand_stack.join(" > "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Output {
+    pub text: String,
+    /// 编译期间输出的日志与lint等非致命的诊断
+    pub logs: Vec<Diagnostic>,
+    /// 启用[`CompileOptions::source_map`]时生成的映射
+    pub source_map: Option<LineMap>,
+    /// 启用[`OptimizeOptions::report`]时生成的每个优化前后的行数
+    pub pass_report: Option<PassReport>,
+    /// 启用[`SizeOptions::report`]时生成的指令数报告
+    pub size_report: Option<SizeReport>,
+    /// 模式为[`CompileMode::BangToSplitLogic`]
+    /// 或[`CompileMode::SchematicToMdtLogic`]时每个处理器的逻辑,
+    /// 顺序与清单中的相同, 之后的模式会分别作用于每个处理器的逻辑
+    pub processors: Vec<String>,
+    /// 最后一个模式为[`CompileMode::MdtLogicToSchematic`]时的`.msch`文件内容
+    pub schematic: Option<Vec<u8>>,
+}
+
+/// 生成的指令数, 以及每个顶层的const展开所产生的指令数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    pub total: usize,
+    pub limit: Option<usize>,
+    /// 从多到少排列, 不在任何展开中的行的名称为[`None`]
+    pub expansions: Vec<(Option<String>, usize)>,
+}
+impl SizeReport {
+    fn new(
+        meta: &CompileMeta,
+        sources: &[Option<usize>],
+        limit: Option<usize>,
+    ) -> Self {
+        let line_sources = meta.line_sources();
+        let mut counts: HashMap<Option<String>, usize> = HashMap::new();
+        for source in sources {
+            let name = source.and_then(|id| line_sources.get(id))
+                .and_then(|source| source.expand_stack.first())
+                .map(|(name, _)| name.to_string());
+            *counts.entry(name).or_default() += 1;
+        }
+        let mut expansions = Vec::from_iter(counts);
+        expansions.sort_by(|(a_name, a), (b_name, b)| {
+            b.cmp(a).then_with(|| a_name.cmp(b_name))
+        });
+        Self { total: sources.len(), limit, expansions }
+    }
+}
+/// 第一行为总指令数, 其后每行为展开所产生的指令数与其名称
+impl Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            Some(limit) => {
+                writeln!(f, "instructions: {} / {limit}", self.total)?;
+            },
+            None => writeln!(f, "instructions: {}", self.total)?,
+        }
+        for (name, count) in &self.expansions {
+            let name = name.as_deref().unwrap_or("<top level>");
+            writeln!(f, "{count:>8}  {name}")?;
+        }
+        Ok(())
+    }
+}
+
+type Result<T, E = Diagnostics> = std::result::Result<T, E>;
+
+/// 使用选项中的每个模式依次编译,
+/// 仅第一个模式的输入来自[`CompileOptions::path`]
+pub fn compile(source: &str, options: &CompileOptions) -> Result<Output> {
+    let mut output = Output { text: source.into(), ..Default::default() };
+    let mut path = options.path.as_deref();
+    for (i, &mode) in options.modes.iter().enumerate() {
+        let src = mem::take(&mut output.text);
+        let first_log = output.logs.len();
+        let path = path.take();
+        let result = compile_mode(mode, src, path, options, &mut output);
+        let logs = &mut output.logs;
+        // 仅第一个模式的诊断位置是位于输入文件中的
+        let file = path.map(|path| Rc::from(path.display().to_string()));
+        let fill_file = |diag: &mut Diagnostic| {
+            if let Some(file) = &file { diag.fill_file(file) }
+        };
+        logs[first_log..].iter_mut().for_each(fill_file);
+        output.text = result
+            .map_err(|mut e| {
+                e.diagnostics.iter_mut().for_each(fill_file);
+                // 失败前的日志也应被返回
+                logs.append(&mut e.diagnostics);
+                e.diagnostics = mem::take(logs);
+                e
+            })?;
+        let rest = &options.modes[i+1..];
+        if !output.processors.is_empty() && !rest.is_empty() {
+            let options = CompileOptions {
+                modes: rest.to_vec(),
+                path: None,
+                ..options.clone()
+            };
+            for processor in &mut output.processors {
+                let mut processor_output = compile(processor, &options)
+                    .map_err(|mut e| {
+                        output.logs.append(&mut e.diagnostics);
+                        e.diagnostics = mem::take(&mut output.logs);
+                        e
+                    })?;
+                output.logs.append(&mut processor_output.logs);
+                *processor = processor_output.text;
+            }
+            break;
+        }
+    }
+    Ok(output)
+}
+
+fn compile_mode(
+    mode: CompileMode,
+    src: String,
+    path: Option<&Path>,
+    options: &CompileOptions,
+    output: &mut Output,
+) -> Result<String> {
+    type Mode = CompileMode;
+    let Output {
+        logs, source_map, pass_report, size_report, processors, schematic, ..
+    } = output;
+    // 映射与报告只对最后生成的逻辑行有效
+    *source_map = None;
+    *pass_report = None;
+    *size_report = None;
+    processors.clear();
+    *schematic = None;
+    Ok(match mode {
+        Mode::BangToMdtLogic => {
+            let mut importer = importer(options);
+            let mut ast_meta = ast_meta(options);
+            let record_sources = options.source_map
+                || options.size.report
+                || options.target.is_some();
+            ast_meta.record_locations = record_sources;
+            let (ast, src_map)
+                = load_ast(&mut importer, &mut ast_meta, &src, path)?;
+            let mut meta = compile_ast_with(
+                ast, src.clone(), src_map, logs,
+                |meta| meta.set_record_line_sources(record_sources),
+            )?;
+            let logic_codes = mem::take(meta.parse_lines_mut());
+            let mut tag_codes = logic_to_tagcode(logic_codes, &src)?;
+            let report = optimize(&mut tag_codes, &options.optimize, logs)?;
+            if options.optimize.report {
+                *pass_report = Some(report);
+            }
+            let mut logic_lines = tag_codes.compile()
+                .map_err(tag_down_err)?;
+            let mut sources = tag_codes.sources().collect::<Vec<_>>();
+            // 在加入注释之前检查, 注释并不占用处理器的指令数
+            if options.size.report {
+                let limit = options.size.limit;
+                *size_report = Some(SizeReport::new(&meta, &sources, limit));
+            }
+            check_size(logic_lines.len(), &options.size, logs)?;
+            check_target(&logic_lines, &sources, &meta, options.target)?;
+            if options.std_stamp && importer.uses_std() {
+                logic_lines.insert(0, std_stamp());
+                sources.insert(0, None);
+            }
+            if options.source_map {
+                *source_map = Some(line_map(&meta, &sources));
+            }
+            logic_lines.join("\n")
+        },
+        Mode::BangToSplitLogic => {
+            let mut importer = importer(options);
+            let mut ast_meta = ast_meta(options);
+            let record_sources = options.target.is_some();
+            ast_meta.record_locations = record_sources;
+            let (ast, src_map)
+                = load_ast(&mut importer, &mut ast_meta, &src, path)?;
+            let mut meta = compile_ast_with(
+                ast, src.clone(), src_map, logs,
+                |meta| meta.set_record_line_sources(record_sources),
+            )?;
+            let logic_codes = mem::take(meta.parse_lines_mut());
+            let mut tag_codes = logic_to_tagcode(logic_codes, &src)?;
+            let report = optimize(&mut tag_codes, &options.optimize, logs)?;
+            if options.optimize.report {
+                *pass_report = Some(report);
+            }
+            let cell = options.split_cell.as_deref()
+                .unwrap_or(DEFAULT_SPLIT_CELL);
+            let mut split = split_program(&tag_codes, cell).map_err(|e| {
+                let message = e.to_string();
+                let diag = Diagnostic::error(codes::TAG_CODE_SPLIT, message);
+                Diagnostics::new(4, diag)
+            })?;
+            for part in &mut split.parts {
+                let mut logic_lines = part.tag_codes.compile()
+                    .map_err(tag_down_err)?;
+                check_size(logic_lines.len(), &options.size, logs)?;
+                let sources = part.tag_codes.sources().collect::<Vec<_>>();
+                check_target(&logic_lines, &sources, &meta, options.target)?;
+                if options.std_stamp && importer.uses_std() {
+                    logic_lines.insert(0, std_stamp());
+                }
+                processors.push(logic_lines.join("\n"));
+            }
+            split.to_string().trim_end().into()
+        },
+        Mode::BangToASTDebug => {
+            let (ast, _) = build_ast(&mut importer(options), &src, path)?;
+            format!("{ast:#?}")
+        },
+        Mode::BangToASTDisplay => {
+            let (ast, _) = build_ast(&mut importer(options), &src, path)?;
+            display_ast(&ast)
+        },
+        Mode::BangToMdtTagCode { tag_down } => {
+            let (ast, source_map)
+                = build_ast(&mut importer(options), &src, path)?;
+            let mut meta = compile_ast(ast, src.clone(), source_map, logs)?;
+            let logic_codes = mem::take(meta.parse_lines_mut());
+            let mut tag_codes = logic_to_tagcode(logic_codes, &src)?;
+            if tag_down { build_tag_down(&mut tag_codes)?; }
+            tag_codes.to_string()
+        },
+        Mode::MdtLogicToMdtTagCode { tag_down } => {
+            let mut lines = logic_src_to_tagcode(&src)?;
+            if tag_down {
+                lines.build_tagdown().map_err(tag_down_err)?;
+                lines.tag_up();
+            }
+            lines.to_string()
+        },
+        Mode::MdtLogicToBang => {
+            let logic_lines = logic_parse(&src)?;
+            let ast = Expand::try_from(logic_lines)
+                .map_err(|e| {
+                    let (line, col) = e.location(&src);
+                    let message
+                        = format!("MdtLogicToBang {line}:{col} {}", e.value);
+                    let diag = Diagnostic::error(codes::LOGIC_TO_BANG, message)
+                        .with_primary(Span::point(None, line, col));
+                    Diagnostics::new(4, diag)
+                })?;
+            display_ast(&ast)
+        },
+        Mode::MdtTagCodeToMdtLogic => {
+            let mut tag_codes = logic_src_to_tagcode(&src)?;
+            build_tag_down(&mut tag_codes)?;
+            let logic_lines = tag_codes.compile()
+                .map_err(tag_down_err)?;
+            logic_lines.join("\n")
+        },
+        Mode::LintLogic => {
+            let linter = Source::from_str(&src)
+                .with_target(options.target.unwrap_or_default());
+            logs.extend(linter.lint().iter()
+                .map(|lint| linter.lint_diagnostic(lint)));
+            src
+        },
+        Mode::IndentLogic => {
+            let mut logic_lines = logic_parse(&src)?;
+            logic_lines.index_label_popup();
+            format!("{logic_lines:#}")
+        },
+        Mode::RenameLabel => {
+            let mut logic_lines = logic_parse(&src)?;
+            logic_lines.for_each_inner_label_mut(|mut lab| {
+                lab.to_mut().push_str("_RENAME");
+            });
+            format!("{logic_lines:#}")
+        },
+        Mode::BangToMdtLabel => {
+            let (ast, source_map)
+                = build_ast(&mut importer(options), &src, path)?;
+            let mut meta = compile_ast(ast, src.clone(), source_map, logs)?;
+            meta.parse_lines_mut().index_label_popup();
+            format!("{}", meta.parse_lines())
+        },
+        Mode::BuildExpr => {
+            let lines = logic_parse(&src)?;
+            let out = tag_code::expr_builder::build(lines.iter()
+                .map(|x| &**x));
+            out.join("\n")
+        },
+        Mode::ParenToMdtLogic => {
+            let lines = mini_paren::parser::lines(&src).map_err(|e| {
+                let (line, column) = (e.location.line, e.location.column);
+                let message = format!(
+                    "ParseParenCode {line}:{column} expected {}",
+                    e.expected,
+                );
+                let span = Span::point(None, line as u32, column as u32);
+                let diag = Diagnostic::error(codes::PAREN_PARSE, message)
+                    .with_primary(span);
+                Diagnostics::new(9, diag)
+            })?;
+            let mut state = mini_paren::State::default();
+            state.process_lines(&lines);
+            state.out.truncate(state.out.trim_end().len());
+            state.out
+        },
+        Mode::EmulateLogic => {
+            let program = emulate_program(&src)?;
+            logs.extend(program.unsupported().iter().map(|cmd| {
+                let (line, column) = cmd.location(&src);
+                let message = format!(
+                    "Emulate {line}:{column} 不支持模拟的指令 `{}`, \
+                    将被视作noop",
+                    cmd.value,
+                );
+                Diagnostic::warning(codes::EMULATE_UNSUPPORTED, message)
+                    .with_primary(Span::point(None, line, column))
+            }));
+            let mut emulator = Emulator::new(program, options.emulate);
+            emulator.run();
+            let mut report = emulator.to_string();
+            report.truncate(report.trim_end().len());
+            report
+        },
+        Mode::MdtLogicToSchematic => {
+            let schematic_options = &options.schematic;
+            // 前面的模式已经使用了`path`, 所以从选项中获取输入文件
+            let name = schematic_options.name.clone()
+                .or_else(|| {
+                    let stem = options.path.as_ref()?.file_stem()?;
+                    Some(stem.to_string_lossy().into())
+                })
+                .unwrap_or_else(|| "processor".into());
+            let processor_type = schematic_options.processor;
+            let mut processor
+                = ProcessorSchematic::new(name, processor_type, src);
+            processor.links.clone_from(&schematic_options.links);
+            let bytes = processor.to_bytes();
+            let text = base64::encode(&bytes);
+            *schematic = Some(bytes);
+            text
+        },
+        Mode::SchematicToMdtLogic => {
+            let (manifest, codes) = import_processors(&src)?;
+            *processors = codes;
+            manifest
+        },
+    })
+}
+
+/// 从蓝图或单个处理器配置的base64文本中读取每个处理器,
+/// 返回描述各处理器的位置与链接的清单, 以及各处理器的逻辑
+fn import_processors(src: &str) -> Result<(String, Vec<String>)> {
+    let err = |e: schematic::Error| {
+        let message = format!("读取蓝图失败: {e}");
+        Diagnostics::new(4, Diagnostic::error(codes::SCHEMATIC_PARSE, message))
+    };
+    let bytes = base64::decode(src)
+        .map_err(|i| err(schematic::Error::Base64(i)))?;
+    if !bytes.starts_with(schematic::HEADER) {
+        let (code, links) = schematic::decode_config(&bytes).map_err(err)?;
+        let manifest = fmt_processor(0, "processor", &links);
+        return Ok((manifest.trim_end().into(), vec![code]));
+    }
+    let imported = Schematic::from_bytes(&bytes).map_err(err)?;
+    let mut manifest = String::new();
+    if let Some(name) = imported.tag("name") {
+        manifest += &format!("name {name}\n");
+    }
+    let mut codes = vec![];
+    for tile in &imported.tiles {
+        let Some(processor) = tile.processor() else { continue };
+        let (code, links) = processor.map_err(err)?;
+        let desc = format!("{} ({}, {})", tile.block, tile.x, tile.y);
+        manifest += &fmt_processor(codes.len(), &desc, &links);
+        codes.push(code);
+    }
+    manifest.truncate(manifest.trim_end().len());
+    Ok((manifest, codes))
+}
+
+fn fmt_processor(i: usize, desc: &str, links: &[schematic::Link]) -> String {
+    let mut s = format!("processor {i} {desc}\n");
+    if !links.is_empty() {
+        let links = links.iter()
+            .map(|link| format!("{} ({}, {})", link.name, link.x, link.y))
+            .collect::<Vec<_>>();
+        s += &format!("    links: {}\n", links.join(" "));
+    }
+    s
+}
+
+fn importer(options: &CompileOptions) -> Importer {
+    let mut importer = Importer::new();
+    for path in &options.search_paths {
+        importer.add_search_path(path);
+    }
+    importer
+}
+
+/// 记录编译时使用的标准库修订号, 作为逻辑注释放置于输出首行
+pub fn std_stamp() -> String {
+    format!("# {} revision {}", std_lib::NAMESPACE, std_lib::revision())
+}
+
+fn logic_to_tagcode(lines: ParseLines<'_>, src: &str) -> Result<TagCodes> {
+    TagCodes::try_from(lines).map_err(|e| {
+        let (line, column) = e.location(src);
+        let message = format!("ParseTagCode {line}:{column} {}", e.value);
+        Diagnostics::new(10, Diagnostic::error(codes::TAG_CODE_PARSE, message)
+            .with_primary(Span::point(None, line, column))
+            .with_suggestion("或许你可以使用`Li`选项编译来详细查看"))
+    })
+}
+
+fn logic_parse(src: &str) -> Result<ParseLines<'_>> {
+    tparser::lines(src).map_err(|e| {
+        let (line, column) = (e.location.line, e.location.column);
+        let message = format!("ParseLogicCode {line}:{column} expected {}",
+            e.expected,
+        );
+        let span = Span::point(None, line as u32, column as u32);
+        Diagnostics::new(9, Diagnostic::error(codes::LOGIC_PARSE, message)
+            .with_primary(span))
+    })
+}
+
+fn emulate_program(src: &str) -> Result<Program> {
+    let lines = logic_parse(src)?;
+    Program::new(&lines).map_err(|e| {
+        let (line, column) = e.location(src);
+        let message = format!("Emulate {line}:{column} {}", e.value);
+        Diagnostics::new(11, Diagnostic::error(codes::EMULATE_PROGRAM, message)
+            .with_primary(Span::point(None, line, column)))
+    })
+}
+
+fn logic_src_to_tagcode(src: &str) -> Result<TagCodes> {
+    let lines = logic_parse(src)?;
+    logic_to_tagcode(lines, src)
+}
+
+fn display_ast(ast: &Expand) -> String {
+    let mut meta = Default::default();
+    ast.display_source(&mut meta);
+    let _ = meta.pop_lf();
+    meta.buffer().into()
+}
+
+fn tag_down_err((line, tag): (usize, impl Debug)) -> Diagnostics {
+    let message = format!("重复的标记: {tag:?} (line {line})");
+    let diag = Diagnostic::error(codes::TAG_CODE_DUPLICATE_TAG, message);
+    Diagnostics::new(4, diag)
+}
+
+/// 超过指令数限制时报告警告, 或者在[`SizeOptions::deny`]时报告错误
+fn check_size(
+    count: usize,
+    options: &SizeOptions,
+    logs: &mut Vec<Diagnostic>,
+) -> Result<()> {
+    let Some(limit) = options.limit.filter(|&limit| count > limit) else {
+        return Ok(());
+    };
+    let message
+        = format!("生成了 {count} 条指令, 超过了处理器的 {limit} 条指令限制");
+    if options.deny {
+        let code = codes::TAG_CODE_INSTRUCTION_LIMIT_EXCEEDED;
+        return Err(Diagnostics::new(4, Diagnostic::error(code, message)));
+    }
+    let code = codes::TAG_CODE_INSTRUCTION_LIMIT;
+    logs.push(Diagnostic::warning(code, message));
+    Ok(())
+}
+
+/// 检查生成的逻辑中是否有目标版本中不可用的指令或操作符,
+/// `sources`为每行的来源编号, 用于定位到源码
+fn check_target(
+    logic_lines: &[String],
+    sources: &[Option<usize>],
+    meta: &CompileMeta,
+    target: Option<Target>,
+) -> Result<()> {
+    let Some(target) = target else { return Ok(()) };
+    let diagnostics = logic_lines.iter()
+        .zip(sources)
+        .filter_map(|(line, source)| {
+            let args = mdt_logic_split_unwraped(line);
+            let arg = args[target_unsupported(target, &args)?];
+            let message = format!(
+                "`{arg}` 在目标版本{target}中不可用, 生成的行: {line}",
+            );
+            let span = source.and_then(|id| meta.line_sources().get(id))
+                .and_then(|source| meta.source_map().location(source.index))
+                .map(|pos| {
+                    Span::point(pos.file, pos.line, pos.column)
+                        .with_label(arg)
+                });
+            let code = codes::TAG_CODE_UNSUPPORTED_BY_TARGET;
+            Some(Diagnostic::error(code, message).with_primary(span))
+        })
+        .collect::<Vec<_>>();
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+    Err(Diagnostics { diagnostics, exit_code: 4 })
+}
+
+fn build_tag_down(tag_codes: &mut TagCodes) -> Result<()> {
+    tag_codes.build_tagdown().map_err(tag_down_err)
+}
+
+/// 使用启用的优化对生成的代码进行优化,
+/// 启用[`OptimizeOptions::report`]时将每个优化进行的次数作为日志
+fn optimize(
+    tag_codes: &mut TagCodes,
+    options: &OptimizeOptions,
+    logs: &mut Vec<Diagnostic>,
+) -> Result<PassReport> {
+    let mut manager = passes();
+    manager.set_level(options.level);
+    for (name, enabled) in &options.passes {
+        if !manager.set_enabled(name, *enabled) {
+            let message = format!("未知的优化 {name:?}");
+            let code = codes::TAG_CODE_UNKNOWN_PASS;
+            return Err(Diagnostics::new(2, Diagnostic::error(code, message)));
+        }
+    }
+    let report = manager.run(tag_codes).map_err(tag_down_err)?;
+    if !options.report {
+        return Ok(report);
+    }
+    for pass in manager.passes() {
+        let n = report.changes(pass.name);
+        if n == 0 { continue }
+        let (code, message) = match pass.name {
+            "const-fold" => (
+                codes::TAG_CODE_CONST_FOLD,
+                format!("折叠了 {n} 行常量运算与比较"),
+            ),
+            "copy-propagation" => (
+                codes::TAG_CODE_COPY_PROPAGATION,
+                format!("进行了 {n} 次复制传播与临时变量合并"),
+            ),
+            "dead-code" => (
+                codes::TAG_CODE_DEAD_CODE,
+                format!("删除了 {n} 行无法到达的代码"),
+            ),
+            "jump-optimize" => (
+                codes::TAG_CODE_JUMP_OPTIMIZE,
+                format!("进行了 {n} 次跳转优化"),
+            ),
+            "dead-store" => (
+                codes::TAG_CODE_DEAD_STORE,
+                format!("删除了 {n} 行对临时变量的无用赋值"),
+            ),
+            "reuse-temps" => (
+                codes::TAG_CODE_REUSE_TEMPS,
+                format!("通过复用名称减少了 {n} 个临时变量"),
+            ),
+            name => unreachable!("pass {name:?} without log"),
+        };
+        logs.push(Diagnostic::info(code, message));
+    }
+    Ok(report)
+}
+
+fn build_ast(
+    importer: &mut Importer,
+    src: &str,
+    path: Option<&Path>,
+) -> Result<(Expand, SourceMap)> {
+    load_ast(importer, &mut Meta::new(), src, path)
+}
+
+fn ast_meta(options: &CompileOptions) -> Meta {
+    let mut meta = Meta::new();
+    meta.target = options.target.unwrap_or_default();
+    meta
+}
+
+fn load_ast(
+    importer: &mut Importer,
+    meta: &mut Meta,
+    src: &str,
+    path: Option<&Path>,
+) -> Result<(Expand, SourceMap)> {
+    importer
+        .load(meta, path, Rc::new(src.into()))
+        .map_err(|e| Diagnostics::new(4, e.to_diagnostic()))
+}
+
+/// 由每个逻辑行的来源编号构建映射, 编号见[`CompileMeta::line_sources`]
+fn line_map(meta: &CompileMeta, sources: &[Option<usize>]) -> LineMap {
+    let line_sources = meta.line_sources();
+    let lines = sources.iter()
+        .enumerate()
+        .map(|(line, source)| {
+            let source = source.and_then(|id| line_sources.get(id));
+            let Some(source) = source else {
+                let expand_stack = vec![];
+                return LineMapping { line, source: None, expand_stack };
+            };
+            let expand_stack = source.expand_stack.iter()
+                .map(|(name, binder)| match binder {
+                    Some(binder) => format!("{name} ..{binder}"),
+                    None => name.to_string(),
+                })
+                .collect();
+            LineMapping {
+                line,
+                source: meta.source_map().location(source.index),
+                expand_stack,
+            }
+        })
+        .collect();
+    LineMap { lines }
+}
+
+struct CompileMetaExtender {
+    source: Rc<String>,
+    display_meta: RefCell<DisplaySourceMeta>,
+}
+impl CompileMetaExtender {
+    fn new(source: Rc<String>, display_meta: RefCell<DisplaySourceMeta>) -> Self {
+        Self {
+            source,
+            display_meta,
+        }
+    }
+}
+impl CompileMetaExtends for CompileMetaExtender {
+    fn source_location(&self, index: usize) -> [syntax::Location; 2] {
+        let (line, col) = line_column::line_column(&self.source, index);
+        [line as syntax::Location, col as syntax::Location]
+    }
+    fn display_value(&self, value: &syntax::Value) -> Cow<'_, str> {
+        let meta = &mut *self.display_meta.borrow_mut();
+        meta.to_default();
+        value.display_source_and_get(meta).to_owned().into()
+    }
+    fn display_binds(&self, value: syntax::BindsDisplayer<'_>) -> Cow<'_, str> {
+        let meta = &mut *self.display_meta.borrow_mut();
+        meta.to_default();
+        value.display_source_and_get(meta).to_owned().into()
+    }
+}
+
+/// 编译AST, 编译期间的日志会被加入`logs`, 编译错误则会被作为诊断返回
+fn compile_ast(
+    ast: Expand,
+    src: String,
+    source_map: SourceMap,
+    logs: &mut Vec<Diagnostic>,
+) -> Result<CompileMeta> {
+    compile_ast_with(ast, src, source_map, logs, |_| ())
+}
+
+/// 同[`compile_ast`], 但是可以在编译前对[`CompileMeta`]进行配置
+fn compile_ast_with(
+    ast: Expand,
+    src: String,
+    source_map: SourceMap,
+    logs: &mut Vec<Diagnostic>,
+    config: impl FnOnce(&mut CompileMeta),
+) -> Result<CompileMeta> {
+    let mut meta = CompileMeta::new();
+    let src = Rc::new(src);
+    meta.set_extender(Box::new(CompileMetaExtender::new(
+        src.clone(),
+        DisplaySourceMeta::new().into(),
+    )));
+    meta.set_source(src);
+    meta.set_source_map(source_map);
+    config(&mut meta);
+
+    let quiet = syntax::set_quiet(true);
+    let _ = syntax::take_last_err();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        ast.compile(&mut meta);
+    }));
+    syntax::set_quiet(quiet);
+    logs.extend_from_slice(meta.logs());
+
+    match result {
+        Ok(()) => Ok(meta),
+        Err(payload) => {
+            let Some(&CompileExit(code)) = payload.downcast_ref() else {
+                resume_unwind(payload)
+            };
+            let error = syntax::take_last_err().unwrap_or_else(|| {
+                let message = format!("编译被终止, 退出码: {code}");
+                Diagnostic::error(codes::COMPILE_EXIT, message)
+            });
+            Err(Diagnostics::new(code, error))
+        },
+    }
+}
+
+/// 一个测试块的运行结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    /// 测试块名称所在的位置
+    pub location: Option<Span>,
+    /// 编译与执行期间的日志
+    pub logs: Vec<Diagnostic>,
+    /// 失败的原因, 包括编译错误与失败的断言
+    pub failure: Option<Diagnostic>,
+}
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// 找到源码中的测试块, 并依次编译后在模拟器中执行
+///
+/// 测试可以使用源码中定义的const, 但是测试块之外的代码不会被执行,
+/// 被导入的文件中的测试块会被忽略
+pub fn run_tests(
+    source: &str,
+    options: &CompileOptions,
+) -> Result<Vec<TestResult>> {
+    let mut importer = importer(options);
+    let mut meta = Meta::new();
+    let (ast, source_map) = importer
+        .load(&mut meta, options.path.as_deref(), Rc::new(source.into()))
+        .map_err(|e| Diagnostics::new(4, e.to_diagnostic()))?;
+    let tests = meta.take_tests().into_iter()
+        .filter(|test| source_map.file_of(test.name.index)
+            .is_some_and(|file| file.base() == 0))
+        .collect::<Vec<_>>();
+    Ok(tests.into_iter()
+        .map(|test| {
+            run_test(test, &ast, &source_map, &mut meta, source, options)
+        })
+        .collect())
+}
+
+fn run_test(
+    test: TestCase,
+    ast: &Expand,
+    source_map: &SourceMap,
+    meta: &mut Meta,
+    src: &str,
+    options: &CompileOptions,
+) -> TestResult {
+    let location = source_map.location(test.name.index).map(Span::from);
+    let mut result = TestResult {
+        name: test.name().into(),
+        location: location.clone(),
+        logs: vec![],
+        failure: None,
+    };
+    // 跳过测试块之外的代码, 但依旧编译它们以使测试可以使用其中的const,
+    // 末尾的`end`使跳转至末尾的跳转不会被优化为跳转至开头
+    let start = meta.get_tag();
+    let ast = Expand(vec![
+        Goto(start.clone(), JumpCmp::Always.into()).into(),
+        InlineBlock(ast.0.clone()).into(),
+        LogicLine::new_label(start, meta),
+        test.body.into(),
+        LogicLine::Other(vec![Value::ReprVar("end".into())].into()),
+    ]);
+    let logs = &mut result.logs;
+    let emulator = compile_ast(ast, src.into(), source_map.clone(), logs)
+        .and_then(|mut meta| {
+            let logic_codes = mem::take(meta.parse_lines_mut());
+            let mut tag_codes = logic_to_tagcode(logic_codes, src)?;
+            build_tag_down(&mut tag_codes)?;
+            let logic_lines = tag_codes.compile().map_err(tag_down_err)?;
+            emulate_program(&logic_lines.join("\n"))
+        })
+        // 测试以`end`结束, 所以总是在`end`处停止
+        .map(|program| Emulator::new(program, logic_emulator::Config {
+            halt_on_end: true,
+            ..options.emulate
+        }));
+    let mut emulator = match emulator {
+        Ok(emulator) => emulator,
+        Err(mut e) => {
+            result.failure = e.diagnostics.pop();
+            result.logs.append(&mut e.diagnostics);
+            return result;
+        },
+    };
+
+    let mut unsupported = emulator.program().unsupported().iter()
+        .map(|cmd| &*cmd.value)
+        .collect::<Vec<_>>();
+    unsupported.sort_unstable();
+    unsupported.dedup();
+    if !unsupported.is_empty() {
+        let message = format!(
+            "测试 {} 中使用了不支持模拟的指令 `{}`, 它们将被视作noop",
+            result.name,
+            unsupported.join("`, `"),
+        );
+        result.logs.push(Diagnostic::warning(codes::TEST_UNSUPPORTED, message)
+            .with_primary(location.clone()));
+    }
+
+    result.failure = match emulator.run() {
+        Status::AssertFailed => {
+            let failure = emulator.assert_failure().unwrap();
+            let &(start, end) = meta.assertions()
+                .get(failure.id.num() as usize)
+                .expect("invalid assertion id");
+            Some(assert_failed(source_map, start, end, failure.print.as_ref()))
+        },
+        Status::StepLimit => {
+            let message = format!(
+                "测试 {} 执行的指令数超过了{}, 或许陷入了死循环",
+                result.name,
+                options.emulate.max_steps,
+            );
+            Some(Diagnostic::error(codes::TEST_STEP_LIMIT, message)
+                .with_primary(location)
+                .with_suggestion("可以使用`--max-steps`调整限制"))
+        },
+        Status::Running | Status::End | Status::Stop => None,
+    };
+    result
+}
+
+fn assert_failed(
+    source_map: &SourceMap,
+    start: usize,
+    end: usize,
+    print: Option<&(String, String)>,
+) -> Diagnostic {
+    let file = source_map.file_of(start).unwrap();
+    let text = &file.text()[start-file.base()..end-file.base()];
+    let start = source_map.location(start).unwrap();
+    let end = source_map.location(end).unwrap();
+    let message = format!("{}:{} 断言失败: assert {}",
+        start.line,
+        start.column,
+        text.trim(),
+    );
+    let mut diag = Diagnostic::error(codes::TEST_ASSERT_FAILED, message)
+        .with_primary(Span::new(start.file.clone(), start.pair(), end.pair()));
+    if let Some((expected, actual)) = print {
+        diag = diag
+            .with_note(format!("期望输出: {expected:?}"))
+            .with_note(format!("实际输出: {actual:?}"));
+    }
+    diag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modes(modes: &str) -> CompileOptions {
+        CompileOptions::new(CompileMode::parse_modes(modes).unwrap())
+    }
+
+    #[test]
+    fn compile_test() {
+        let output = compile("print 1;", &modes("c")).unwrap();
+        assert_eq!(output.text, "print 1");
+        assert!(output.logs.is_empty());
+
+        let output = compile("print 1;", &modes("cr")).unwrap();
+        assert_eq!(output.text, "'print' 1;");
+
+        let output = compile(r#"Builtin.Info! "hi"; print 1;"#, &modes("c"))
+            .unwrap();
+        assert_eq!(output.logs.len(), 1);
+        assert_eq!(output.logs[0].severity, Severity::Info);
+        assert_eq!(output.logs[0].code, codes::LOG_INFO);
+        assert_eq!(output.logs[0].message, r#""hi""#);
+
+        let output = compile("print 1; end; print 2;", &modes("c")).unwrap();
+        assert_eq!(output.text, "print 1\nend\nprint 2");
+        assert!(output.logs.is_empty());
+    }
+
+    /// 启用所有默认的优化并报告每个优化进行的次数
+    fn optimized() -> CompileOptions {
+        modes("c").opt_level(2).pass_report(true)
+    }
+
+    #[test]
+    fn dead_code_pass_test() {
+        let src = "print 1; end; print 2;";
+        let output = compile(src, &optimized()).unwrap();
+        assert_eq!(output.text, "print 1\nend");
+        assert_eq!(output.logs.len(), 1);
+        assert_eq!(output.logs[0].code, codes::TAG_CODE_DEAD_CODE);
+
+        // 默认不进行优化, 需要`-O1`
+        let output = compile(src, &modes("c")).unwrap();
+        assert_eq!(output.text, "print 1\nend\nprint 2");
+        let output = compile(src, &modes("c").opt_level(1)).unwrap();
+        assert_eq!(output.text, "print 1\nend");
+    }
+
+    #[test]
+    fn pass_report_test() {
+        // 没有`--pass-report`时优化不会输出日志
+        let src = "take A = (?2); print 1; end; x = A + 1; y = (?a+1);";
+        let output = compile(src, &modes("c").opt_level(2)).unwrap();
+        assert!(output.logs.is_empty(), "{:?}", output.logs);
+        assert!(output.pass_report.is_none());
+        let output = compile(src, &optimized()).unwrap();
+        assert!(!output.logs.is_empty());
+        assert!(output.pass_report.is_some());
+    }
+
+    #[test]
+    fn dead_store_pass_test() {
+        let src = "take X = ($ = a + 1;); print 2;";
+        let output = compile(src, &optimized()).unwrap();
+        assert_eq!(output.text, "print 2");
+        assert_eq!(output.logs[0].code, codes::TAG_CODE_DEAD_STORE);
+    }
+
+    #[test]
+    fn copy_propagation_pass_test() {
+        let output = compile("x = (?a+1); y = x;", &optimized()).unwrap();
+        assert_eq!(output.text, "op add x a 1\nset y x");
+        assert_eq!(output.logs[0].code, codes::TAG_CODE_COPY_PROPAGATION);
+    }
+
+    #[test]
+    fn const_fold_pass_test() {
+        let src = "take A = (?2); if A < 3 { print 1; } x = A + 1;";
+        let output = compile(src, &optimized()).unwrap();
+        assert_eq!(output.text, "print 1\nset x 3");
+        assert_eq!(output.logs[0].code, codes::TAG_CODE_CONST_FOLD);
+    }
+
+    #[test]
+    fn jump_optimize_pass_test() {
+        let src = "const C = 3; while C < 2 { print 1; } print 2;";
+        let output = compile(src, &optimized()).unwrap();
+        assert_eq!(output.text, "print 2");
+        assert!(output.logs.iter()
+            .any(|log| log.code == codes::TAG_CODE_JUMP_OPTIMIZE));
+    }
+
+    #[test]
+    fn reuse_temps_pass_test() {
+        let src = "print (read $ cell1 0;); print (read $ cell1 1;);";
+        let output = compile(src, &optimized()).unwrap();
+        assert_eq!(output.text.matches("__1").count(), 2);
+        let output = compile(src, &optimized().reuse_temps(true)).unwrap();
+        assert!(!output.text.contains("__1"));
+        assert_eq!(output.logs[0].code, codes::TAG_CODE_REUSE_TEMPS);
+    }
+
+    #[test]
+    fn std_stamp_test() {
+        let src = r#"import "std/array"; print 1;"#;
+        let output = compile(src, &modes("c")).unwrap();
+        assert_eq!(output.text, "print 1");
+        let output = compile(src, &modes("c").std_stamp(true)).unwrap();
+        assert_eq!(output.text, format!("{}\nprint 1", std_stamp()));
+        let output = compile("print 1;", &modes("c").std_stamp(true)).unwrap();
+        assert_eq!(output.text, "print 1");
+    }
+
+    #[test]
+    fn optimize_options_test() {
+        let src = "const C = 3; while C < 2 { print 1; } x = (?a+1); y = x;";
+        // 加入优化之前的编译器的输出
+        let baseline = "\
+            jump 3 greaterThanEq 3 2\n\
+            print 1\n\
+            jump 1 lessThan 3 2\n\
+            op add __0 a 1\n\
+            set x __0\n\
+            set y x";
+        let output = compile(src, &modes("c")).unwrap();
+        assert_eq!(output.text, baseline);
+        let output = compile(src, &modes("c").opt_level(0)).unwrap();
+        assert_eq!(output.text, baseline);
+        assert!(output.logs.is_empty());
+        let output = compile(src, &modes("c").opt_level(2)).unwrap();
+        let lines = output.text.lines().count();
+        assert!(lines < baseline.lines().count(), "{}", output.text);
+        let output = compile(src, &modes("c").opt_level(1)).unwrap();
+        assert_eq!(output.text, "op add __0 a 1\nset x __0\nset y x");
+        let options = modes("c").opt_level(2).pass("copy-propagation", false);
+        let output = compile(src, &options).unwrap();
+        assert_eq!(output.text, "op add __0 a 1\nset x __0\nset y x");
+        let options = modes("c")
+            .opt_level(0)
+            .pass("const-fold", true)
+            .pass_report(true);
+        let output = compile(src, &options).unwrap();
+        assert_eq!(output.logs.len(), 1);
+        assert_eq!(output.logs[0].code, codes::TAG_CODE_CONST_FOLD);
+
+        let err = compile(src, &modes("c").pass("foo", true)).unwrap_err();
+        assert_eq!(err.error().code, codes::TAG_CODE_UNKNOWN_PASS);
+
+        let output = compile(src, &modes("c")).unwrap();
+        assert!(output.pass_report.is_none());
+        let options = modes("c").opt_level(2).pass_report(true);
+        let output = compile(src, &options).unwrap();
+        let report = output.pass_report.unwrap();
+        assert_eq!((report.before, report.after), (6, 2));
+        assert!(report.runs.iter().all(|run| run.name != "reuse-temps"));
+
+        let output = compile("set a b", &modes("l")).unwrap();
+        assert_eq!(output.text, "set a b");
+        assert!(output.logs.iter().all(|log| log.stage() == Stage::Lint));
+        assert!(!output.logs.is_empty());
+    }
+
+    #[test]
+    fn size_test() {
+        let src = "const F = (print 1 2 3;); take F; take F; print 4;";
+        let exceeded = |output: &Output| output.logs.iter()
+            .any(|log| log.code == codes::TAG_CODE_INSTRUCTION_LIMIT);
+        let limit = |limit| modes("c").instruction_limit(limit);
+        let output = compile(src, &limit(Some(4))).unwrap();
+        assert!(exceeded(&output));
+        assert!(output.size_report.is_none());
+        let output = compile(src, &limit(Some(7))).unwrap();
+        assert!(!exceeded(&output));
+        let output = compile(src, &limit(None)).unwrap();
+        assert!(!exceeded(&output));
+
+        let options = modes("c").instruction_limit(Some(4)).deny_limit(true);
+        let err = compile(src, &options).unwrap_err();
+        let code = codes::TAG_CODE_INSTRUCTION_LIMIT_EXCEEDED;
+        assert_eq!(err.error().code, code);
+
+        let output = compile(src, &modes("c").size_report(true)).unwrap();
+        let report = output.size_report.unwrap();
+        assert_eq!((report.total, report.limit), (7, Some(1000)));
+        assert_eq!(report.expansions, [(Some("F".into()), 6), (None, 1)]);
+    }
+
+    #[test]
+    fn target_test() {
+        let src = "x = select a < b ? 1 : 2; print x;";
+        let output = compile(src, &modes("c")).unwrap();
+        assert!(output.text.starts_with("select x lessThan a b 1 2"));
+        let output = compile(src, &modes("c").target(Target::V7)).unwrap();
+        assert!(!output.text.contains("select"));
+        assert!(output.text.starts_with("jump "));
+
+        let src = "print 1;\nop x a emod b;\nprint x;";
+        assert!(compile(src, &modes("c").target(Target::V8)).is_ok());
+        let err = compile(src, &modes("c").target(Target::V7)).unwrap_err();
+        assert_eq!(err.error().code, codes::TAG_CODE_UNSUPPORTED_BY_TARGET);
+        let span = err.error().primary.as_ref().unwrap();
+        assert_eq!(span.start, (2, 1));
+        assert_eq!(span.label.as_deref(), Some("emod"));
+        let options = modes("c").target(Target::V8);
+        let err = compile("setrate 10;", &options).unwrap_err();
+        assert_eq!(err.error().code, codes::TAG_CODE_UNSUPPORTED_BY_TARGET);
+        assert!(compile("setrate 10;", &modes("c")).is_ok());
+
+        let options = modes("l").target(Target::V7);
+        let output = compile("op emod x a b", &options).unwrap();
+        assert!(output.logs.iter()
+            .any(|log| log.code == codes::LINT_UNSUPPORTED_BY_TARGET));
+    }
+
+    #[test]
+    fn split_test() {
+        let src = "read x cell1 0; y = x + 1; '#' split show; print y;";
+        let output = compile(src, &modes("s")).unwrap();
+        assert!(output.text.starts_with("cell bank1\nslot 1 y\n"));
+        assert_eq!(output.processors.len(), 2);
+        assert!(output.processors[1].ends_with("print y\nwrite 0 bank1 0"));
+        let output = compile(src, &modes("s").split_cell("cell2")).unwrap();
+        let sync = "write y cell2 1\nwrite 1 cell2 0";
+        assert!(output.processors[0].ends_with(sync));
+        let output = compile(src, &modes("c")).unwrap();
+        assert!(output.processors.is_empty());
+
+        let src = "print 1; '#' split; end;";
+        let err = compile(src, &modes("s")).unwrap_err();
+        assert_eq!(err.error().code, codes::TAG_CODE_SPLIT);
+    }
+
+    #[test]
+    fn schematic_test() {
+        let options = modes("cm")
+            .processor(ProcessorType::Micro)
+            .link("cell1");
+        let output = compile("print 1;", &options).unwrap();
+        assert!(output.text.starts_with("bXNjaA"));
+        let bytes = output.schematic.unwrap();
+        assert_eq!(schematic::base64::encode(&bytes), output.text);
+        let output = compile("print 1;", &modes("c")).unwrap();
+        assert!(output.schematic.is_none());
+    }
+
+    #[test]
+    fn import_schematic_test() {
+        let options = modes("cm").link("cell1");
+        let text = compile("print 1; print 2;", &options).unwrap().text;
+        let output = compile(&text, &modes("M")).unwrap();
+        assert_eq!(output.text, "name processor\n\
+            processor 0 logic-processor (0, 0)\n    links: cell1 (2, 0)");
+        assert_eq!(output.processors, ["print 1\nprint 2"]);
+        let output = compile(&text, &modes("Mr")).unwrap();
+        assert_eq!(output.processors, ["'print' 1;\n'print' 2;"]);
+
+        // 单个处理器的配置
+        let config = schematic::encode_config("end", &[]);
+        let output = compile(&base64::encode(&config), &modes("M")).unwrap();
+        assert_eq!(output.text, "processor 0 processor");
+        assert_eq!(output.processors, ["end"]);
+
+        let err = compile("bXNjaA==", &modes("M")).unwrap_err();
+        assert_eq!(err.error().code, codes::SCHEMATIC_PARSE);
+    }
+
+    #[test]
+    fn compile_error_test() {
+        let err = compile(r#"Builtin.Err! "x";
+            print $;"#, &modes("c")).unwrap_err();
+        assert_eq!(err.exit_code, 6);
+        assert_eq!(err.diagnostics.len(), 2);
+        assert_eq!(err.diagnostics[0].code, codes::LOG_ERROR);
+        assert_eq!(err.error().code, codes::OUT_OF_DEXP);
+        let span = err.error().primary.as_ref().unwrap();
+        assert_eq!(span.start, (2, 19));
+
+        let err = compile("print (", &modes("c")).unwrap_err();
+        assert_eq!(err.exit_code, 4);
+        assert_eq!(err.error().code, codes::PARSE_UNRECOGNIZED_EOF);
+
+        let err = compile("print 1;\n:a\n:a", &modes("c")).unwrap_err();
+        assert_eq!(err.exit_code, 10);
+        assert_eq!(err.error().stage(), Stage::TagCode);
+
+        let err = compile("(set a", &modes("p")).unwrap_err();
+        assert_eq!(err.error().code, codes::PAREN_PARSE);
+
+        let err = compile("jump x always", &modes("e")).unwrap_err();
+        assert_eq!(err.exit_code, 11);
+        assert_eq!(err.error().code, codes::EMULATE_PROGRAM);
+    }
+
+    #[test]
+    fn run_tests_test() {
+        let src = r#"
+            const Add = (take A = _0; take B = _1; $ = A + B;);
+            print "main";
+            test "add" {
+                x = Add[1 2];
+                assert x == 3;
+                print x;
+                assert print "3";
+                write x cell1 0;
+                read y cell1 0;
+                assert y == 3 && x > 2;
+            }
+            test "fail" {
+                x = 2;
+                assert x == 2;
+                assert x > 2;
+            }
+            test "print" {
+                print "a";
+                assert print "b";
+            }
+            test "loop" {
+                while 1 {}
+            }
+            test "compile" {
+                print $;
+            }
+        "#;
+        let options = modes("").max_steps(100).wrap_end(true);
+        let results = run_tests(src, &options).unwrap();
+        let names = results.iter().map(|r| &*r.name).collect::<Vec<_>>();
+        assert_eq!(names, ["add", "fail", "print", "loop", "compile"]);
+        assert!(results[0].passed(), "{:?}", results[0].failure);
+        assert_eq!(results[0].location.as_ref().unwrap().start, (4, 18));
+
+        let failure = results[1].failure.as_ref().unwrap();
+        assert_eq!(failure.code, codes::TEST_ASSERT_FAILED);
+        assert_eq!(failure.message, "16:24 断言失败: assert x > 2");
+        let span = failure.primary.as_ref().unwrap();
+        assert_eq!((span.start, span.end), ((16, 24), (16, 29)));
+
+        let failure = results[2].failure.as_ref().unwrap();
+        assert_eq!(failure.code, codes::TEST_ASSERT_FAILED);
+        assert_eq!(failure.notes, [r#"期望输出: "b""#, r#"实际输出: "a""#]);
+
+        let failure = results[3].failure.as_ref().unwrap();
+        assert_eq!(failure.code, codes::TEST_STEP_LIMIT);
+
+        let failure = results[4].failure.as_ref().unwrap();
+        assert_eq!(failure.code, codes::OUT_OF_DEXP);
+
+        let output = compile(src, &modes("c")).unwrap();
+        assert_eq!(output.text, r#"print "main""#);
+    }
+
+    #[test]
+    fn source_map_test() {
+        let src = "const F = (\n    print _0;\n);\ni = 1;\nF! i;\nF! 2;";
+        let output = compile(src, &modes("c").source_map(true)).unwrap();
+        assert_eq!(output.text, "set i 1\nprint i\nprint 2");
+        let map = output.source_map.unwrap();
+        let lines = map.lines.iter()
+            .map(|mapping| (
+                mapping.source.as_ref().unwrap().pair(),
+                &*mapping.expand_stack,
+            ))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [
+            ((4, 1), &[][..]),
+            ((5, 1), &["F".to_owned()][..]),
+            ((6, 1), &["F".to_owned()][..]),
+        ]);
+        assert_eq!(map.to_string(), "0\t4:1\n1\t5:1\tF\n2\t6:1\tF\n");
+
+        let output = compile(src, &modes("c")).unwrap();
+        assert_eq!(output.text, "set i 1\nprint i\nprint 2");
+        assert!(output.source_map.is_none());
+
+        let output = compile(src, &modes("cl").source_map(true)).unwrap();
+        assert!(output.source_map.is_none());
+    }
+
+    #[test]
+    fn emulate_test() {
+        let src = "i = 0; do { print i; } while (*++i) < 3;\
+                   printflush message1;";
+        let output = compile(src, &modes("ce")).unwrap();
+        let text = &output.text;
+        assert!(text.starts_with("status: end"), "{text}");
+        assert!(text.contains("message1:\n    012\n"), "{text}");
+        assert!(output.text.ends_with("    i = 3"), "{}", output.text);
+
+        let output = compile("sensor a b @x\nstop", &modes("e")).unwrap();
+        assert!(output.text.starts_with("status: stop"));
+        assert_eq!(output.logs.len(), 1);
+        assert_eq!(output.logs[0].code, codes::EMULATE_UNSUPPORTED);
+
+        let output = compile("end", &modes("e").max_steps(0)).unwrap();
+        assert!(output.text.starts_with("status: step limit"));
+
+        let src = "op add i i 1\nend";
+        let output = compile(src, &modes("e")).unwrap();
+        assert!(output.text.ends_with("    i = 1"), "{}", output.text);
+        let options = modes("e").max_steps(10).wrap_end(true);
+        let output = compile(src, &options).unwrap();
+        assert!(output.text.starts_with("status: step limit"));
+        assert!(output.text.ends_with("    i = 5"), "{}", output.text);
+    }
+}
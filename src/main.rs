@@ -4,242 +4,528 @@ use std::{
         stdin,
         Read
     },
-    mem,
+    fs,
+    path::{Path, PathBuf},
     process::exit,
     fmt::Display,
     cell::RefCell,
-    borrow::Cow,
+    mem,
     rc::Rc,
 };
 
-use display_source::{
-    DisplaySource,
-    DisplaySourceMeta,
-};
-use syntax::{
-    CompileMeta,
-    Error,
-    Expand,
-    Meta,
-    CompileMetaExtends,
-};
-use parser::{
-    TopLevelParser,
-    lalrpop_util::{
-        lexer::Token,
-        ParseError
-    },
+use mindustry_logic_bang_lang::{
+    codes,
+    compile,
+    passes,
+    run_tests,
+    CompileMode,
+    CompileOptions,
+    Diagnostic,
+    Diagnostics,
+    Output,
+    Severity,
+    Stage,
 };
-use tag_code::{
-    logic_parser::{parser as tparser, ParseLines}, TagCodes,
-};
-use logic_lint::Source;
+use parser::std_lib;
+use schematic::base64;
+use getopts_macro::{getopts::Options, getopts_options};
+
+thread_local! {
+    /// 正在处理的输入文件名, 从标准输入读取时为空
+    static INPUT_NAME: RefCell<Option<Rc<str>>> = const { RefCell::new(None) };
+}
 
 /// 带有错误前缀, 并且文本为红色的eprintln
+///
+/// 正在处理某个输入文件时, 会在错误前加上其文件名
 macro_rules! err {
     ( $($args:tt)* ) => {{
         let str = format!($($args)*);
-        let mut iter = str.lines();
-        eprintln!("\x1b[1;31mMainError: {}\x1b[22;39m", iter.next().unwrap());
-        for line in iter {
-            eprintln!("    \x1b[1;31m{}\x1b[22;39m", line);
-        }
+        let str = INPUT_NAME.with_borrow(|name| match name {
+            Some(name) => format!("{name}: {str}"),
+            None => str,
+        });
+        main_err(&str);
     }};
 }
 
+fn main_err(str: &str) {
+    let mut iter = str.lines();
+    let first = iter.next().unwrap_or_default();
+    eprintln!("\x1b[1;31mMainError: {first}\x1b[22;39m");
+    for line in iter {
+        eprintln!("    \x1b[1;31m{}\x1b[22;39m", line);
+    }
+}
+
 macro_rules! concat_lines {
     ( $( $( $s:expr ),* ; )* ) => {
         concat!( $( $($s ,)* "\n" ),* )
     };
 }
 
-const MAX_INVALID_TOKEN_VIEW: usize = 5;
-
-fn help() {
-    print!("Usage: {} {}", args().next().unwrap(), HELP_MSG);
+fn help(options: &Options) {
+    let brief = format!("Usage: {} {}", args().next().unwrap(), HELP_MSG);
+    print!("{}", options.usage(brief.trim_end()));
+    print!("\n{HELP_FOOTER}");
 }
 
 fn main() {
-    let args = args().skip(1).collect::<Vec<_>>();
-    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
-        help();
+    let options = getopts_options! {
+        -o, --output=PATH       "output file, or directory for multiple FILE";
+            --message-format=FMT
+                                "diagnostics format: human (default) or json";
+            --max-steps=N       "max executed instructions of emulate mode";
+            --wrap-end          "`end` and last line jump back to line 0";
+            --source-map        "write output lines to source map PATH.map";
+        -O=LEVEL                "optimization level: 0 (default), 1 or 2";
+            --pass*=TOGGLE      "enable (+NAME) or disable (-NAME) a pass";
+            --pass-report       "print changes and lines of each pass";
+            --reuse-temps       "reuse temp names with disjoint lifetimes";
+            --limit=N           "instruction limit, 0 disables (default 1000)";
+            --deny-limit        "exceeding --limit is an error, not a warning";
+            --size-report       "print instructions of each top level const";
+            --target=VERSION    "check against game version: v7, v8 or world";
+            --split-cell=NAME   "split processors' cell (default bank1)";
+            --processor=TYPE    "micro, logic (default), hyper or world";
+            --link*=NAME        "building linked to processor, e.g. cell1";
+            --std-list          "list bundled std modules and std revision";
+            --std-print=NAME    "print source of bundled std module";
+            --std-stamp         "add std revision comment when std is used";
+        -h, --help              "show help message";
+    };
+    let matches = options.parse(args().skip(1)).unwrap_or_else(|e| {
+        err!("{e}");
+        exit(2)
+    });
+    if matches.opt_present("help") {
+        help(&options);
+        exit(0)
+    }
+    if matches.opt_present("std-list") {
+        std_list();
         exit(0)
     }
-    let Some(mode) = args.first() else {
+    if let Some(name) = matches.opt_str("std-print") {
+        std_print(&name);
+        exit(0)
+    }
+    let Some((mode, files)) = matches.free.split_first() else {
         err!("missing MODE args");
-        help();
+        help(&options);
         exit(1)
     };
-    if let Some(arg) = args.get(1) {
-        err!("多余的参数: {:?}", arg);
-        exit(2);
-    }
-    let modes = Vec::from_iter(
-        mode.chars()
-            .map(|char| {
-                CompileMode::try_from(char).unwrap_or_else(|mode| {
-                    err!("mode {mode:?} no pattern");
-                    help();
-                    exit(2)
-                })
-            })
-    );
-    let mut src = read_stdin();
-    for mode in modes {
-        src = mode.compile(src)
-    }
-    println!("{src}")
+    let test = mode == "test";
+    let modes = if test { vec![] } else {
+        CompileMode::parse_modes(mode).unwrap_or_else(|mode| {
+            err!("mode {mode:?} no pattern");
+            help(&options);
+            exit(2)
+        })
+    };
+    let output = matches.opt_str("output").map(PathBuf::from);
+    let format = match matches.opt_str("message-format").as_deref() {
+        None | Some("human") => MessageFormat::Human,
+        Some("json") => MessageFormat::Json,
+        Some(format) => {
+            err!("unknown message format {format:?}, expected human or json");
+            exit(2)
+        },
+    };
+    let mut options = CompileOptions::new(modes).env_search_paths();
+    if let Some(max_steps) = matches.opt_str("max-steps") {
+        let max_steps = max_steps.parse().unwrap_or_else(|e| {
+            err!("invalid max steps {max_steps:?}: {e}");
+            exit(2)
+        });
+        options = options.max_steps(max_steps);
+    }
+    if matches.opt_present("wrap-end") {
+        options = options.wrap_end(true);
+    }
+    let source_map = matches.opt_present("source-map");
+    if source_map {
+        if options.modes.last() != Some(&CompileMode::BangToMdtLogic) {
+            err!("--source-map only available when last mode is `c`");
+            exit(2)
+        }
+        if output.is_none() && files.len() < 2 {
+            err!("--source-map requires output file, use `-o`");
+            exit(2)
+        }
+        options = options.source_map(true);
+    }
+    if let Some(level) = matches.opt_str("O") {
+        let level = level.parse().ok()
+            .filter(|&level| level <= 2)
+            .unwrap_or_else(|| {
+                err!("invalid optimization level {level:?}, expected 0 to 2");
+                exit(2)
+            });
+        options = options.opt_level(level);
+    }
+    for pass in matches.opt_strs("pass") {
+        let (enabled, name) = match pass.split_at_checked(1) {
+            Some(("+", name)) => (true, name),
+            Some(("-", name)) => (false, name),
+            _ => {
+                err!("invalid pass toggle {pass:?}, expected +NAME or -NAME");
+                exit(2)
+            },
+        };
+        if passes().passes().all(|pass| pass.name != name) {
+            let names = passes().passes()
+                .map(|pass| pass.name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            err!("unknown pass {name:?}, expected one of: {names}");
+            exit(2)
+        }
+        options = options.pass(name, enabled);
+    }
+    if matches.opt_present("reuse-temps") {
+        options = options.reuse_temps(true);
+    }
+    if matches.opt_present("pass-report") {
+        options = options.pass_report(true);
+    }
+    if let Some(limit) = matches.opt_str("limit") {
+        let limit = limit.parse::<usize>().unwrap_or_else(|e| {
+            err!("invalid instruction limit {limit:?}: {e}");
+            exit(2)
+        });
+        let limit = Some(limit).filter(|&limit| limit != 0);
+        options = options.instruction_limit(limit);
+    }
+    if matches.opt_present("deny-limit") {
+        options = options.deny_limit(true);
+    }
+    if matches.opt_present("size-report") {
+        options = options.size_report(true);
+    }
+    if let Some(target) = matches.opt_str("target") {
+        let target = target.parse().unwrap_or_else(|e| {
+            err!("{e}");
+            exit(2)
+        });
+        options = options.target(target);
+    }
+    if let Some(cell) = matches.opt_str("split-cell") {
+        options = options.split_cell(cell);
+    }
+    if let Some(processor) = matches.opt_str("processor") {
+        let processor = processor.parse().unwrap_or_else(|e| {
+            err!("{e}");
+            exit(2)
+        });
+        options = options.processor(processor);
+    }
+    for link in matches.opt_strs("link") {
+        options = options.link(link);
+    }
+    if matches.opt_present("std-stamp") {
+        options = options.std_stamp(true);
+    }
+    if test {
+        exit(test_files(&options, format, files))
+    }
+
+    match files {
+        [] => {
+            let out = compile_or_exit(&options, format, &read_stdin());
+            write_output(output.as_deref(), out, &options);
+        },
+        [file] => {
+            let file = Path::new(file);
+            let options = options.path(file);
+            let src = read_input(file).unwrap_or_else(|e| {
+                show_diagnostics(format, &e.diagnostics, true);
+                exit(e.exit_code)
+            });
+            let out = compile_or_exit(&options, format, &src);
+            write_output(output.as_deref(), out, &options);
+        },
+        files => {
+            if let Some(dir) = &output {
+                fs::create_dir_all(dir).unwrap_or_else(|e| {
+                    err!("创建输出目录 {} 失败: {e}", dir.display());
+                    exit(3)
+                });
+            }
+            let extension = options.modes.last().unwrap().output_extension();
+            let mut exit_code = 0;
+            for file in files {
+                let file = Path::new(file);
+                let out_path = batch_output_path(
+                    file,
+                    output.as_deref(),
+                    extension,
+                );
+                let options = options.clone().path(file);
+                // 某个文件失败时继续编译其它文件, 最后以失败退出
+                let result = read_input(file)
+                    .map_err(|e| {
+                        show_diagnostics(format, &e.diagnostics, true);
+                        e.exit_code
+                    })
+                    .and_then(|src| compile_and_show(&options, format, &src));
+                match result {
+                    Ok(out) => write_output(Some(&out_path), out, &options),
+                    Err(code) => exit_code = code,
+                }
+            }
+            exit(exit_code)
+        },
+    }
 }
 
-enum CompileMode {
-    BangToMdtLogic,
-    BangToASTDebug,
-    BangToASTDisplay,
-    BangToMdtTagCode { tag_down: bool },
-    MdtLogicToMdtTagCode { tag_down: bool },
-    MdtLogicToBang,
-    MdtTagCodeToMdtLogic,
-    LintLogic,
-    IndentLogic,
-    RenameLabel,
-    BangToMdtLabel,
-    BuildExpr,
-    ParenToMdtLogic,
+/// 诊断信息的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageFormat {
+    Human,
+    /// 每行一个JSON对象, 见[`Diagnostic::to_json`]
+    Json,
 }
-impl CompileMode {
-    fn compile(&self, src: String) -> String {
-        match *self {
-            Self::BangToMdtLogic => {
-                let ast = build_ast(&src);
-                let mut meta = compile_ast(ast, src.clone());
-                let logic_codes = mem::take(meta.parse_lines_mut());
-                let mut tag_codes = logic_to_tagcode(logic_codes, &src);
-                build_tag_down(&mut tag_codes);
-                let logic_lines = tag_codes.compile().unwrap();
-                logic_lines.join("\n")
-            },
-            Self::BangToASTDebug => {
-                let ast = build_ast(&src);
-                format!("{ast:#?}")
-            },
-            Self::BangToASTDisplay => {
-                let ast = build_ast(&src);
-                display_ast(&ast)
-            },
-            Self::BangToMdtTagCode { tag_down } => {
-                let ast = build_ast(&src);
-                let mut meta = compile_ast(ast, src.clone());
-                let mut tag_codes = logic_to_tagcode(mem::take(meta.parse_lines_mut()), &src);
-                if tag_down { build_tag_down(&mut tag_codes); }
-                tag_codes.to_string()
-            },
-            Self::MdtLogicToMdtTagCode { tag_down } => {
-                let mut lines = logic_src_to_tagcode(&src);
-                if tag_down {
-                    lines.build_tagdown().unwrap();
-                    lines.tag_up();
-                }
-                lines.to_string()
-            },
-            Self::MdtLogicToBang => {
-                let logic_lines = logic_parse(&src);
-                let ast = Expand::try_from(logic_lines)
-                    .unwrap_or_else(|e| {
-                        let (line, col) = e.location(&src);
-                        err!("MdtLogicToBang {line}:{col} {}", e.value);
-                        exit(4)
-                    });
-                display_ast(&ast)
-            },
-            Self::MdtTagCodeToMdtLogic => {
-                let mut tag_codes = logic_src_to_tagcode(&src);
-                build_tag_down(&mut tag_codes);
-                let logic_lines = tag_codes.compile().unwrap();
-                logic_lines.join("\n")
-            },
-            Self::LintLogic => {
-                let linter = Source::from_str(&src);
-                linter.show_lints();
-                src
-            },
-            Self::IndentLogic => {
-                let mut logic_lines = logic_parse(&src);
-                logic_lines.index_label_popup();
-                format!("{logic_lines:#}")
-            },
-            Self::RenameLabel => {
-                let mut logic_lines = logic_parse(&src);
-                logic_lines.for_each_inner_label_mut(|mut lab| {
-                    lab.to_mut().push_str("_RENAME");
-                });
-                format!("{logic_lines:#}")
-            },
-            Self::BangToMdtLabel => {
-                let ast = build_ast(&src);
-                let mut meta = compile_ast(ast, src.clone());
-                meta.parse_lines_mut().index_label_popup();
-                format!("{}", meta.parse_lines())
-            },
-            Self::BuildExpr => {
-                let lines = logic_parse(&src);
-                let out = tag_code::expr_builder::build(lines.iter()
-                    .map(|x| &**x));
-                out.join("\n")
-            },
-            Self::ParenToMdtLogic => {
-                let lines = match mini_paren::parser::lines(&src) {
-                    Ok(lines) => lines,
-                    Err(e) => {
-                        err!("ParseParenCode {}:{} expected {}",
-                            e.location.line,
-                            e.location.column,
-                            e.expected,
-                        );
-                        exit(9)
-                    },
-                };
-                let mut state = mini_paren::State::default();
-                state.process_lines(&lines);
-                state.out.truncate(state.out.trim_end().len());
-                state.out
+
+/// 编译并输出所有诊断, 失败时返回退出码
+fn compile_and_show(
+    options: &CompileOptions,
+    format: MessageFormat,
+    src: &str,
+) -> Result<Output, i32> {
+    let (diagnostics, result) = match compile(src, options) {
+        Ok(mut output) => (mem::take(&mut output.logs), Ok(output)),
+        Err(Diagnostics { diagnostics, exit_code }) => {
+            (diagnostics, Err(exit_code))
+        },
+    };
+    show_diagnostics(format, &diagnostics, result.is_err());
+    if let Ok(Output { pass_report: Some(report), .. }) = &result {
+        eprint!("{report}");
+    }
+    if let Ok(Output { size_report: Some(report), .. }) = &result {
+        eprint!("{report}");
+    }
+    result
+}
+
+/// 运行每个输入中的测试, 返回退出码
+fn test_files(
+    options: &CompileOptions,
+    format: MessageFormat,
+    files: &[String],
+) -> i32 {
+    if files.is_empty() {
+        return test_source(options, format, &read_stdin());
+    }
+    let mut exit_code = 0;
+    for file in files {
+        let file = Path::new(file);
+        let options = options.clone().path(file);
+        let code = match read_input(file) {
+            Ok(src) => test_source(&options, format, &src),
+            Err(Diagnostics { diagnostics, exit_code }) => {
+                show_diagnostics(format, &diagnostics, true);
+                exit_code
             },
+        };
+        if code != 0 {
+            exit_code = code;
         }
     }
+    exit_code
 }
 
-fn logic_to_tagcode<'a>(lines: ParseLines<'a>, src: &str) -> TagCodes {
-    let tagcodes = match TagCodes::try_from(lines) {
-        Ok(tagcode) => tagcode,
-        Err(e) => {
-            let (line, column) = e.location(src);
-            let prefix = format!("ParseTagCode {line}:{column}");
-            err!("{prefix} {e}\n或许你可以使用`Li`选项编译来详细查看");
-            exit(10)
+fn test_source(
+    options: &CompileOptions,
+    format: MessageFormat,
+    src: &str,
+) -> i32 {
+    let results = match run_tests(src, options) {
+        Ok(results) => results,
+        Err(Diagnostics { diagnostics, exit_code }) => {
+            show_diagnostics(format, &diagnostics, true);
+            return exit_code;
         },
     };
-    tagcodes
+    let from = INPUT_NAME.with_borrow(|name| name.as_ref()
+        .map(|name| format!(" from {name}"))
+        .unwrap_or_default());
+    println!("running {} tests{from}", results.len());
+    for result in &results {
+        let status = if result.passed() { "ok" } else { "FAILED" };
+        println!("test {} ... {status}", result.name);
+        let diagnostics = result.logs.iter()
+            .chain(&result.failure)
+            .cloned()
+            .collect::<Vec<_>>();
+        show_diagnostics(format, &diagnostics, !result.passed());
+    }
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    let status = if failed == 0 { "ok" } else { "FAILED" };
+    println!("\ntest result: {status}. {} passed; {failed} failed",
+        results.len() - failed);
+    if failed == 0 { 0 } else { 12 }
 }
 
-fn logic_parse(src: &str) -> ParseLines<'_> {
-    match tparser::lines(src) {
-        Ok(lines) => lines,
-        Err(e) => {
-            err!("ParseLogicCode {}:{} expected {}",
-                e.location.line,
-                e.location.column,
-                e.expected,
-            );
-            exit(9)
+/// 输出诊断, `failed`时最后一个诊断为导致失败的错误
+fn show_diagnostics(
+    format: MessageFormat,
+    diagnostics: &[Diagnostic],
+    failed: bool,
+) {
+    if format == MessageFormat::Json {
+        for diag in diagnostics {
+            eprintln!("{}", diag.to_json());
+        }
+        return;
+    }
+    match diagnostics.split_last() {
+        Some((error, logs)) if failed => {
+            logs.iter().for_each(show_log);
+            show_error(error);
         },
+        _ => diagnostics.iter().for_each(show_log),
     }
 }
 
-fn logic_src_to_tagcode(src: &str) -> TagCodes {
-    let lines = logic_parse(src);
-    logic_to_tagcode(lines, src)
+fn compile_or_exit(
+    options: &CompileOptions,
+    format: MessageFormat,
+    src: &str,
+) -> Output {
+    compile_and_show(options, format, src).unwrap_or_else(|code| exit(code))
 }
+
+fn show_log(diag: &Diagnostic) {
+    if diag.rendered.is_some() {
+        eprintln!("{diag}");
+        return;
+    }
+    let message = diag.to_string();
+    let message = message.trim_end().replace('\n', "\n    ");
+    match diag.severity {
+        Severity::Error => {
+            eprintln!("\x1b[1;91m[E] {message}\x1b[22;39m")
+        },
+        Severity::Warning => {
+            eprintln!("\x1b[1;93m[W] {message}\x1b[22;39m")
+        },
+        Severity::Info => eprintln!("\x1b[1m[I] {message}\x1b[22m"),
+    }
+}
+
+fn show_error(diag: &Diagnostic) {
+    match diag.stage() {
+        Stage::Compile | Stage::Test => {
+            let kind = match diag.stage() {
+                Stage::Test => "TestError",
+                _ => "CompileError",
+            };
+            let file = INPUT_NAME.with_borrow(|name| name.as_ref()
+                .map(|name| format!(" {name}"))
+                .unwrap_or_default());
+            eprintln!("\x1b[1;31m{kind}:{file}\n{diag}\x1b[22;39m");
+        },
+        // 导入错误自身已经带有所在的文件名
+        Stage::Parse => main_err(&diag.to_string()),
+        _ => err!("{diag}"),
+    }
+}
+
+/// 批量编译时的输出路径, 没有指定输出目录时输出到输入文件旁边
+fn batch_output_path(
+    file: &Path,
+    output_dir: Option<&Path>,
+    extension: &str,
+) -> PathBuf {
+    let path = match output_dir {
+        Some(dir) => {
+            let Some(name) = file.file_name() else {
+                err!("无效的输入文件路径: {}", file.display());
+                exit(2)
+            };
+            dir.join(name).with_extension(extension)
+        },
+        None => file.with_extension(extension),
+    };
+    if path == file {
+        err!("输出会覆盖输入文件 {}, 请使用`-o`指定输出目录",
+            file.display());
+        exit(2)
+    }
+    path
+}
+
+/// 读取输入文件, 并将其设置为当前处理的输入
+///
+/// 失败时返回诊断而不是退出, 以便批量处理时继续处理其它输入
+fn read_input(file: &Path) -> Result<String, Diagnostics> {
+    let name: Rc<str> = file.display().to_string().into();
+    INPUT_NAME.set(Some(name));
+    // 二进制的蓝图转换为与剪贴板中相同的base64文本
+    let src = if file.extension().is_some_and(|ext| ext == "msch") {
+        fs::read(file).map(|bytes| base64::encode(&bytes))
+    } else {
+        fs::read_to_string(file)
+    };
+    src.map_err(|e| {
+        let message = format!("read from file {} error: {e}", file.display());
+        let diagnostic = Diagnostic::error(codes::IMPORT_IO, message);
+        Diagnostics { diagnostics: vec![diagnostic], exit_code: 3 }
+    })
+}
+
+/// 没有输出路径时输出到标准输出, 源码映射会被输出到输出路径加上`.map`后缀处,
+/// 拆分或从蓝图中读取的每个处理器会被输出到输出路径的扩展名替换为`.N.logic`处,
+/// 之后还有其它模式时使用最后一个模式的扩展名,
+/// 输出路径的扩展名为`.msch`时输出二进制的蓝图
+fn write_output(
+    path: Option<&Path>,
+    output: Output,
+    options: &CompileOptions,
+) {
+    let Some(path) = path else {
+        println!("{}", output.text);
+        for (i, processor) in output.processors.iter().enumerate() {
+            println!("\n# processor {i}\n{processor}");
+        }
+        return;
+    };
+    match output.schematic {
+        Some(schematic)
+            if path.extension().is_some_and(|ext| ext == "msch") =>
+        {
+            write_file(path, schematic);
+        },
+        _ => write_file(path, output.text + "\n"),
+    }
+    if let Some(source_map) = output.source_map {
+        let mut map_path = path.as_os_str().to_owned();
+        map_path.push(".map");
+        write_file(Path::new(&map_path), source_map.to_string());
+    }
+    let extension = match options.modes.last() {
+        Some(CompileMode::BangToSplitLogic)
+        | Some(CompileMode::SchematicToMdtLogic)
+        | None => "logic",
+        Some(mode) => mode.output_extension(),
+    };
+    for (i, processor) in output.processors.into_iter().enumerate() {
+        let processor_path = path.with_extension(format!("{i}.{extension}"));
+        write_file(&processor_path, processor + "\n");
+    }
+}
+
+fn write_file(path: &Path, src: impl AsRef<[u8]>) {
+    fs::write(path, src).unwrap_or_else(|e| {
+        err!("write to {} error: {e}", path.display());
+        exit(3)
+    })
+}
+
 pub const HELP_MSG: &str = concat_lines! {
-    "<MODE...>";
+    "[OPTIONS] <MODE...> [FILE...]";
     env!("CARGO_PKG_DESCRIPTION");
     ;
     "MODE:";
@@ -258,62 +544,58 @@ pub const HELP_MSG: &str = concat_lines! {
     "\t", "L: compile MdtBangLang to MdtLabelCode";
     "\t", "b: compile MdtLogicCode to expressions";
     "\t", "p: compile MdtParenCode to MdtLogicCode";
+    "\t", "e: emulate MdtLogicCode, output messages and variables";
+    "\t", "s: split MdtBangLang at `# split NAME` lines";
+    "\t", "   to multiple processors,";
+    "\t", "   output manifest, and each processor to PATH.N.logic";
+    "\t", "m: compile MdtLogicCode to schematic for clipboard,";
+    "\t", "   or binary schematic when PATH ends with .msch";
+    "\t", "M: read processors from base64 schematic or processor config,";
+    "\t", "   output manifest, and each processor to PATH.N.logic,";
+    "\t", "   following modes apply to each processor";
+    "\t", "test: run `test` blocks of MdtBangLang on the emulator,";
+    "\t", "      must be used alone";
     ;
-    "input from stdin";
-    "output to stdout";
-    "error to stderr";
+    "input from FILE, or stdin when no FILE";
+    "imports search from directory of input and MDTLBL_PATH,";
+    "except `std/` which is bundled in the binary";
+    "output to PATH, or stdout when no PATH";
+    "source map lines are `LINE<TAB>FILE:LINE:COL[<TAB>EXPAND > ...]`";
+    "split processors output after manifest when no PATH";
+    "input FILE ending with .msch is read as binary schematic";
+    "emulator only supports processor and memory cell instructions,";
+    "others like `sensor` are noop and leave their result unchanged,";
+    "and it halts at `end` or the last line";
+    "instead of jumping back to line 0,";
+    "unless --wrap-end, which runs until --max-steps";
+    "no optimization pass runs by default, -O1 enables const-fold, dead-code";
+    "and jump-optimize, -O2 also copy-propagation and dead-store,";
+    "reuse-temps only runs with --reuse-temps or --pass=+reuse-temps";
+    "multiple FILE output to directory PATH, or beside each FILE";
+    "error to stderr, each diagnostic as a line of JSON";
+    "when --message-format=json";
     "Learning this language, from mindustry_logic_bang_lang/examples/README.md";
-    ;
+};
+pub const HELP_FOOTER: &str = concat_lines! {
     "Repository: https://github.com/A4-Tacks/mindustry_logic_bang_lang";
     "Author: A4-Tacks A4的钉子";
     "Version: ", env!("CARGO_PKG_VERSION");
 };
-impl TryFrom<char> for CompileMode {
-    type Error = char;
 
-    fn try_from(mode: char) -> Result<Self, Self::Error> {
-        Ok(match mode {
-            'c' => Self::BangToMdtLogic,
-            'a' => Self::BangToASTDebug,
-            'A' => Self::BangToASTDisplay,
-            't' => Self::BangToMdtTagCode { tag_down: false },
-            'T' => Self::BangToMdtTagCode { tag_down: true },
-            'f' => Self::MdtLogicToMdtTagCode { tag_down: false },
-            'F' => Self::MdtLogicToMdtTagCode { tag_down: true },
-            'r' => Self::MdtLogicToBang,
-            'C' => Self::MdtTagCodeToMdtLogic,
-            'l' => Self::LintLogic,
-            'i' => Self::IndentLogic,
-            'n' => Self::RenameLabel,
-            'L' => Self::BangToMdtLabel,
-            'b' => Self::BuildExpr,
-            'p' => Self::ParenToMdtLogic,
-            mode => return Err(mode),
-        })
+fn std_list() {
+    println!("revision {}", std_lib::revision());
+    for (name, _) in std_lib::MODULES {
+        println!("{}/{name}", std_lib::NAMESPACE);
     }
 }
 
-fn display_ast(ast: &Expand) -> String {
-    let mut meta = Default::default();
-    ast.display_source(&mut meta);
-    let _ = meta.pop_lf();
-    meta.buffer().into()
-}
-
-fn build_tag_down(tag_codes: &mut TagCodes) {
-    let result = tag_codes.build_tagdown();
-    result.unwrap_or_else(|(line, tag)| {
-        err!("重复的标记: {tag:?} (line {line})");
-        exit(4)
-    })
-}
-
-type ParseResult<'a> = Result<Expand, ParseError<usize, Token<'a>, Error>>;
-
-fn build_ast(src: &str) -> Expand {
-    let parser = TopLevelParser::new();
-    let mut meta = Meta::new();
-    unwrap_parse_err(parser.parse(&mut meta, src), src)
+fn std_print(name: &str) {
+    let module = std_lib::module_name(name).unwrap_or(name);
+    let Some(src) = std_lib::get(module) else {
+        err!("未找到标准库模块: {name:?}, 可以使用`--std-list`查看所有模块");
+        exit(2)
+    };
+    print!("{src}")
 }
 
 fn read_stdin_unwrapper(e: impl Display) -> ! {
@@ -328,53 +610,3 @@ fn read_stdin() -> String {
         .unwrap_or_else(|e| read_stdin_unwrapper(e));
     buf
 }
-
-fn unwrap_parse_err(result: ParseResult<'_>, src: &str) -> Expand {
-    match result {
-        Ok(ast) => ast,
-        Err(e) => {
-            err!("{}", parser::format_parse_err::<MAX_INVALID_TOKEN_VIEW>(e, src));
-            exit(4)
-        }
-    }
-}
-
-struct CompileMetaExtender {
-    source: Rc<String>,
-    display_meta: RefCell<DisplaySourceMeta>,
-}
-impl CompileMetaExtender {
-    fn new(source: Rc<String>, display_meta: RefCell<DisplaySourceMeta>) -> Self {
-        Self {
-            source,
-            display_meta,
-        }
-    }
-}
-impl CompileMetaExtends for CompileMetaExtender {
-    fn source_location(&self, index: usize) -> [syntax::Location; 2] {
-        let (line, col) = line_column::line_column(&self.source, index);
-        [line as syntax::Location, col as syntax::Location]
-    }
-    fn display_value(&self, value: &syntax::Value) -> Cow<'_, str> {
-        let meta = &mut *self.display_meta.borrow_mut();
-        meta.to_default();
-        value.display_source_and_get(meta).to_owned().into()
-    }
-    fn display_binds(&self, value: syntax::BindsDisplayer<'_>) -> Cow<'_, str> {
-        let meta = &mut *self.display_meta.borrow_mut();
-        meta.to_default();
-        value.display_source_and_get(meta).to_owned().into()
-    }
-}
-
-fn compile_ast(ast: Expand, src: String) -> CompileMeta {
-    let mut meta = CompileMeta::new();
-    let src = Rc::new(src);
-    meta.set_extender(Box::new(CompileMetaExtender::new(
-        src.clone(),
-        DisplaySourceMeta::new().into(),
-    )));
-    meta.set_source(src);
-    meta.compile_res_self(ast)
-}
@@ -21,7 +21,7 @@ syn case match
 " 一些关键字 {{{1
 syn keyword mdtlblKeyword
             \ while gwhile do skip if elif else switch gswitch break continue
-            \ const setres select match
+            \ const setres select match import test assert
             \ op noop print
 syn keyword mdtlblKeyword goto		nextgroup=mdtlblIdentLabelRest
 syn keyword mdtlblKeyword inline	nextgroup=mdtlblIdentLabelRest,mdtlblStar,mdtlblRepeatZero	skipwhite
@@ -0,0 +1,92 @@
+//! 多个输入文件的批量编译
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// 创建一个空的临时目录, 并写入各个文件
+fn temp_dir(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("mdtlbl-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (name, src) in files {
+        fs::write(dir.join(name), src).unwrap();
+    }
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mindustry_logic_bang_lang"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// 一个有效, 一个语法错误, 一个无法作为文本读取的文件
+const FILES: &[(&str, &[u8])] = &[
+    ("ok.mdtlbl", b"print 1;"),
+    ("bad.mdtlbl", b"print (;"),
+    ("binary.mdtlbl", b"print \xFF\xFE;"),
+];
+
+#[test]
+fn batch_errors_test() {
+    let dir = temp_dir("batch-errors", FILES);
+    let output = run(&dir, &["c", "ok.mdtlbl", "bad.mdtlbl", "binary.mdtlbl"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // 失败的文件不影响其它文件, 并以最后一个失败的退出码退出
+    assert_eq!(output.status.code(), Some(3), "{stderr}");
+    let ok = fs::read_to_string(dir.join("ok.logic")).unwrap();
+    assert_eq!(ok, "print 1\n");
+    assert!(!dir.join("bad.logic").exists());
+    assert!(!dir.join("binary.logic").exists());
+    assert!(stderr.contains("bad.mdtlbl"), "{stderr}");
+    let read_error = "read from file binary.mdtlbl error";
+    assert!(stderr.contains(read_error), "{stderr}");
+    assert!(!stderr.contains("ok.mdtlbl"), "{stderr}");
+
+    let output = run(&dir, &["c", "binary.mdtlbl", "ok.mdtlbl"]);
+    assert_eq!(output.status.code(), Some(3));
+    let output = run(&dir, &["c", "ok.mdtlbl", "ok.mdtlbl"]);
+    assert_eq!(output.status.code(), Some(0));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn batch_output_test() {
+    let dir = temp_dir("batch-output", &[
+        ("a.mdtlbl", b"print 1;"),
+        ("b.mdtlbl", b"print 2; '#' split second; print 3;"),
+    ]);
+
+    let output = run(&dir, &["c", "-o", "out", "a.mdtlbl", "b.mdtlbl"]);
+    assert_eq!(output.status.code(), Some(0));
+    let out = dir.join("out");
+    let a = fs::read_to_string(out.join("a.logic")).unwrap();
+    assert_eq!(a, "print 1\n");
+    assert!(out.join("b.logic").exists());
+    assert!(!dir.join("a.logic").exists());
+
+    // 拆分出的处理器输出到各个输入文件的`PATH.N.logic`处
+    let output = run(&dir, &["s", "-o", "split", "a.mdtlbl", "b.mdtlbl"]);
+    assert_eq!(output.status.code(), Some(0));
+    let out = dir.join("split");
+    assert!(out.join("a.txt").exists());
+    assert!(out.join("b.txt").exists());
+    let first = fs::read_to_string(out.join("a.0.logic")).unwrap();
+    assert_eq!(first, "print 1\n");
+    assert!(!out.join("a.1.logic").exists());
+    let second = fs::read_to_string(out.join("b.1.logic")).unwrap();
+    assert!(out.join("b.0.logic").exists());
+    assert!(second.contains("print 3\n"), "{second}");
+
+    // 输出会覆盖输入时拒绝编译
+    let output = run(&dir, &["A", "a.mdtlbl", "b.mdtlbl"]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(dir).unwrap();
+}
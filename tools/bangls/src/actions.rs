@@ -3,8 +3,8 @@ use std::{collections::HashMap, mem};
 use anyhow::{Result, bail};
 use display_source::{DisplaySource, DisplaySourceMeta};
 use itertools::Itertools;
-use lsp_types::{CodeActionParams, TextEdit, WorkspaceEdit};
-use syntax::EmulateConfig;
+use lsp_types::{CodeActionParams, TextEdit, Uri, WorkspaceEdit};
+use syntax::{EmulateConfig, SourceMap};
 use tag_code::TagCodes;
 
 use crate::{Ctx, emulate, rgpos};
@@ -28,8 +28,8 @@ fn compile_to_logic_code(ctx: &Ctx, param: &CodeActionParams) -> Option<(String,
 
     let handler = Box::new(move |ctx: &mut Ctx| {
         let file = ctx.open_files.get(&uri).map_or("", |it| it);
-        let expand = parse_file(ctx, file)?;
-        let (infos, mut meta) = emulate(expand, file.to_owned(), EmulateConfig {
+        let (expand, source_map) = parse_file_with_imports(ctx, &uri, file)?;
+        let (infos, mut meta) = emulate(expand, file.to_owned(), source_map, EmulateConfig {
             diagnostics: true,
             record_free_info: true,
             ..Default::default()
@@ -69,7 +69,7 @@ fn compile_to_desugar(ctx: &Ctx, param: &CodeActionParams) -> Option<(String, In
 
     let handler = Box::new(move |ctx: &mut Ctx| {
         let file = ctx.open_files.get(&uri).map_or("", |it| it);
-        let expand = parse_file(ctx, file)?;
+        let (expand, _) = parse_file(ctx, file)?;
 
         let display = expand.display_source_and_get(&mut DisplaySourceMeta::new()).to_owned();
 
@@ -90,8 +90,8 @@ fn compile_to_logic_label_code(ctx: &Ctx, param: &CodeActionParams) -> Option<(S
 
     let handler = Box::new(move |ctx: &mut Ctx| {
         let file = ctx.open_files.get(&uri).map_or("", |it| it);
-        let expand = parse_file(ctx, file)?;
-        let (infos, mut meta) = emulate(expand, file.to_owned(), EmulateConfig {
+        let (expand, source_map) = parse_file_with_imports(ctx, &uri, file)?;
+        let (infos, mut meta) = emulate(expand, file.to_owned(), source_map, EmulateConfig {
             diagnostics: true,
             record_free_info: true,
             ..Default::default()
@@ -114,10 +114,18 @@ fn compile_to_logic_label_code(ctx: &Ctx, param: &CodeActionParams) -> Option<(S
     }))
 }
 
-fn parse_file(ctx: &Ctx, file: &str) -> Result<syntax::Expand> {
+fn parse_file(ctx: &Ctx, file: &str) -> Result<(syntax::Expand, syntax::Meta)> {
     match ctx.parse_for_parse_error(file) {
         Err((_, e)) => bail!("{}", e.replace('\n', " , ")),
-        Ok(expand) => Ok(expand),
+        Ok(parsed) => Ok(parsed),
+    }
+}
+
+fn parse_file_with_imports(ctx: &Ctx, uri: &Uri, file: &str) -> Result<(syntax::Expand, SourceMap)> {
+    let (expand, mut meta) = parse_file(ctx, file)?;
+    match ctx.with_imports(uri, &mut meta, file, expand) {
+        Err((_, e)) => bail!("{}", e.to_string().replace('\n', " , ")),
+        Ok(loaded) => Ok(loaded),
     }
 }
 
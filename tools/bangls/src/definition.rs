@@ -0,0 +1,376 @@
+//! 查找光标处名称的定义, 用于定义跳转
+//!
+//! 语法树中的名称不带有位置, 所以定义所在的语句由[`LogicLine::Location`]定位,
+//! 需要在解析时启用[`Meta::record_locations`], 再从语句的起始处查找名称
+
+use std::ops::ControlFlow;
+
+use syntax::{
+    walk::{self, Node, ScopedVisitor},
+    Const, ConstKey, Expand, LogicLine, Take, Value, ValueBind, Var,
+};
+#[cfg(doc)]
+use syntax::Meta;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Const,
+    Take,
+    Label,
+    /// 值绑定, 例如`const a.b = ...;`
+    Bind,
+    /// `match`与`const match`的模式中绑定的名称
+    MatchBind,
+}
+
+/// 一个名称的定义处
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub kind: DefinitionKind,
+    pub name: Var,
+    /// 名称在源码中的起止位置
+    pub range: (usize, usize),
+}
+
+/// 光标处被引用的名称
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reference {
+    Name(Var),
+    /// 不包括前导`:`的标记
+    Label(Var),
+    /// 值绑定的被绑定者与名称, 被绑定者仅在其为名称时记录
+    Bind(Option<Var>, Var),
+}
+
+pub(crate) fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// 以`end`结束的名称的起始位置
+fn name_start(src: &str, end: usize) -> usize {
+    src[..end].char_indices()
+        .rev()
+        .take_while(|&(_, ch)| is_name_char(ch))
+        .last()
+        .map_or(end, |(i, _)| i)
+}
+
+/// 获取光标处的引用, 以及其名称在源码中的起止位置
+pub fn reference_at(src: &str, index: usize) -> Option<(Reference, (usize, usize))> {
+    let index = index.min(src.len());
+    let start = name_start(src, index);
+    let end = src[index..].char_indices()
+        .find(|&(_, ch)| !is_name_char(ch))
+        .map_or(src.len(), |(i, _)| index + i);
+    if start == end { return None }
+    let name = Var::from(&src[start..end]);
+    let reference = match src[..start].chars().next_back() {
+        Some(':') => Reference::Label(name),
+        Some('.') => {
+            let binder_end = start - 1;
+            let binder_start = name_start(src, binder_end);
+            let binder = (binder_start != binder_end)
+                .then(|| src[binder_start..binder_end].into());
+            Reference::Bind(binder, name)
+        },
+        _ => Reference::Name(name),
+    };
+    Some((reference, (start, end)))
+}
+
+/// 从`from`开始查找以`prefix`开头的完整名称, 返回名称的起止位置
+pub(crate) fn find_name(src: &str, from: usize, prefix: &str, name: &str) -> Option<(usize, usize)> {
+    let pattern = format!("{prefix}{name}");
+    let mut from = from.min(src.len());
+    while let Some(i) = src[from..].find(&pattern) {
+        let start = from + i + prefix.len();
+        let end = start + name.len();
+        // 有前缀时前缀之前可以是名称, 例如值绑定的被绑定者
+        let before = src[..from + i].chars().next_back()
+            .filter(|_| prefix.is_empty());
+        let after = src[end..].chars().next();
+        if !before.is_some_and(is_name_char) && !after.is_some_and(is_name_char) {
+            return Some((start, end));
+        }
+        from = end;
+    }
+    None
+}
+
+/// 作用域中的定义, 值为名称时的值, 以及其是否被泄露到外层
+type ScopeEntry = (Definition, Option<Value>, bool);
+
+struct Finder<'a> {
+    src: &'a str,
+    cursor: usize,
+    reference: &'a Reference,
+    /// 当前语句的起始位置
+    pos: usize,
+    /// 已经经过了光标所在的语句
+    passed: bool,
+    /// 每层作用域中的定义
+    scopes: Vec<Vec<ScopeEntry>>,
+    labels: Vec<Definition>,
+    /// 值绑定的定义, 以及在定义处解析后的被绑定者
+    binds: Vec<(Option<Var>, Definition)>,
+    /// 光标所在的语句处可见的定义
+    visible: Option<Definition>,
+    /// 在光标所在的语句处解析后的被绑定者
+    binder: Option<Var>,
+    /// 光标正位于该定义的名称上
+    exact: Option<Definition>,
+}
+impl Finder<'_> {
+    fn definition(&mut self, kind: DefinitionKind, name: &Var, prefix: &str) -> Definition {
+        let range = find_name(self.src, self.pos, prefix, name)
+            .unwrap_or((self.pos, self.pos));
+        let def = Definition { kind, name: name.clone(), range };
+        if range.0 <= self.cursor && self.cursor <= range.1 && range.0 != range.1 {
+            self.exact.get_or_insert(def.clone());
+        }
+        def
+    }
+
+    fn define(
+        &mut self,
+        kind: DefinitionKind,
+        name: &Var,
+        value: Option<&Value>,
+    ) {
+        let def = self.definition(kind, name, "");
+        let value = value.filter(|value| {
+            matches!(value, Value::Var(_) | Value::ReprVar(_))
+        });
+        self.scopes.last_mut().unwrap().push((def, value.cloned(), false));
+    }
+
+    fn lookup_entry(&self, name: &Var) -> Option<&ScopeEntry> {
+        self.scopes.iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(def, ..)| def.name == *name)
+    }
+
+    fn lookup(&self, name: &Var) -> Option<Definition> {
+        self.lookup_entry(name).map(|(def, ..)| def.clone())
+    }
+
+    /// 沿着值为名称的const解析被绑定者, 值不是名称时无法解析
+    fn resolve(&self, binder: &Value) -> Option<Var> {
+        let mut name = match binder {
+            Value::Var(name) => name.clone(),
+            Value::ReprVar(name) => return Some(name.clone()),
+            _ => return None,
+        };
+        // 限制次数以避免`const a = a;`之类的循环
+        for _ in 0..64 {
+            let Some((_, value, _)) = self.lookup_entry(&name) else {
+                return Some(name);
+            };
+            match value.as_ref()? {
+                Value::Var(next) if *next != name => name = next.clone(),
+                Value::ReprVar(next) | Value::Var(next) => {
+                    return Some(next.clone());
+                },
+                _ => return None,
+            }
+        }
+        None
+    }
+}
+impl ScopedVisitor for Finder<'_> {
+    fn visit(&mut self, node: Node<'_>) -> ControlFlow<()> {
+        match node {
+            Node::Line(LogicLine::Location(loc)) => {
+                self.passed |= loc.index > self.cursor;
+                self.pos = loc.index;
+                match (self.passed, self.reference) {
+                    (false, Reference::Name(name)) => {
+                        self.visible = self.lookup(name);
+                    },
+                    (false, Reference::Bind(Some(binder), _)) => {
+                        self.binder = self.resolve(&binder.clone().into());
+                    },
+                    _ => (),
+                }
+            },
+            Node::Line(LogicLine::Expand(_)) | Node::Value(Value::DExp(_)) => {
+                self.scopes.push(vec![]);
+            },
+            Node::Line(
+                | LogicLine::Const(Const(key, value, _))
+                | LogicLine::Take(Take(key, value))
+            ) => {
+                let kind = match node {
+                    Node::Line(LogicLine::Const(_)) => DefinitionKind::Const,
+                    _ => DefinitionKind::Take,
+                };
+                match key {
+                    ConstKey::Var(name) => {
+                        self.define(kind, name, Some(value));
+                    },
+                    ConstKey::ValueBind(ValueBind(binder, name)) => {
+                        let binder = self.resolve(binder);
+                        let def = self.definition(DefinitionKind::Bind, name, ".");
+                        self.binds.push((binder, def));
+                    },
+                    ConstKey::Unused(_) => (),
+                }
+            },
+            Node::Line(LogicLine::ConstLeak(name)) => {
+                if let Some((.., leaked)) = self.scopes.last_mut().unwrap()
+                    .iter_mut()
+                    .rfind(|(def, ..)| def.name == *name)
+                {
+                    *leaked = true;
+                }
+            },
+            Node::Line(LogicLine::Label(name)) => {
+                let def = self.definition(DefinitionKind::Label, name, ":");
+                self.labels.push(def);
+            },
+            Node::MatchPatAtom(atom) if !atom.name().is_empty() => {
+                let name = atom.name().into();
+                self.define(DefinitionKind::MatchBind, &name, None);
+            },
+            Node::ConstMatchPatAtom(atom) if !atom.name().is_empty() => {
+                let name = atom.name().into();
+                self.define(DefinitionKind::MatchBind, &name, None);
+            },
+            _ => (),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn leave_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        let leaked = scope.into_iter().filter(|(.., leaked)| *leaked);
+        self.scopes.last_mut().unwrap()
+            .extend(leaked.map(|(def, value, _)| (def, value, false)));
+    }
+}
+
+/// 查找光标处的名称的定义, `top`需要在解析时记录语句位置
+///
+/// const与take遵循块作用域, 标记与值绑定则在整个文件中查找,
+/// 值绑定的被绑定者会沿着值为名称的const解析, 无法解析时不查找
+pub fn find_definition(top: &Expand, src: &str, index: usize) -> Option<Definition> {
+    let (reference, _) = reference_at(src, index)?;
+    let mut finder = Finder {
+        src,
+        cursor: index,
+        reference: &reference,
+        pos: 0,
+        passed: false,
+        scopes: vec![vec![]],
+        labels: vec![],
+        binds: vec![],
+        visible: None,
+        binder: None,
+        exact: None,
+    };
+    let _ = walk::scoped_nodes(top.iter(), &mut finder);
+    let Finder { labels, binds, visible, binder, exact, .. } = finder;
+    if exact.is_some() {
+        return exact;
+    }
+    match reference {
+        Reference::Name(_) => visible,
+        Reference::Label(name) => labels.into_iter()
+            .find(|def| def.name == name),
+        Reference::Bind(_, name) => {
+            let binder = binder?;
+            binds.into_iter()
+                .rfind(|(bind_binder, def)| {
+                    def.name == name && bind_binder.as_ref() == Some(&binder)
+                })
+                .map(|(_, def)| def)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在`$0`处查找定义, 返回定义处之后的源码
+    #[track_caller]
+    fn definition_at(src: &str) -> Option<(DefinitionKind, String)> {
+        let index = src.find("$0").unwrap();
+        let src = src.replacen("$0", "", 1);
+        let mut meta = syntax::Meta::new();
+        meta.record_locations = true;
+        let top = parser::TopLevelParser::new().parse(&mut meta, &src).unwrap();
+        let def = find_definition(&top, &src, index)?;
+        Some((def.kind, src[def.range.0..].lines().next().unwrap().to_owned()))
+    }
+
+    #[test]
+    fn reference_at_test() {
+        let src = "print a.bc :lab x;";
+        assert_eq!(reference_at(src, 6), Some((Reference::Name("a".into()), (6, 7))));
+        assert_eq!(reference_at(src, 9), Some((Reference::Bind(Some("a".into()), "bc".into()), (8, 10))));
+        assert_eq!(reference_at(src, 14), Some((Reference::Label("lab".into()), (12, 15))));
+        assert_eq!(reference_at("a  b", 2), None);
+    }
+
+    #[test]
+    fn definition_test() {
+        let src = "const A = 1;\nprint A$0;";
+        assert_eq!(definition_at(src), Some((DefinitionKind::Const, "A = 1;".into())));
+        let src = "const A = 1;\n{\n    take A = 2;\n    print A$0;\n}\nprint A;";
+        assert_eq!(definition_at(src), Some((DefinitionKind::Take, "A = 2;".into())));
+        let src = "const A = 1;\n{\n    take A = 2;\n    print 3;\n}\nprint A$0;";
+        assert_eq!(definition_at(src), Some((DefinitionKind::Const, "A = 1;".into())));
+        let src = "print A$0;\nconst A = 1;";
+        assert_eq!(definition_at(src), None);
+        let src = "const A$0 = 1;\nprint A;";
+        assert_eq!(definition_at(src), Some((DefinitionKind::Const, "A = 1;".into())));
+    }
+
+    #[test]
+    fn leak_definition_test() {
+        let src = "const F = (setres _0;);\ntake[1] R = F;\nprint R$0;";
+        assert_eq!(definition_at(src), Some((DefinitionKind::Take, "R = F;".into())));
+        let src = "{\n    take R = 1;\n    print R;\n}\nprint R$0;";
+        assert_eq!(definition_at(src), None);
+    }
+
+    #[test]
+    fn label_and_bind_definition_test() {
+        let src = "goto :end$0;\nprint 1;\n:end\nend;";
+        assert_eq!(definition_at(src), Some((DefinitionKind::Label, "end".into())));
+        let src = "const a.x = 1;\nconst b.x = 2;\nprint a.x$0;\nprint c.x;";
+        assert_eq!(definition_at(src), Some((DefinitionKind::Bind, "x = 1;".into())));
+        let src = "const a.x = 1;\nconst b.x = 2;\nprint a.x;\nprint c.x$0;";
+        assert_eq!(definition_at(src), None);
+    }
+
+    #[test]
+    fn bind_binder_definition_test() {
+        /// 值绑定的定义处之后的源码
+        #[track_caller]
+        fn bind_at(src: &str) -> Option<String> {
+            let (kind, line) = definition_at(src)?;
+            assert_eq!(kind, DefinitionKind::Bind);
+            Some(line)
+        }
+
+        // 不同的被绑定者定义了相同的名称
+        let src = "const a.x = 1;\nconst b.x = 2;\nprint b.x$0;\nprint a.x;";
+        assert_eq!(bind_at(src).as_deref(), Some("x = 2;"));
+        // 沿着值为名称的const解析被绑定者
+        let src = "const a.x = 1;\nconst b.x = 2;\nconst c = a;\nprint c.x$0;";
+        assert_eq!(bind_at(src).as_deref(), Some("x = 1;"));
+        let src = "const C = b;\nconst C.x = 2;\nconst a.x = 1;\nprint b.x$0;";
+        assert_eq!(bind_at(src).as_deref(), Some("x = 2;"));
+        // 遵循作用域
+        let src = "\
+            const a.x = 1;\nconst b.x = 2;\n\
+            {\n    const c = b;\n    print 1;\n}\n\
+            const c = a;\nprint c.x$0;";
+        assert_eq!(bind_at(src).as_deref(), Some("x = 1;"));
+        let src = "const a.x = 1;\nconst c = (x: print 1;);\nprint c.x$0;";
+        assert_eq!(bind_at(src), None);
+    }
+}
@@ -0,0 +1,376 @@
+//! 格式化源码, 仅调整缩进与空白, 保留注释与原本的写法
+//!
+//! 语法树中`if`与op-expr等已被展开, 所以不从语法树生成源码,
+//! 而是按词法规则扫描源码, 注释与字符串等记号会被原样保留,
+//! 并根据括号的层数重新缩进每一行
+
+use crate::definition::is_name_char;
+
+/// 行首所处的词法状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Normal,
+    /// 跨行的字符串中
+    String,
+    /// 跨行的`#* ... *#`注释中
+    LongComment,
+}
+
+/// 格式化后的一行
+struct Line {
+    /// 该行在源码中的起止位置, 不包括换行
+    range: (usize, usize),
+    text: String,
+    /// 行首位于跨行的字符串或注释中, 该行被原样保留
+    verbatim: bool,
+}
+
+/// 扫描源码中的每一行, 重新缩进并去除行尾的空白
+///
+/// 一行中打开的多个括号只增加一层缩进, 例如`([F](`,
+/// 语句的后续行保留其相对于语句首行多出的缩进, 以保留对齐等写法
+fn format_lines(src: &str, indent: &str) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut state = State::Normal;
+    // 每个未闭合的括号所在的行的缩进层数
+    let mut levels: Vec<usize> = vec![];
+    let inner = |levels: &[usize]| levels.last().map_or(0, |&n| n + 1);
+    // 上一行代码的最后一个字符, 用于判断语句是否延续到下一行
+    let mut tail = ';';
+    // 语句首行的原缩进, 新缩进层数与行尾未闭合的括号的层数
+    let mut anchor: Option<(&str, usize, usize)> = None;
+    let mut start = 0;
+    for raw in src.split('\n') {
+        let range = (start, start + raw.len());
+        start = range.1 + 1;
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        let verbatim = state != State::Normal;
+        let continued = !matches!(tail, ';' | ':' | '(' | '[' | '{' | '}');
+        let depth = levels.len();
+        let mut chars = raw.char_indices().peekable();
+        let mut text = String::new();
+        let mut level = None;
+        // 行首的右括号闭合的括号所在的行的缩进层数
+        let mut closed = None;
+        if verbatim {
+            level = Some(inner(&levels));
+        } else {
+            while chars.next_if(|&(_, ch)| ch.is_whitespace()).is_some() {}
+        }
+        let mut last = '\0';
+        while let Some((i, ch)) = chars.next() {
+            text.push(ch);
+            match state {
+                State::String => {
+                    match ch {
+                        '\\' => if let Some((_, ch)) = chars.next() {
+                            text.push(ch);
+                        },
+                        '"' => state = State::Normal,
+                        _ => (),
+                    }
+                    continue;
+                },
+                State::LongComment => {
+                    if last == '*' && ch == '#' {
+                        state = State::Normal;
+                    }
+                    last = ch;
+                    continue;
+                },
+                State::Normal => (),
+            }
+            // 以右括号开头的行与其闭合的括号所在的行对齐
+            if level.is_none()
+                && !matches!(ch, ')' | ']' | '}')
+                && !ch.is_whitespace()
+                && !raw[i..].starts_with("%)")
+            {
+                let inner = inner(&levels);
+                // `switch`等中的`case`与其所在的块对齐
+                let is_case = raw[i..].strip_prefix("case")
+                    .is_some_and(|rest| !rest.starts_with(is_name_char));
+                level = closed.or(Some(match is_case {
+                    true => inner.saturating_sub(1),
+                    false => inner,
+                }));
+            }
+            match ch {
+                '"' => state = State::String,
+                '\'' => {
+                    let rest = &raw[i+1..];
+                    let len = rest
+                        .find(|ch: char| ch == '\'' || ch.is_whitespace())
+                        .filter(|&len| {
+                            len != 0 && rest[len..].starts_with('\'')
+                        });
+                    if let Some(len) = len {
+                        text.push_str(&rest[..=len]);
+                        let end = i + 1 + len + 1;
+                        while chars.next_if(|&(j, _)| j < end).is_some() {}
+                    }
+                },
+                '#' if raw[i..].starts_with("#*") => {
+                    chars.next();
+                    text.push('*');
+                    last = '\0';
+                    state = State::LongComment;
+                    continue;
+                },
+                '#' => {
+                    text.pop();
+                    if !text.is_empty()
+                        && !text.ends_with(char::is_whitespace)
+                    {
+                        text.push(' ');
+                    }
+                    text.push_str(&raw[i..]);
+                    break;
+                },
+                '(' | '[' | '{' => levels.push(level.unwrap()),
+                ')' | ']' | '}' => {
+                    let popped = levels.pop();
+                    if level.is_none() {
+                        closed = popped.or(closed);
+                    }
+                },
+                _ => (),
+            }
+            // `(%`与`(`相同
+            if !ch.is_whitespace() && (tail != '(' || ch != '%') {
+                tail = ch;
+            }
+        }
+        if state != State::String {
+            text.truncate(text.trim_end().len());
+        }
+        if verbatim {
+            text = raw.into();
+            if state != State::String {
+                text.truncate(text.trim_end().len());
+            }
+        } else if !text.is_empty() {
+            let level = level.or(closed).unwrap_or_else(|| inner(&levels));
+            let continued = continued
+                && closed.is_none()
+                && anchor.is_some_and(|(_, _, n)| n == depth);
+            let lead = &raw[..raw.len() - raw.trim_start().len()];
+            if !continued {
+                anchor = Some((lead, level, levels.len()));
+            }
+            let (head, head_level, _) = anchor.unwrap();
+            let prefix = lead.strip_prefix(head)
+                .filter(|extra| continued && !extra.is_empty())
+                .map_or_else(
+                    || indent.repeat(level),
+                    |extra| indent.repeat(head_level) + extra,
+                );
+            text.insert_str(0, &prefix);
+        }
+        lines.push(Line { range, text, verbatim });
+    }
+    lines
+}
+
+/// 将源码中的位置信息替换后的语法树, 用于比较格式化前后的语法树
+fn parse_without_indexes(src: &str) -> Option<String> {
+    let mut meta = syntax::Meta::new();
+    let top = parser::TopLevelParser::new().parse(&mut meta, src).ok()?;
+    let debug = format!("{top:?}");
+    let mut result = String::with_capacity(debug.len());
+    let mut rest = &*debug;
+    while let Some(i) = rest.find("index: ") {
+        let (head, tail) = rest.split_at(i + "index: ".len());
+        result.push_str(head);
+        result.push('_');
+        rest = tail.trim_start_matches(|ch: char| ch.is_ascii_digit());
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+/// 格式化源码, 连续的多个空行会被合并为一个
+///
+/// 指定范围时仅格式化与其相交的行, 返回被替换的范围与替换后的文本,
+/// 源码无法解析或格式化后的语法树发生改变时返回[`None`]
+pub fn format_source(
+    src: &str,
+    indent: &str,
+    range: Option<(usize, usize)>,
+) -> Option<((usize, usize), String)> {
+    let expected = parse_without_indexes(src)?;
+    let lines = format_lines(src, indent);
+    let selected = match range {
+        Some((start, end)) => {
+            let first = lines.iter().position(|line| line.range.1 >= start)?;
+            let last = lines.iter().rposition(|line| line.range.0 <= end)?;
+            &lines[first..=last.max(first)]
+        },
+        None => &lines[..],
+    };
+
+    let mut text = String::new();
+    let mut blank = range.is_none();
+    for line in selected {
+        if line.text.is_empty() && !line.verbatim {
+            if !blank {
+                text.push('\n');
+            }
+            blank = true;
+            continue;
+        }
+        text.push_str(&line.text);
+        text.push('\n');
+        blank = false;
+    }
+    let replaced = match range {
+        Some(_) => {
+            text.pop();
+            (selected[0].range.0, selected.last().unwrap().range.1)
+        },
+        None => {
+            text.truncate(text.trim_end().len());
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            (0, src.len())
+        },
+    };
+
+    let formatted = [&src[..replaced.0], &text, &src[replaced.1..]].concat();
+    let same = parse_without_indexes(&formatted)
+        .is_some_and(|ast| ast == expected);
+    same.then_some((replaced, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn format(src: &str, range: Option<(usize, usize)>) -> String {
+        let ((start, end), text) = format_source(src, "  ", range).unwrap();
+        let formatted = format!("{}{text}{}", &src[..start], &src[end..]);
+        assert_eq!(
+            parse_without_indexes(&formatted),
+            parse_without_indexes(src),
+        );
+        formatted
+    }
+
+    #[test]
+    fn format_test() {
+        let src = "foo  1;# a\n{foo 2;\n# b\nfoo 3;}";
+        assert_eq!(
+            format(src, None),
+            "foo  1; # a\n{foo 2;\n  # b\n  foo 3;}\n",
+        );
+        let src = " foo 1;\n foo 2;\n# c\n foo 3;  \n";
+        assert_eq!(
+            format(src, Some((9, 10))),
+            " foo 1;\nfoo 2;\n# c\n foo 3;  \n",
+        );
+        assert_eq!(
+            format(src, Some((0, 10))),
+            "foo 1;\nfoo 2;\n# c\n foo 3;  \n",
+        );
+        let src = "{switch 1 {\ncase:\nprint 1;\n    } }";
+        assert_eq!(
+            format(src, None),
+            "{switch 1 {\ncase:\n  print 1;\n} }\n",
+        );
+        let src = "const F = ([A](\nprint A;\n));\nprint  F;";
+        assert_eq!(
+            format(src, None),
+            "const F = ([A](\n  print A;\n));\nprint  F;\n",
+        );
+        let src = "\n\nprint 1;\n\n\n\nprint 2;\n\n";
+        assert_eq!(format(src, None), "print 1;\n\nprint 2;\n");
+        assert_eq!(format_source("print (;", "  ", None), None);
+    }
+
+    #[test]
+    fn format_surface_syntax_test() {
+        let src = "print   1;\nx = a+b;   # c\n  op add y x 1;";
+        assert_eq!(
+            format(src, None),
+            "print   1;\nx = a+b;   # c\nop add y x 1;\n",
+        );
+        let src = "print 'a#b' \"x  #  y\";\nprint '(' ;";
+        assert_eq!(
+            format(src, None),
+            "print 'a#b' \"x  #  y\";\nprint '(' ;\n",
+        );
+        // 语句的后续行保留多出的缩进
+        let src = "{\nconst Q = B[]\n    .A[1]  # c\n    ->X;\n}";
+        assert_eq!(
+            format(src, None),
+            "{\n  const Q = B[]\n      .A[1]  # c\n      ->X;\n}\n",
+        );
+        let src = "if 1 {\n} else\n    print 2;\n\
+                   const F = (%\nprint 1;\n%)->X;";
+        assert_eq!(
+            format(src, None),
+            "if 1 {\n} else\n    print 2;\n\
+             const F = (%\n  print 1;\n%)->X;\n",
+        );
+    }
+
+    #[test]
+    fn format_comments_test() {
+        let src = "\
+            const F = (\n\
+            # in dexp\n\
+            print 1; # after\n\
+            );\n\
+            if x < 2 {\n\
+            # in if\n\
+            print 2;\n\
+            } else {\n\
+                    # in else\n\
+            print 3;\n\
+            }\n\
+            while x < 3 {\n\
+            x = x + 1; # in while\n\
+            }\n\
+            match 1 2 {\n\
+            A B {\n\
+            # in match\n\
+            print A;\n\
+            }\n\
+            }\n\
+            #* long\n  comment *#\n\
+            print 4;";
+        assert_eq!(format(src, None), "\
+            const F = (\n\
+            \x20 # in dexp\n\
+            \x20 print 1; # after\n\
+            );\n\
+            if x < 2 {\n\
+            \x20 # in if\n\
+            \x20 print 2;\n\
+            } else {\n\
+            \x20 # in else\n\
+            \x20 print 3;\n\
+            }\n\
+            while x < 3 {\n\
+            \x20 x = x + 1; # in while\n\
+            }\n\
+            match 1 2 {\n\
+            \x20 A B {\n\
+            \x20   # in match\n\
+            \x20   print A;\n\
+            \x20 }\n\
+            }\n\
+            #* long\n  comment *#\n\
+            print 4;\n");
+    }
+
+    #[test]
+    fn format_multiline_string_test() {
+        let src = "{\nprint \"a\n   b  \n\n\n\";\n}";
+        assert_eq!(format(src, None), "{\n  print \"a\n   b  \n\n\n\";\n}\n");
+        let src = "#*#\n  *#   print 1;";
+        assert_eq!(format(src, None), "#*#\n  *#   print 1;\n");
+    }
+}
@@ -1,4 +1,12 @@
+pub mod definition;
+pub mod format;
+pub mod references;
+pub mod rename;
+pub mod semantic_tokens;
+
 use syntax::*;
+use tag_code::logic_parser::{IdxBox, ParseLine};
+use var_utils::Target;
 use walk::Node;
 use std::ops::ControlFlow;
 
@@ -43,6 +51,20 @@ pub fn cur_location(top: &Expand) -> CurLocation {
     location
 }
 
+/// 生成的逻辑中目标版本不可用的指令或操作符, 以及产生该行的语句所在的位置,
+/// 需要在编译时启用[`CompileMeta::set_record_line_sources`]
+pub fn target_unsupported(meta: &CompileMeta, target: Target) -> Vec<(SourcePos, String)> {
+    meta.parse_lines().lines().iter()
+        .filter_map(|line| {
+            let ParseLine::Args(args) = &**line else { return None };
+            let arg = &args[logic_lint::lints::target_unsupported(target, args)?];
+            let source = meta.line_sources().get(line.index)?;
+            let pos = meta.location(&IdxBox::new(source.index, ()));
+            Some((pos, format!("`{arg}` 在目标版本{target}中不可用")))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,6 +139,27 @@ mod tests {
         check_not_first("op $0 2 + 3;");
     }
 
+    #[test]
+    fn target_test() {
+        let src = "print 1;\nx = select a < b ? 1 : 2;\nop emod y x 2;";
+        let compile = |target| {
+            let mut meta = Meta::new();
+            meta.target = target;
+            meta.record_locations = true;
+            let top = PARSER.with(|parser| parser.parse(&mut meta, src)).unwrap();
+            let mut compile_meta = CompileMeta::with_source(src.to_owned().into());
+            compile_meta.set_record_line_sources(true);
+            target_unsupported(&compile_meta.compile_res_self(top), Target::V7)
+        };
+        let unsupported = compile(Target::V8);
+        assert_eq!(unsupported.len(), 2);
+        assert_eq!(unsupported[0].0.pair(), (2, 1));
+        assert!(unsupported[0].1.contains("`select`"));
+        assert_eq!(unsupported[1].0.pair(), (3, 1));
+        assert!(unsupported[1].1.contains("`emod`"));
+        assert_eq!(compile(Target::V7).len(), 1);
+    }
+
     #[test]
     fn test_on_bind_name() {
         check_at_bind_name("x.$0;");
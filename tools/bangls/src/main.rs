@@ -1,8 +1,9 @@
 use display_source::DisplaySource;
 use getopts_macro::getopts_options;
 use itertools::Itertools;
-use var_utils::AsVarType;
+use var_utils::{AsVarType, Target};
 use std::{any::Any, borrow::Cow, cell::RefCell, collections::HashSet, rc::Rc};
+use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::{Result, anyhow, bail};
 use crossbeam_channel::{Receiver, Sender};
@@ -10,12 +11,17 @@ use display_source::DisplaySourceMeta;
 use linked_hash_map::LinkedHashMap;
 use lsp_server::{IoThreads, Message, RequestId};
 use lsp_types::{CodeAction, CodeActionKind, CodeActionOptions, CodeActionOrCommand, CompletionItem, CompletionItemKind, CompletionOptions, Diagnostic, DiagnosticSeverity, InitializeParams, InitializeResult, InsertTextFormat, MessageType, Position, PublishDiagnosticsParams, ServerCapabilities, ShowMessageParams, TextDocumentSyncCapability, TextDocumentSyncKind, TraceValue, Uri, notification::{self, Notification}, request::{self, Request}};
+use lsp_types::{SemanticToken, SemanticTokenModifier, SemanticTokenType};
+use parser::import::{ImportError, Importer};
 use syntax::{Compile, CompileMeta, CompileMetaExtends, Emulate, EmulateConfig, EmulateInfo, Expand, LSP_DEBUG, LSP_HOVER};
+use syntax::SourceMap;
 use bangls::*;
+use references::ReferenceRange;
 
 fn main() {
     let options = getopts_options! {
             --vscode        "vscode fallback mode";
+            --target=VERSION "check against game version: v7, v8 or world";
         -h, --help          "show help message";
         -v, --version       "show version";
     };
@@ -28,6 +34,11 @@ fn main() {
         eprintln!("Extra arg {arg:?}");
         std::process::exit(2);
     }
+    let target = matches.opt_str("target").map(|it| it.parse::<Target>());
+    if let Some(Err(e)) = target {
+        eprintln!("{e}");
+        std::process::exit(2);
+    }
 
     main_loop(&matches).unwrap();
 }
@@ -42,6 +53,10 @@ fn rgpos(index: usize, src: &str) -> Position {
     Position { line: line - 1, character: column - 1 }
 }
 
+fn rgrange((start, end): (usize, usize), src: &str) -> lsp_types::Range {
+    lsp_types::Range { start: rgpos(start, src), end: rgpos(end, src) }
+}
+
 struct IoJoiner(pub Option<IoThreads>);
 impl std::ops::DerefMut for IoJoiner {
     fn deref_mut(&mut self) -> &mut Self::Target {
@@ -81,6 +96,29 @@ fn main_loop(matches: &getopts_macro::getopts::Matches) -> Result<()> {
         )),
         text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
         hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
+        definition_provider: Some(lsp_types::OneOf::Left(true)),
+        references_provider: Some(lsp_types::OneOf::Left(true)),
+        document_highlight_provider: Some(lsp_types::OneOf::Left(true)),
+        document_formatting_provider: Some(lsp_types::OneOf::Left(true)),
+        document_range_formatting_provider: Some(lsp_types::OneOf::Left(true)),
+        semantic_tokens_provider: Some(
+            lsp_types::SemanticTokensOptions {
+                legend: lsp_types::SemanticTokensLegend {
+                    token_types: TOKEN_TYPES.into(),
+                    token_modifiers: TOKEN_MODIFIERS.into(),
+                },
+                full: Some(lsp_types::SemanticTokensFullOptions::Delta {
+                    delta: Some(true),
+                }),
+                ..Default::default()
+            }.into(),
+        ),
+        rename_provider: Some(lsp_types::OneOf::Right(
+            lsp_types::RenameOptions {
+                prepare_provider: Some(true),
+                work_done_progress_options: Default::default(),
+            },
+        )),
         code_action_provider: Some(lsp_types::CodeActionProviderCapability::Options(CodeActionOptions {
             resolve_provider: Some(true),
             code_action_kinds: Some([CodeActionKind::EMPTY].into()),
@@ -113,6 +151,7 @@ fn main_loop(matches: &getopts_macro::getopts::Matches) -> Result<()> {
     let mut ctx = Ctx::new(connect.sender, connect.receiver);
     ctx.trace = !matches!(trace, None | Some(TraceValue::Off));
     ctx.vscode = matches.opt_present("vscode");
+    ctx.target = matches.opt_str("target").and_then(|it| it.parse().ok());
     ctx.run().map_err(|e| { ctx.trace(&e); e })
 }
 
@@ -125,6 +164,11 @@ struct Ctx {
     id_counter: i32,
     request_contents: LinkedHashMap<RequestId, (&'static str, Box<dyn Any>)>,
     vscode: bool,
+    /// 面向的游戏版本, 设置时会对生成的逻辑中不可用的指令进行诊断
+    target: Option<Target>,
+    /// 每个文件上次发送的语义高亮, 用于计算增量
+    semantic_tokens: LinkedHashMap<Uri, (String, Vec<SemanticToken>)>,
+    semantic_tokens_counter: u32,
 }
 impl Ctx {
     fn new(sender: Sender<Message>, recver: Receiver<Message>) -> Self {
@@ -137,6 +181,9 @@ impl Ctx {
             id_counter: 1,
             request_contents: Default::default(),
             vscode: false,
+            target: None,
+            semantic_tokens: Default::default(),
+            semantic_tokens_counter: 0,
         }
     }
 
@@ -171,6 +218,17 @@ impl Ctx {
 
         self.try_handle_req::<request::Completion>(request)?;
         self.try_handle_req::<request::HoverRequest>(request)?;
+        self.try_handle_req::<request::GotoDefinition>(request)?;
+        self.try_handle_req::<request::References>(request)?;
+        self.try_handle_req::<request::DocumentHighlightRequest>(request)?;
+        self.try_handle_req::<request::PrepareRenameRequest>(request)?;
+        self.try_handle_req::<request::Rename>(request)?;
+        self.try_handle_req::<request::Formatting>(request)?;
+        self.try_handle_req::<request::RangeFormatting>(request)?;
+        self.try_handle_req::<request::SemanticTokensFullRequest>(request)?;
+        self.try_handle_req::<request::SemanticTokensFullDeltaRequest>(
+            request,
+        )?;
         self.try_handle_req::<request::DocumentDiagnosticRequest>(request)?;
         self.try_handle_req::<request::CodeActionRequest>(request)?;
         self.try_handle_req::<request::CodeActionResolveRequest>(request)?;
@@ -261,6 +319,12 @@ impl Ctx {
         Ok(())
     }
 
+    fn new_meta(&self) -> syntax::Meta {
+        let mut meta = syntax::Meta::new();
+        meta.target = self.target.unwrap_or_default();
+        meta
+    }
+
     fn read_file(&self, uri: &Uri) -> Result<&str> {
         match self.open_files.get(uri) {
             Some(s) => Ok(s),
@@ -268,7 +332,12 @@ impl Ctx {
         }
     }
 
-    fn try_parse_for_complete(&self, index: usize, file: &str) -> Option<(Expand, String)> {
+    fn try_parse_for_complete(
+        &self,
+        uri: &Uri,
+        index: usize,
+        file: &str,
+    ) -> Option<(Expand, String, SourceMap)> {
         let placeholders = [
             format!("{LSP_DEBUG} "),
             format!("{LSP_DEBUG} __lsp_arg;"),
@@ -277,27 +346,122 @@ impl Ctx {
         let parser = parser::TopLevelParser::new();
         for placeholder in &placeholders {
             let source = String::from_iter([&file[..index], placeholder, &file[index..]]);
-            match parser.parse(&mut syntax::Meta::new(), &source) {
+            let mut meta = self.new_meta();
+            match parser.parse(&mut meta, &source) {
                 Err(_) => (),
-                Ok(top) => return Some((top, source)),
+                Ok(top) => {
+                    let (top, source_map) = self
+                        .with_imports(uri, &mut meta, &source, top)
+                        .unwrap_or_else(|(top, _)| (top, SourceMap::new()));
+                    return Some((top, source, source_map))
+                },
             }
         }
         None
     }
 
-    fn try_parse_for_hover(&self, index: usize, file: &str) -> Option<(Expand, String)> {
+    fn try_parse_for_hover(
+        &self,
+        uri: &Uri,
+        index: usize,
+        file: &str,
+    ) -> Option<(Expand, String, SourceMap)> {
         let parser = parser::TopLevelParser::new();
         let source = String::from_iter([&file[..index], LSP_HOVER, &file[index..]]);
-        match parser.parse(&mut syntax::Meta::new(), &source) {
+        let mut meta = self.new_meta();
+        match parser.parse(&mut meta, &source) {
             Err(_) => None,
-            Ok(top) => Some((top, source)),
+            Ok(top) => {
+                let (top, source_map) = self
+                    .with_imports(uri, &mut meta, &source, top)
+                    .unwrap_or_else(|(top, _)| (top, SourceMap::new()));
+                Some((top, source, source_map))
+            },
         }
     }
 
-    fn parse_for_parse_error(&self, file: &str) -> Result<Expand, ((usize, usize), String)> {
+    /// 加载根源码中的导入, 被导入的代码放置于根源码之前, 失败时原样返回根源码
+    fn with_imports(
+        &self,
+        uri: &Uri,
+        meta: &mut syntax::Meta,
+        src: &str,
+        top: Expand,
+    ) -> Result<(Expand, SourceMap), (Expand, ImportError)> {
+        let path = uri_path(uri).filter(|path| path.is_file());
+        let loaded = Importer::from_env()
+            .load_parsed(meta, path.as_deref(), Rc::new(src.into()));
+        match loaded {
+            Ok((mut lines, source_map)) => {
+                lines.0.extend(top.0);
+                Ok((lines, source_map))
+            },
+            Err(e) => Err((top, e)),
+        }
+    }
+
+    /// 解析并记录每个语句的位置, 见[`definition::find_definition`]
+    fn parse_for_locations(&self, file: &str) -> Option<Expand> {
+        let parser = parser::TopLevelParser::new();
+        let mut meta = self.new_meta();
+        meta.record_locations = true;
+        parser.parse(&mut meta, file).ok()
+    }
+
+    /// 模拟编译并查找光标处名称的所有引用, 见[`references::find_references`]
+    fn find_references(
+        &self,
+        uri: &Uri,
+        file: &str,
+        index: usize,
+    ) -> Vec<ReferenceRange> {
+        let Some(top) = self.parse_for_locations(file) else { return vec![] };
+        let mut meta = self.new_meta();
+        meta.record_locations = true;
+        let (full, source_map) = self
+            .with_imports(uri, &mut meta, file, top.clone())
+            .unwrap_or_else(|(top, _)| (top, SourceMap::new()));
+        let cfg = EmulateConfig {
+            record_references: true,
+            ..Default::default()
+        };
+        let (infos, _) = emulate(full, file.to_owned(), source_map, cfg);
+        let uses = infos.into_iter()
+            .filter_map(|info| info.reference)
+            .collect::<Vec<_>>();
+        references::find_references(&top, file, &uses, index)
+    }
+
+    /// 计算文件的语义高亮并记录, 返回其结果编号,
+    /// 见[`semantic_tokens::semantic_tokens`]
+    fn update_semantic_tokens(
+        &mut self,
+        uri: &Uri,
+    ) -> Result<(String, Vec<SemanticToken>)> {
+        let file = self.read_file(uri)?;
+        let tokens = match self.parse_for_locations(file) {
+            Some(top) => {
+                let tokens = semantic_tokens::semantic_tokens(&top, file);
+                encode_semantic_tokens(&tokens, file)
+            },
+            None => vec![],
+        };
+        self.semantic_tokens_counter += 1;
+        let result_id = self.semantic_tokens_counter.to_string();
+        let entry = (result_id.clone(), tokens.clone());
+        self.semantic_tokens.insert(uri.clone(), entry);
+        Ok((result_id, tokens))
+    }
+
+    fn parse_for_parse_error(
+        &self,
+        file: &str,
+    ) -> Result<(Expand, syntax::Meta), ((usize, usize), String)> {
         let parser = parser::TopLevelParser::new();
-        match parser.parse(&mut syntax::Meta::new(), file) {
-            Ok(top) => Ok(top),
+        let mut meta = self.new_meta();
+        meta.record_locations = self.target.is_some();
+        match parser.parse(&mut meta, file) {
+            Ok(top) => Ok((top, meta)),
             Err(e) => {
                 let loc = match e {
                     parser::lalrpop_util::ParseError::InvalidToken { location } |
@@ -382,11 +546,12 @@ impl RequestHandler for request::Completion {
         let file = ctx.read_file(&uri)?;
         let index = loidx(param.text_document_position.position, &file);
 
-        let Some((top, src)) = ctx.try_parse_for_complete(index, &file) else {
+        let parsed = ctx.try_parse_for_complete(&uri, index, &file);
+        let Some((top, src, source_map)) = parsed else {
             return Ok(None);
         };
         let cur_location = cur_location(&top);
-        let (infos, _) = emulate(top, src, EmulateConfig {
+        let (infos, _) = emulate(top, src, source_map, EmulateConfig {
             complete_filter: Some(completion_name_filter),
             ..Default::default()
         });
@@ -403,11 +568,12 @@ impl RequestHandler for request::HoverRequest {
         let file = ctx.read_file(&uri)?;
         let index = loidx(param.text_document_position_params.position, &file);
 
-        let Some((top, src)) = ctx.try_parse_for_hover(index, &file) else {
+        let parsed = ctx.try_parse_for_hover(&uri, index, &file);
+        let Some((top, src, source_map)) = parsed else {
             return Ok(None);
         };
         let cfg = EmulateConfig::default();
-        let (infos, _) = emulate(top, src, cfg);
+        let (infos, _) = emulate(top, src, source_map, cfg);
         let mut strings = vec![];
         let mut dedup_set = HashSet::new();
 
@@ -430,6 +596,161 @@ impl RequestHandler for request::HoverRequest {
         Ok(Some(lsp_types::Hover { contents: lsp_types::HoverContents::Array(strings), range: None }))
     }
 }
+impl RequestHandler for request::GotoDefinition {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let uri = param.text_document_position_params.text_document.uri;
+        let file = ctx.read_file(&uri)?;
+        let index = loidx(param.text_document_position_params.position, file);
+
+        let Some(top) = ctx.parse_for_locations(file) else { return Ok(None) };
+        let Some(def) = definition::find_definition(&top, file, index) else {
+            return Ok(None);
+        };
+        let range = rgrange(def.range, file);
+        let location = lsp_types::Location { uri, range };
+        Ok(Some(lsp_types::GotoDefinitionResponse::Scalar(location)))
+    }
+}
+impl RequestHandler for request::References {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let uri = param.text_document_position.text_document.uri;
+        let file = ctx.read_file(&uri)?;
+        let index = loidx(param.text_document_position.position, file);
+        let include_declaration = param.context.include_declaration;
+
+        let locations = ctx.find_references(&uri, file, index).into_iter()
+            .filter(|it| include_declaration || !it.is_define)
+            .map(|ReferenceRange { range, .. }| lsp_types::Location {
+                uri: uri.clone(),
+                range: rgrange(range, file),
+            })
+            .collect();
+        Ok(Some(locations))
+    }
+}
+impl RequestHandler for request::DocumentHighlightRequest {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let uri = param.text_document_position_params.text_document.uri;
+        let file = ctx.read_file(&uri)?;
+        let index = loidx(param.text_document_position_params.position, file);
+
+        let highlights = ctx.find_references(&uri, file, index).into_iter()
+            .map(|ReferenceRange { range, is_define }| {
+                lsp_types::DocumentHighlight {
+                    range: rgrange(range, file),
+                    kind: Some(if is_define {
+                        lsp_types::DocumentHighlightKind::WRITE
+                    } else {
+                        lsp_types::DocumentHighlightKind::READ
+                    }),
+                }
+            })
+            .collect();
+        Ok(Some(highlights))
+    }
+}
+impl RequestHandler for request::PrepareRenameRequest {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let uri = param.text_document.uri;
+        let file = ctx.read_file(&uri)?;
+        let index = loidx(param.position, file);
+
+        let Some(top) = ctx.parse_for_locations(file) else {
+            return Ok(None);
+        };
+        let range = rename::prepare_rename(&top, file, index, |src, index| {
+            ctx.find_references(&uri, src, index)
+        });
+        Ok(range.map(|range| {
+            lsp_types::PrepareRenameResponse::Range(rgrange(range, file))
+        }))
+    }
+}
+impl RequestHandler for request::Rename {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let uri = param.text_document_position.text_document.uri;
+        let file = ctx.read_file(&uri)?;
+        let index = loidx(param.text_document_position.position, file);
+
+        let Some(top) = ctx.parse_for_locations(file) else {
+            bail!("源码解析失败");
+        };
+        let new_name = &param.new_name;
+        let ranges = rename::rename(&top, file, index, new_name, |src, index| {
+            ctx.find_references(&uri, src, index)
+        }).map_err(|e| anyhow!(e))?;
+        let edits = ranges.into_iter()
+            .map(|range| lsp_types::TextEdit::new(
+                rgrange(range, file),
+                param.new_name.clone(),
+            ))
+            .collect();
+        Ok(Some(lsp_types::WorkspaceEdit::new(
+            HashMap::from_iter([(uri, edits)]),
+        )))
+    }
+}
+impl RequestHandler for request::Formatting {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let file = ctx.read_file(&param.text_document.uri)?;
+        Ok(format_edits(file, &param.options, None))
+    }
+}
+impl RequestHandler for request::RangeFormatting {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let file = ctx.read_file(&param.text_document.uri)?;
+        let range = param.range;
+        let range = (loidx(range.start, file), loidx(range.end, file));
+        Ok(format_edits(file, &param.options, Some(range)))
+    }
+}
+impl RequestHandler for request::SemanticTokensFullRequest {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let uri = param.text_document.uri;
+        let (result_id, data) = ctx.update_semantic_tokens(&uri)?;
+        Ok(Some(lsp_types::SemanticTokens {
+            result_id: Some(result_id),
+            data,
+        }.into()))
+    }
+}
+impl RequestHandler for request::SemanticTokensFullDeltaRequest {
+    fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
+        let uri = param.text_document.uri;
+        let previous = ctx.semantic_tokens.get(&uri)
+            .filter(|(result_id, _)| *result_id == param.previous_result_id)
+            .map(|(_, tokens)| tokens.clone());
+        let (result_id, data) = ctx.update_semantic_tokens(&uri)?;
+        let Some(previous) = previous else {
+            return Ok(Some(lsp_types::SemanticTokens {
+                result_id: Some(result_id),
+                data,
+            }.into()));
+        };
+
+        // 仅替换前后相同部分之间的高亮
+        let prefix = previous.iter().zip(&data)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = previous[prefix..].iter().rev()
+            .zip(data[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let edits = if previous == data {
+            vec![]
+        } else {
+            vec![lsp_types::SemanticTokensEdit {
+                start: (prefix * 5) as u32,
+                delete_count: ((previous.len() - prefix - suffix) * 5) as u32,
+                data: Some(data[prefix..data.len() - suffix].to_vec()),
+            }]
+        };
+        Ok(Some(lsp_types::SemanticTokensDelta {
+            result_id: Some(result_id),
+            edits,
+        }.into()))
+    }
+}
 impl RequestHandler for request::DocumentDiagnosticRequest {
     fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<Self::Result> {
         Ok(lsp_types::DocumentDiagnosticReportResult::Report(
@@ -651,6 +972,7 @@ impl NotificationHandler for notification::DidCloseTextDocument {
     fn handle(ctx: &mut Ctx, param: Self::Params) -> Result<()> {
         let uri = param.text_document.uri;
         ctx.trace(format_args!("close file {}", uri.as_str()));
+        ctx.semantic_tokens.remove(&uri);
         if ctx.open_files.remove(&uri).is_none() {
             ctx.send_window_notif(MessageType::WARNING, format_args!("Cannot close unknown file: {uri:?}"))?;
         }
@@ -681,13 +1003,60 @@ fn tigger_diagnostics(ctx: &Ctx, uri: &Uri) -> Vec<Diagnostic> {
                 ..Default::default()
             });
         }
-        Ok(top) => {
+        Ok((top, mut meta)) => {
+            let loaded = ctx.with_imports(uri, &mut meta, file, top);
+            let (top, source_map) = match loaded {
+                Ok(loaded) => loaded,
+                Err((top, e)) => {
+                    let (line, column) = match &e {
+                        ImportError::NotFound { at, .. }
+                            if at.file.is_none()
+                                || at.file == root_name(uri) => at.pair(),
+                        _ => (1, 1),
+                    };
+                    let index = line_column::index(file, line, column);
+                    let start = rgpos(index, file);
+                    diags.push(Diagnostic {
+                        message: e.to_string(),
+                        range: lsp_types::Range { start, end: start },
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        ..Default::default()
+                    });
+                    (top, SourceMap::new())
+                },
+            };
+            let root = source_map.files().first()
+                .and_then(|it| it.name().cloned());
             let cfg = EmulateConfig { diagnostics: true, ..Default::default() };
-            let (infos, _) = emulate(top, file.clone(), cfg);
+            let record_sources = ctx.target.is_some();
+            let (infos, meta) = emulate_with(
+                top, file.clone(), source_map, cfg,
+                |meta| meta.set_record_line_sources(record_sources),
+            );
             ctx.trace(format_args!("diagnostic infos: {infos:#?}"));
 
+            if let Some(target) = ctx.target {
+                for (pos, message) in target_unsupported(&meta, target) {
+                    if pos.file.is_some() && pos.file != root {
+                        continue;
+                    }
+                    let index = line_column::index(file, pos.line, pos.column);
+                    let start = rgpos(index, file);
+                    diags.push(Diagnostic {
+                        message,
+                        range: lsp_types::Range { start, end: start },
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        ..Default::default()
+                    });
+                }
+            }
+
             for info in infos {
                 let Some(diagnostic) = info.diagnostic else { continue };
+                if info.file.is_some() && info.file != root {
+                    // 被导入的文件中的诊断
+                    continue;
+                }
                 let Some(loc) = info.location.or_else(|| info.is_error.then(|| {
                     (1, 1)
                 })) else { continue };
@@ -709,11 +1078,114 @@ fn tigger_diagnostics(ctx: &Ctx, uri: &Uri) -> Vec<Diagnostic> {
     diags
 }
 
-fn emulate(top: Expand, src: String, cfg: EmulateConfig) -> (Vec<EmulateInfo>, CompileMeta) {
+/// 语义高亮的种类, [`SemanticToken::token_type`]为其中的下标
+const TOKEN_TYPES: [SemanticTokenType; 7] = [
+    SemanticTokenType::MACRO,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::new("label"),
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::FUNCTION,
+];
+/// 语义高亮的修饰, [`SemanticToken::token_modifiers_bitset`]中的各位
+const TOKEN_MODIFIERS: [SemanticTokenModifier; 3] = [
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::READONLY,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+];
+
+/// 转换为相对于上一个高亮的位置, 位置的列使用UTF-16计数
+fn encode_semantic_tokens(
+    tokens: &[semantic_tokens::Token],
+    file: &str,
+) -> Vec<SemanticToken> {
+    use semantic_tokens::TokenKind;
+
+    let (mut index, mut line, mut column) = (0, 0, 0);
+    let (mut last_line, mut last_column) = (0, 0);
+    tokens.iter().map(|token| {
+        let (start, end) = token.range;
+        for ch in file[index..start].chars() {
+            if ch == '\n' {
+                line += 1;
+                column = 0;
+            } else {
+                column += ch.len_utf16() as u32;
+            }
+        }
+        index = start;
+        let (token_type, mut modifiers) = match token.kind {
+            TokenKind::Const => (0, 0),
+            TokenKind::Take => (1, 0b010),
+            TokenKind::Label => (2, 0),
+            TokenKind::ResultHandle => (3, 0),
+            TokenKind::Bind => (4, 0),
+            TokenKind::BuiltinNamespace => (5, 0b100),
+            TokenKind::Builtin => (6, 0b100),
+        };
+        if token.declaration {
+            modifiers |= 0b001;
+        }
+        let delta_start = if line == last_line {
+            column - last_column
+        } else {
+            column
+        };
+        let token = SemanticToken {
+            delta_line: line - last_line,
+            delta_start,
+            length: file[start..end].encode_utf16().count() as u32,
+            token_type,
+            token_modifiers_bitset: modifiers,
+        };
+        (last_line, last_column) = (line, column);
+        token
+    }).collect()
+}
+
+/// 格式化文件, 缩进由编辑器的格式化选项决定, 见[`format::format_source`]
+fn format_edits(
+    file: &str,
+    options: &lsp_types::FormattingOptions,
+    range: Option<(usize, usize)>,
+) -> Option<Vec<lsp_types::TextEdit>> {
+    let indent = if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".into()
+    };
+    let ((start, end), text) = format::format_source(file, &indent, range)?;
+    let range = lsp_types::Range {
+        start: rgpos(start, file),
+        end: rgpos(end, file),
+    };
+    Some(vec![lsp_types::TextEdit::new(range, text)])
+}
+
+fn emulate(
+    top: Expand,
+    src: String,
+    source_map: SourceMap,
+    cfg: EmulateConfig,
+) -> (Vec<EmulateInfo>, CompileMeta) {
+    emulate_with(top, src, source_map, cfg, |_| ())
+}
+
+/// 同[`emulate`], 但是可以在编译前对[`CompileMeta`]进行配置
+fn emulate_with(
+    top: Expand,
+    src: String,
+    source_map: SourceMap,
+    cfg: EmulateConfig,
+    config: impl FnOnce(&mut CompileMeta),
+) -> (Vec<EmulateInfo>, CompileMeta) {
     let source: Rc<String> = src.into();
     let mut meta = CompileMeta::with_source(source.clone());
+    meta.set_source_map(source_map);
     meta.emutale_config = Some(cfg);
     meta.set_extender(Box::new(Extender::new(source, DisplaySourceMeta::new().into())));
+    config(&mut meta);
 
     let assert_meta = std::panic::AssertUnwindSafe(&mut meta);
     let _ = std::panic::catch_unwind(|| {
@@ -722,6 +1194,21 @@ fn emulate(top: Expand, src: String, cfg: EmulateConfig) -> (Vec<EmulateInfo>, C
     (meta.emulate_infos.take(), meta)
 }
 
+fn uri_path(uri: &Uri) -> Option<PathBuf> {
+    if !uri.scheme()?.as_str().eq_ignore_ascii_case("file") {
+        return None;
+    }
+    let path = uri.path().as_estr().decode().into_string().ok()?;
+    Some(path.into_owned().into())
+}
+
+/// 根源码在导入时所使用的文件名
+fn root_name(uri: &Uri) -> Option<Rc<str>> {
+    uri_path(uri)
+        .filter(|path| path.is_file())
+        .map(|path| path.display().to_string().into())
+}
+
 struct Extender {
     source: Rc<String>,
     display_meta: RefCell<DisplaySourceMeta>,
@@ -0,0 +1,146 @@
+//! 查找光标处名称的所有引用, 用于引用跳转与高亮
+//!
+//! 名称的使用由模拟编译时记录的[`VarReference`]得到, 所以包括在const展开中产生的使用,
+//! 使用只记录到语句, 再在语句中查找名称的具体位置
+
+use std::{collections::{BTreeSet, HashSet}, ops::ControlFlow};
+
+use syntax::{walk::{self, Node}, Expand, LogicLine, VarReference};
+
+use crate::definition::{find_definition, find_name, reference_at, Reference};
+
+/// 名称的一处引用
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceRange {
+    /// 名称在源码中的起止位置
+    pub range: (usize, usize),
+    /// 是否是常量的定义处
+    pub is_define: bool,
+}
+
+/// 所有语句的起始位置, 升序
+fn statement_starts(top: &Expand) -> Vec<usize> {
+    let mut starts = vec![];
+    let _ = walk::nodes(top.iter(), |node| {
+        if let Node::Line(LogicLine::Location(loc)) = node {
+            starts.push(loc.index);
+        }
+        ControlFlow::Continue(())
+    });
+    starts.sort_unstable();
+    starts.dedup();
+    starts
+}
+
+/// 查找光标处的名称的所有引用
+///
+/// `top`需要在解析时记录语句位置, `uses`为模拟编译时记录的使用,
+/// 其中位于根源码之外的使用仅用于确定其所指的定义, 根源码在位置空间中的基址为0
+pub fn find_references(
+    top: &Expand,
+    src: &str,
+    uses: &[VarReference],
+    index: usize,
+) -> Vec<ReferenceRange> {
+    let Some((Reference::Name(name), cursor)) = reference_at(src, index) else {
+        return vec![];
+    };
+    let starts = statement_starts(top);
+    let statement = |index: usize| {
+        let i = starts.partition_point(|&start| start <= index);
+        let start = i.checked_sub(1).map_or(0, |i| starts[i]);
+        let end = starts.get(i).copied().unwrap_or(src.len());
+        (start, end)
+    };
+    let uses = uses.iter()
+        .filter(|it| it.name == name)
+        .collect::<Vec<_>>();
+
+    // 光标处的名称可能在多次展开中指向不同的定义
+    let (at, _) = statement(cursor.0);
+    let mut defines = uses.iter()
+        .filter(|it| it.location == at)
+        .map(|it| it.define)
+        .collect::<HashSet<_>>();
+    if find_definition(top, src, index).is_some_and(|def| def.range == cursor) {
+        defines.insert(Some(at));
+    }
+
+    let mut result = defines.iter()
+        .flatten()
+        .filter(|&&define| define <= src.len())
+        .filter_map(|&define| find_name(src, define, "", &name))
+        .map(|range| ReferenceRange { range, is_define: true })
+        .collect::<Vec<_>>();
+    let locations = uses.iter()
+        .filter(|it| it.location <= src.len() && defines.contains(&it.define))
+        .map(|it| it.location)
+        .collect::<BTreeSet<_>>();
+    for location in locations {
+        let (mut from, end) = statement(location);
+        while let Some(range) = find_name(&src[..end], from, "", &name) {
+            from = range.1;
+            let is_name = matches!(reference_at(src, range.0), Some((Reference::Name(_), _)));
+            if is_name && result.iter().all(|it| it.range != range) {
+                result.push(ReferenceRange { range, is_define: false });
+            }
+        }
+    }
+    result.sort_unstable_by_key(|it| it.range);
+    result
+}
+
+/// 解析并模拟编译`src`, 再查找引用, 解析失败时返回空
+#[cfg(test)]
+pub(crate) fn emulate_references(src: &str, index: usize) -> Vec<ReferenceRange> {
+    use syntax::{Compile, CompileMeta, EmulateConfig};
+
+    let mut meta = syntax::Meta::new();
+    meta.record_locations = true;
+    let Ok(top) = parser::TopLevelParser::new().parse(&mut meta, src) else {
+        return vec![];
+    };
+    let mut meta = CompileMeta::with_source(src.to_owned().into());
+    meta.emutale_config = Some(EmulateConfig {
+        record_references: true,
+        ..Default::default()
+    });
+    top.clone().compile(&mut meta);
+    let uses = meta.emulate_infos.take().into_iter()
+        .filter_map(|info| info.reference)
+        .collect::<Vec<_>>();
+    find_references(&top, src, &uses, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在`$0`处查找引用, 返回各个引用所在的行与是否为定义
+    #[track_caller]
+    fn references_at(src: &str) -> Vec<(usize, bool)> {
+        let index = src.find("$0").unwrap();
+        let src = src.replacen("$0", "", 1);
+        emulate_references(&src, index).into_iter()
+            .map(|it| (src[..it.range.0].matches('\n').count(), it.is_define))
+            .collect()
+    }
+
+    #[test]
+    fn references_test() {
+        let src = "const A = 1;\nprint A$0;\n{\n    const A = 2;\n    print A;\n}\nprint A;";
+        assert_eq!(references_at(src), [(0, true), (1, false), (6, false)]);
+        let src = "const A$0 = 1;\nprint A;\nprint 2;";
+        assert_eq!(references_at(src), [(0, true), (1, false)]);
+        let src = "set x$0 1;\nprint x;\nconst x = 2;\nprint x;";
+        assert_eq!(references_at(src), [(0, false), (1, false)]);
+        let src = "print a.A$0;\nconst A = 1;\nprint A;";
+        assert_eq!(references_at(src), []);
+    }
+
+    #[test]
+    fn expand_references_test() {
+        let src = "const F = (\n    print A;\n    print 1;\n);\nconst A = 1;\nF;\nprint A$0;";
+        assert_eq!(references_at(src), [(1, false), (4, true), (6, false)]);
+    }
+}
@@ -0,0 +1,393 @@
+//! 重命名光标处的const, 变量或标记
+//!
+//! const与变量的引用见[`find_references`], 重命名后会再次查找引用,
+//! 以确认新名称没有捕获其它名称, 也没有被其它定义捕获
+//!
+//! 标记的使用由语法树中的标记与跳转语句得到, 并按其所在的const值区分,
+//! 因为const中定义的标记在每次展开时都会被重命名
+#[cfg(doc)]
+use crate::references::find_references;
+
+use std::{collections::{BTreeSet, HashSet}, ops::ControlFlow, ptr};
+
+use syntax::{
+    walk::{self, Node, ScopedVisitor},
+    ClosuredValue, Const, Expand, Goto, LogicLine, Value, Var,
+};
+
+use crate::{
+    definition::{find_name, is_name_char, reference_at, Reference},
+    references::ReferenceRange,
+    semantic_tokens::words,
+};
+
+fn is_valid_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|ch| !ch.is_ascii_digit())
+        && name.chars().all(is_name_char)
+}
+
+/// 标记作用域, 即const与闭包的值, 其中定义的标记在每次展开时被重命名
+struct LabelScope {
+    parent: usize,
+    /// 该作用域中定义的标记, 其余标记指向外层
+    labels: Vec<Var>,
+}
+
+/// 一处使用标记的语句
+struct LabelSite {
+    /// 所在语句的起始位置
+    location: usize,
+    scope: usize,
+    name: Var,
+}
+
+/// 收集语法树中的标记定义与跳转, 以及其所在的标记作用域
+struct LabelCollector {
+    scopes: Vec<LabelScope>,
+    current: usize,
+    /// 每层DExp与块所进入的作用域之前的作用域
+    entered: Vec<Option<usize>>,
+    /// 等待被访问的const或闭包的值, 以及其中定义的标记
+    pending: Option<(*const Value, Vec<Var>)>,
+    /// 值中的首个DExp将产生新的标记作用域
+    descend: Option<Vec<Var>>,
+    location: usize,
+    starts: Vec<usize>,
+    sites: Vec<LabelSite>,
+}
+impl LabelCollector {
+    fn site(&mut self, name: &Var) {
+        self.sites.push(LabelSite {
+            location: self.location,
+            scope: self.current,
+            name: name.clone(),
+        });
+    }
+
+    /// 标记名称在该作用域中所指向的作用域
+    fn resolve(&self, mut scope: usize, name: &Var) -> usize {
+        while scope != 0 && !self.scopes[scope].labels.contains(name) {
+            scope = self.scopes[scope].parent;
+        }
+        scope
+    }
+
+    /// 从`scope`到其指向的作用域`to`之间是否有作用域定义了`name`
+    fn shadowed(&self, mut scope: usize, to: usize, name: &Var) -> bool {
+        while scope != to {
+            if self.scopes[scope].labels.contains(name) {
+                return true;
+            }
+            scope = self.scopes[scope].parent;
+        }
+        false
+    }
+}
+impl ScopedVisitor for LabelCollector {
+    fn visit(&mut self, node: Node<'_>) -> ControlFlow<()> {
+        match node {
+            Node::Line(LogicLine::Location(loc)) => {
+                self.location = loc.index;
+                self.starts.push(loc.index);
+            },
+            Node::Line(LogicLine::Const(Const(_, value, labels))) => {
+                self.pending = Some((ptr::from_ref(value), labels.clone()));
+                self.descend = None;
+            },
+            Node::Line(LogicLine::Label(name))
+            | Node::Line(LogicLine::Goto(Goto(name, _))) => {
+                self.site(name);
+                self.descend = None;
+            },
+            Node::Line(line) => {
+                if let LogicLine::Expand(_) = line {
+                    self.entered.push(None);
+                }
+                self.descend = None;
+            },
+            Node::Value(value) => {
+                if self.pending.as_ref()
+                    .is_some_and(|&(ptr, _)| ptr::eq(ptr, value))
+                {
+                    self.descend = self.pending.take().map(|it| it.1);
+                }
+                match value {
+                    Value::DExp(_) => {
+                        let prev = self.descend.take().map(|labels| {
+                            let parent = self.current;
+                            self.scopes.push(LabelScope { parent, labels });
+                            self.current = self.scopes.len() - 1;
+                            parent
+                        });
+                        self.entered.push(prev);
+                    },
+                    Value::ClosuredValue(ClosuredValue::Uninit {
+                        value,
+                        labels,
+                        catch_labels,
+                        ..
+                    }) => {
+                        catch_labels.iter().for_each(|name| self.site(name));
+                        let value = ptr::from_ref(&**value);
+                        self.pending = Some((value, labels.clone()));
+                    },
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn leave_scope(&mut self) {
+        if let Some(prev) = self.entered.pop().flatten() {
+            self.current = prev;
+        }
+    }
+}
+
+/// 源码中标记的各处使用, 按所指向的标记作用域区分
+struct Labels<'a> {
+    src: &'a str,
+    collector: LabelCollector,
+    /// 不在注释与字符串中的名称
+    words: HashSet<(usize, usize)>,
+}
+impl<'a> Labels<'a> {
+    fn new(top: &Expand, src: &'a str) -> Self {
+        let mut collector = LabelCollector {
+            scopes: vec![LabelScope { parent: 0, labels: vec![] }],
+            current: 0,
+            entered: vec![],
+            pending: None,
+            descend: None,
+            location: 0,
+            starts: vec![],
+            sites: vec![],
+        };
+        let _ = walk::scoped_nodes(top.iter(), &mut collector);
+        collector.starts.sort_unstable();
+        collector.starts.dedup();
+        Self { src, collector, words: words(src).into_iter().collect() }
+    }
+
+    /// 指向`scope`的该名称的标记的各个位置, 升序
+    fn ranges(&self, scope: usize, name: &Var) -> Vec<(usize, usize)> {
+        let collector = &self.collector;
+        let locations = collector.sites.iter()
+            .filter(|site| site.name == *name)
+            .filter(|site| collector.resolve(site.scope, name) == scope)
+            .map(|site| site.location)
+            .collect::<BTreeSet<_>>();
+        let mut ranges = BTreeSet::new();
+        for location in locations {
+            let i = collector.starts.partition_point(|&i| i <= location);
+            let end = collector.starts.get(i).copied()
+                .unwrap_or(self.src.len());
+            let src = &self.src[..end];
+            let mut from = location;
+            while let Some(range) = find_name(src, from, ":", name) {
+                from = range.1;
+                if self.words.contains(&range) {
+                    ranges.insert(range);
+                }
+            }
+        }
+        ranges.into_iter().collect()
+    }
+
+    /// 光标处的标记所指向的作用域
+    fn scope_at(&self, cursor: (usize, usize), name: &Var) -> Option<usize> {
+        let collector = &self.collector;
+        collector.sites.iter()
+            .filter(|site| site.name == *name)
+            .map(|site| collector.resolve(site.scope, name))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .find(|&scope| self.ranges(scope, name).contains(&cursor))
+    }
+
+    /// 将指向`scope`的标记重命名为`new_name`后是否会被内层的定义捕获
+    fn captured(&self, scope: usize, name: &Var, new_name: &Var) -> bool {
+        let collector = &self.collector;
+        collector.sites.iter()
+            .filter(|site| site.name == *name)
+            .filter(|site| collector.resolve(site.scope, name) == scope)
+            .any(|site| collector.shadowed(site.scope, scope, new_name))
+    }
+}
+
+/// 将各个位置替换为新名称, 返回替换后的源码与新名称的位置
+fn apply(src: &str, ranges: &[(usize, usize)], new_name: &str) -> (String, Vec<(usize, usize)>) {
+    let mut renamed = String::with_capacity(src.len());
+    let mut new_ranges = Vec::with_capacity(ranges.len());
+    let mut last = 0;
+    for &(start, end) in ranges {
+        renamed.push_str(&src[last..start]);
+        let new_start = renamed.len();
+        renamed.push_str(new_name);
+        new_ranges.push((new_start, renamed.len()));
+        last = end;
+    }
+    renamed.push_str(&src[last..]);
+    (renamed, new_ranges)
+}
+
+/// 光标处可被重命名的名称的起止位置
+///
+/// `top`需要在解析时记录语句位置, 用于确定标记的各处使用
+pub fn prepare_rename(
+    top: &Expand,
+    src: &str,
+    index: usize,
+    references: impl FnOnce(&str, usize) -> Vec<ReferenceRange>,
+) -> Option<(usize, usize)> {
+    let (reference, cursor) = reference_at(src, index)?;
+    let ranges = match reference {
+        Reference::Label(name) => {
+            let labels = Labels::new(top, src);
+            labels.ranges(labels.scope_at(cursor, &name)?, &name)
+        },
+        Reference::Name(_) => references(src, index).into_iter()
+            .map(|it| it.range)
+            .collect(),
+        Reference::Bind(..) => return None,
+    };
+    ranges.contains(&cursor).then_some(cursor)
+}
+
+/// 重命名光标处的名称, 返回需要被替换为新名称的各个位置, 升序
+///
+/// 标记仅在其所在的const或闭包的值中重命名, 并跳过注释与字符串,
+/// `references`用于在给定的源码中查找某处名称的所有引用
+pub fn rename(
+    top: &Expand,
+    src: &str,
+    index: usize,
+    new_name: &str,
+    mut references: impl FnMut(&str, usize) -> Vec<ReferenceRange>,
+) -> Result<Vec<(usize, usize)>, String> {
+    if !is_valid_name(new_name) {
+        return Err(format!("`{new_name}` 不是一个有效的名称"));
+    }
+    let not_found = || "光标处没有可重命名的名称".to_owned();
+    let Some((reference, cursor)) = reference_at(src, index) else {
+        return Err(not_found());
+    };
+    let ranges = match &reference {
+        Reference::Label(name) => {
+            let labels = Labels::new(top, src);
+            let scope = labels.scope_at(cursor, name).ok_or_else(not_found)?;
+            let new_name = Var::from(new_name);
+            if *name != new_name && (
+                !labels.ranges(scope, &new_name).is_empty()
+                || labels.captured(scope, name, &new_name)
+            ) {
+                return Err(format!("标记 `{new_name}` 已存在"));
+            }
+            labels.ranges(scope, name)
+        },
+        Reference::Name(_) => references(src, index).into_iter()
+            .map(|it| it.range)
+            .collect(),
+        Reference::Bind(..) => return Err("不支持重命名值绑定".into()),
+    };
+    if !ranges.contains(&cursor) {
+        return Err(not_found());
+    }
+    if let Reference::Name(_) = reference {
+        let (renamed, new_ranges) = apply(src, &ranges, new_name);
+        let found = references(&renamed, new_ranges[0].0).into_iter()
+            .map(|it| it.range)
+            .collect::<Vec<_>>();
+        if found != new_ranges {
+            return Err(format!("重命名为 `{new_name}` 会与其它名称冲突"));
+        }
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::references::emulate_references;
+
+    use super::*;
+
+    /// 将`$0`处的名称重命名, 返回重命名后的源码
+    #[track_caller]
+    fn rename_at(src: &str, new_name: &str) -> Result<String, String> {
+        let index = src.find("$0").unwrap();
+        let src = src.replacen("$0", "", 1);
+        let mut meta = syntax::Meta::new();
+        meta.record_locations = true;
+        let top = parser::TopLevelParser::new().parse(&mut meta, &src)
+            .unwrap();
+        let ranges = rename(&top, &src, index, new_name, emulate_references)?;
+        Ok(apply(&src, &ranges, new_name).0)
+    }
+
+    #[test]
+    fn rename_test() {
+        let src = "const A = 1;\nprint A$0;\n{\n    const A = 2;\n    print A;\n}";
+        assert_eq!(rename_at(src, "B").unwrap(), "const B = 1;\nprint B;\n{\n    const A = 2;\n    print A;\n}");
+        let src = "set x$0 1;\nprint x;";
+        assert_eq!(rename_at(src, "y").unwrap(), "set y 1;\nprint y;");
+        let src = ":a$0\ngoto :a;\nprint a;";
+        assert_eq!(rename_at(src, "b").unwrap(), ":b\ngoto :b;\nprint a;");
+        assert!(rename_at("const A$0 = 1;", "1x").is_err());
+        assert!(rename_at("print a.b$0;", "c").is_err());
+    }
+
+    #[test]
+    fn rename_capture_test() {
+        let src = "const A = 1;\n{\n    const B = 2;\n    print A$0;\n}";
+        assert!(rename_at(src, "B").is_err());
+        let src = "const A$0 = 1;\nprint B;\nprint A;";
+        assert!(rename_at(src, "B").is_err());
+        let src = ":a$0\n:b\ngoto :a;";
+        assert!(rename_at(src, "b").is_err());
+        let src = ":a$0\nconst F = (\n    :b\n    goto :a;\n);\nF;";
+        assert!(rename_at(src, "b").is_err());
+    }
+
+    #[test]
+    fn rename_label_test() {
+        let src = ":a$0\nprint \":a\";\ngoto :a; # :a\nprint a;";
+        assert_eq!(
+            rename_at(src, "b").unwrap(),
+            ":b\nprint \":a\";\ngoto :b; # :a\nprint a;",
+        );
+        let src = ":a\nprint \":a$0\";";
+        assert!(rename_at(src, "b").is_err());
+        let src = ":a\n# goto :a$0;";
+        assert!(rename_at(src, "b").is_err());
+
+        // const中定义的标记在每次展开时是不同的标记
+        let src = "\
+            const F = (\n    :a\n    goto :a$0;\n);\n\
+            :a\ngoto :a;";
+        assert_eq!(
+            rename_at(src, "b").unwrap(),
+            "const F = (\n    :b\n    goto :b;\n);\n:a\ngoto :a;",
+        );
+        let src = "\
+            const F = (\n    :a\n    goto :a;\n);\n\
+            :a$0\ngoto :a;";
+        assert_eq!(
+            rename_at(src, "b").unwrap(),
+            "const F = (\n    :a\n    goto :a;\n);\n:b\ngoto :b;",
+        );
+        let src = "const F = (goto :a$0;);\n:a\nF;";
+        assert_eq!(
+            rename_at(src, "b").unwrap(),
+            "const F = (goto :b;);\n:b\nF;",
+        );
+        let src = "\
+            const F = (\n    :a\n    goto :a;\n);\n\
+            :b\ngoto :b$0;";
+        assert_eq!(
+            rename_at(src, "a").unwrap(),
+            "const F = (\n    :a\n    goto :a;\n);\n:a\ngoto :a;",
+        );
+    }
+}
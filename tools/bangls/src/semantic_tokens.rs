@@ -0,0 +1,259 @@
+//! 根据语法树区分源码中的各种名称, 用于语义高亮
+//!
+//! 语法树中的名称不带有位置, 所以先按词法规则找出源码中的名称,
+//! 再由其所在语句处可见的定义来区分const与take
+
+use std::{collections::HashMap, ops::ControlFlow};
+
+use syntax::{
+    walk::{self, Node, ScopedVisitor},
+    Const, ConstKey, Expand, LogicLine, Take, Value, Var,
+};
+
+use crate::definition::{find_name, is_name_char};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// const与match中绑定的名称
+    Const,
+    Take,
+    Label,
+    /// `$`与DExp中指定的返回句柄, 例如`(x: ...)`中的`x`
+    ResultHandle,
+    /// 值绑定的名称, 例如`a.b`中的`b`
+    Bind,
+    /// `Builtin.Xxx`中的`Builtin`
+    BuiltinNamespace,
+    /// `Builtin.Xxx`中的`Xxx`
+    Builtin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    /// 在源码中的起止位置
+    pub range: (usize, usize),
+    pub kind: TokenKind,
+    /// 是否为const或take的定义处
+    pub declaration: bool,
+}
+
+/// 源码中所有的名称与`$`的起止位置, 跳过注释, 字符串, 数字与`@xxx`
+pub(crate) fn words(src: &str) -> Vec<(usize, usize)> {
+    let mut skips = parser::comments(src).into_iter()
+        .map(|comment| (comment.index, comment.index + comment.text.len()))
+        .peekable();
+    let mut words = vec![];
+    let mut chars = src.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        while skips.next_if(|&(_, end)| end <= i).is_some() {}
+        if let Some(&(start, end)) = skips.peek() && start <= i {
+            while chars.next_if(|&(j, _)| j < end).is_some() {}
+            continue;
+        }
+        match ch {
+            '"' => while let Some((_, ch)) = chars.next() {
+                match ch {
+                    '\\' => { chars.next(); },
+                    '"' => break,
+                    _ => (),
+                }
+            },
+            '\'' => {
+                let quoted = |&(_, ch): &(usize, char)| {
+                    ch != '\'' && !ch.is_whitespace()
+                };
+                while chars.next_if(quoted).is_some() {}
+                chars.next_if(|&(_, ch)| ch == '\'');
+            },
+            '$' => words.push((i, i + 1)),
+            '@' => {
+                let builtin = |&(_, ch): &(usize, char)| {
+                    is_name_char(ch) || ch == '-'
+                };
+                while chars.next_if(builtin).is_some() {}
+            },
+            ch if is_name_char(ch) => {
+                let mut end = i + ch.len_utf8();
+                let name = |&(_, ch): &(usize, char)| is_name_char(ch);
+                while let Some((j, ch)) = chars.next_if(name) {
+                    end = j + ch.len_utf8();
+                }
+                if !ch.is_ascii_digit() {
+                    words.push((i, end));
+                }
+            },
+            _ => (),
+        }
+    }
+    words
+}
+
+struct Classifier<'a> {
+    src: &'a str,
+    /// 当前语句的起始位置
+    pos: usize,
+    /// 每层作用域中的定义, 以及其是否被泄露到外层
+    scopes: Vec<Vec<(Var, TokenKind, bool)>>,
+    /// 每个语句处可见的定义
+    visible: Vec<(usize, HashMap<Var, TokenKind>)>,
+    /// 定义处的名称位置
+    declarations: HashMap<(usize, usize), TokenKind>,
+}
+impl Classifier<'_> {
+    fn define(&mut self, kind: TokenKind, name: &Var) {
+        if let Some(range) = find_name(self.src, self.pos, "", name) {
+            self.declarations.entry(range).or_insert(kind);
+        }
+        self.scopes.last_mut().unwrap().push((name.clone(), kind, false));
+    }
+}
+impl ScopedVisitor for Classifier<'_> {
+    fn visit(&mut self, node: Node<'_>) -> ControlFlow<()> {
+        match node {
+            Node::Line(LogicLine::Location(loc)) => {
+                self.pos = loc.index;
+                let visible = self.scopes.iter()
+                    .flatten()
+                    .map(|(name, kind, _)| (name.clone(), *kind))
+                    .collect();
+                self.visible.push((loc.index, visible));
+            },
+            Node::Line(LogicLine::Expand(_)) | Node::Value(Value::DExp(_)) => {
+                self.scopes.push(vec![]);
+            },
+            Node::Line(LogicLine::Const(Const(ConstKey::Var(name), ..))) => {
+                self.define(TokenKind::Const, name);
+            },
+            Node::Line(LogicLine::Take(Take(ConstKey::Var(name), _))) => {
+                self.define(TokenKind::Take, name);
+            },
+            Node::Line(LogicLine::ConstLeak(name)) => {
+                if let Some((_, _, leaked)) = self.scopes.last_mut().unwrap()
+                    .iter_mut()
+                    .rfind(|(def, ..)| def == name)
+                {
+                    *leaked = true;
+                }
+            },
+            Node::MatchPatAtom(atom) if !atom.name().is_empty() => {
+                self.define(TokenKind::Const, &atom.name().into());
+            },
+            Node::ConstMatchPatAtom(atom) if !atom.name().is_empty() => {
+                self.define(TokenKind::Const, &atom.name().into());
+            },
+            _ => (),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn leave_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        let leaked = scope.into_iter().filter(|(.., leaked)| *leaked);
+        self.scopes.last_mut().unwrap()
+            .extend(leaked.map(|(name, kind, _)| (name, kind, false)));
+    }
+}
+
+/// 区分源码中的各种名称, 按位置升序, `top`需要在解析时记录语句位置
+///
+/// 普通的变量与关键字等不会被包括在内
+pub fn semantic_tokens(top: &Expand, src: &str) -> Vec<Token> {
+    let mut classifier = Classifier {
+        src,
+        pos: 0,
+        scopes: vec![vec![]],
+        visible: vec![(0, HashMap::new())],
+        declarations: HashMap::new(),
+    };
+    let _ = walk::scoped_nodes(top.iter(), &mut classifier);
+    let Classifier { mut visible, declarations, .. } = classifier;
+    visible.sort_by_key(|&(index, _)| index);
+
+    let before = |i: usize| src[..i].chars().next_back();
+    let after = |i: usize| src[i..].trim_start().chars().next();
+    words(src).into_iter()
+        .filter_map(|range @ (start, end)| {
+            let word = &src[start..end];
+            let kind = if word == "$" {
+                TokenKind::ResultHandle
+            } else if before(start) == Some(':')
+                && !before(start - 1).is_some_and(is_name_char)
+            {
+                TokenKind::Label
+            } else if before(start) == Some('.') {
+                let binder_start = src[..start - 1]
+                    .rfind(|ch| !is_name_char(ch))
+                    .map_or(0, |i| i + 1);
+                if &src[binder_start..start - 1] == "Builtin" {
+                    TokenKind::Builtin
+                } else {
+                    TokenKind::Bind
+                }
+            } else if word == "Builtin" && after(end) == Some('.') {
+                TokenKind::BuiltinNamespace
+            } else if after(end) == Some(':')
+                && src[..start].trim_end().ends_with('(')
+            {
+                TokenKind::ResultHandle
+            } else if let Some(&kind) = declarations.get(&range) {
+                return Some(Token { range, kind, declaration: true });
+            } else {
+                let i = visible.partition_point(|&(index, _)| index <= start);
+                *visible[i.max(1) - 1].1.get(word)?
+            };
+            Some(Token { range, kind, declaration: false })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 返回各个被区分的名称与其种类
+    #[track_caller]
+    fn tokens(src: &str) -> Vec<(&str, TokenKind, bool)> {
+        let mut meta = syntax::Meta::new();
+        meta.record_locations = true;
+        let top = parser::TopLevelParser::new().parse(&mut meta, src).unwrap();
+        semantic_tokens(&top, src).into_iter()
+            .map(|token| {
+                let (start, end) = token.range;
+                (&src[start..end], token.kind, token.declaration)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn semantic_tokens_test() {
+        use TokenKind::*;
+
+        let src = "const A = 1;\ntake B = A;\n\
+                   print A B c \"A\"; # A\n:lab\ngoto :lab;";
+        assert_eq!(tokens(src), [
+            ("A", Const, true),
+            ("B", Take, true),
+            ("A", Const, false),
+            ("A", Const, false),
+            ("B", Take, false),
+            ("lab", Label, false),
+            ("lab", Label, false),
+        ]);
+        let src = "{\n    const A = 1;\n    print A;\n}\nprint A;";
+        assert_eq!(tokens(src), [("A", Const, true), ("A", Const, false)]);
+    }
+
+    #[test]
+    fn handle_and_bind_tokens_test() {
+        use TokenKind::*;
+
+        let src = "print (x: set $ 1;);\nprint a.b;\nprint Builtin.Type[`x`];";
+        assert_eq!(tokens(src), [
+            ("x", ResultHandle, false),
+            ("$", ResultHandle, false),
+            ("b", Bind, false),
+            ("Builtin", BuiltinNamespace, false),
+            ("Type", Builtin, false),
+        ]);
+    }
+}
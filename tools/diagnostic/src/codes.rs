@@ -0,0 +1,155 @@
+//! 所有的诊断代码
+//!
+//! 代码一经发布便不应改变其含义, 废弃的代码也不应被复用,
+//! 错误以`E`开头, 警告以`W`开头, 编译期间的日志以`I`开头, lint以`L`开头,
+//! 其后的前两位数字表示其所在的阶段
+
+use std::fmt::{self, Display};
+
+use crate::Stage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Code {
+    pub name: &'static str,
+    pub stage: Stage,
+}
+impl Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+macro_rules! codes {
+    ( $(
+        $(#[$meta:meta])*
+        $id:ident = $name:literal in $stage:ident;
+    )* ) => {
+        $(
+            $(#[$meta])*
+            pub const $id: Code = Code { name: $name, stage: Stage::$stage };
+        )*
+
+        /// 所有的诊断代码
+        pub const ALL: &[Code] = &[$($id),*];
+    };
+}
+
+codes! {
+    /// 无法被识别的令牌
+    PARSE_INVALID_TOKEN = "E0101" in Parse;
+    /// 源码意外的结束
+    PARSE_UNRECOGNIZED_EOF = "E0102" in Parse;
+    /// 不应出现在此处的令牌
+    PARSE_UNRECOGNIZED_TOKEN = "E0103" in Parse;
+    /// 多余的令牌
+    PARSE_EXTRA_TOKEN = "E0104" in Parse;
+    PARSE_NOT_A_LITERAL_UINT = "E0105" in Parse;
+    /// 分支数量不匹配, 例如`select`的分支
+    PARSE_UNPAIR_BRANCHES = "E0106" in Parse;
+    /// op-expr的结果数量不匹配
+    PARSE_OP_EXPR_INVALID_RESULT = "E0107" in Parse;
+    PARSE_MULTIPLE_OP_EXPR = "E0108" in Parse;
+    /// 仅用于语言服务器的错误
+    PARSE_EMULATE = "E0109" in Parse;
+
+    IMPORT_NOT_FOUND = "E0111" in Parse;
+    IMPORT_IO = "E0112" in Parse;
+    IMPORT_CIRCULAR = "E0113" in Parse;
+
+    /// 最终未被展开的Cmper
+    CMPER_NOT_EXPANDED = "E0201" in Compile;
+    /// DExp返回句柄处使用了值不为Var的const
+    DEXP_RESULT_NOT_VAR = "E0202" in Compile;
+    REPEAT_COUNT_NOT_NUMBER = "E0203" in Compile;
+    REPEAT_COUNT_NEGATIVE = "E0204" in Compile;
+    REPEAT_COUNT_TOO_LARGE = "E0205" in Compile;
+    REPEAT_LIMIT_EXCEEDED = "E0206" in Compile;
+    /// gswitch的id不是一个合法的数字
+    INVALID_GSWITCH_ID = "E0207" in Compile;
+    /// 在DExp外部使用了仅在其内部可用的东西, 例如`$`与`setres`
+    OUT_OF_DEXP = "E0208" in Compile;
+    RECURSION_LIMIT_EXCEEDED = "E0209" in Compile;
+    /// 编译期间输出的错误日志, 例如`Builtin.Err!`
+    LOG_ERROR = "E0210" in Compile;
+    /// 内建函数的调用失败
+    BUILTIN_ERROR = "E0211" in Compile;
+    /// 没有输出错误的终止, 例如`Builtin.Exit!`
+    COMPILE_EXIT = "E0212" in Compile;
+    /// 编译期间输出的信息日志, 例如`Builtin.Info!`
+    LOG_INFO = "I0201" in Compile;
+
+    /// 从逻辑语言构建TagCode失败
+    TAG_CODE_PARSE = "E0301" in TagCode;
+    TAG_CODE_DUPLICATE_TAG = "E0302" in TagCode;
+    /// 启用或禁用了不存在的优化
+    TAG_CODE_UNKNOWN_PASS = "E0303" in TagCode;
+    /// 生成的指令数超过了处理器的限制, 并且设置了将其作为错误
+    TAG_CODE_INSTRUCTION_LIMIT_EXCEEDED = "E0304" in TagCode;
+    /// 无法在拆分标记处拆分程序
+    TAG_CODE_SPLIT = "E0305" in TagCode;
+    /// 生成的逻辑使用了目标版本中不可用的指令或操作符
+    TAG_CODE_UNSUPPORTED_BY_TARGET = "E0306" in TagCode;
+    /// 生成的指令数超过了处理器的限制
+    TAG_CODE_INSTRUCTION_LIMIT = "W0301" in TagCode;
+    /// 删除了无法到达的行
+    TAG_CODE_DEAD_CODE = "I0301" in TagCode;
+    /// 删除了对临时变量的无用赋值
+    TAG_CODE_DEAD_STORE = "I0302" in TagCode;
+    /// 进行了复制传播与临时变量合并
+    TAG_CODE_COPY_PROPAGATION = "I0303" in TagCode;
+    /// 折叠了操作数均为字面量的运算与比较
+    TAG_CODE_CONST_FOLD = "I0304" in TagCode;
+    /// 合并了生命周期不重叠的临时变量
+    TAG_CODE_REUSE_TEMPS = "I0305" in TagCode;
+    /// 进行了跳转的跟踪, 反转与删除
+    TAG_CODE_JUMP_OPTIMIZE = "I0306" in TagCode;
+
+    LOGIC_PARSE = "E0401" in ParseLogic;
+
+    PAREN_PARSE = "E0501" in ParseParen;
+
+    LOGIC_TO_BANG = "E0601" in LogicToBang;
+
+    LINT_USED_DOUBLE_UNDERLINE = "L0701" in Lint;
+    LINT_USED_RAW_ARGS = "L0702" in Lint;
+    LINT_ARGS_COUNT_NOT_MATCH = "L0703" in Lint;
+    LINT_ASSIGN_LITERAL = "L0704" in Lint;
+    LINT_SUSPECTED_CONSTANT = "L0705" in Lint;
+    LINT_SUSPECTED_VAR_CMD = "L0706" in Lint;
+    LINT_SUSPECTED_VALUE_CMD = "L0707" in Lint;
+    LINT_NEVER_USED = "L0708" in Lint;
+    LINT_NO_TARGET_JUMP = "L0709" in Lint;
+    LINT_INVALID_OPER = "L0710" in Lint;
+    /// 指令或操作符在所选的目标版本中不可用
+    LINT_UNSUPPORTED_BY_TARGET = "L0711" in Lint;
+
+    /// 无法构建出可执行的程序, 例如跳转到了不存在的标签
+    EMULATE_PROGRAM = "E0801" in Emulate;
+    /// 模拟器不支持的指令, 会被视作空指令
+    EMULATE_UNSUPPORTED = "W0801" in Emulate;
+
+    /// 测试中的断言失败
+    TEST_ASSERT_FAILED = "E0901" in Test;
+    /// 测试执行的指令数超过了限制, 通常是陷入了死循环
+    TEST_STEP_LIMIT = "E0902" in Test;
+    /// 测试中使用了模拟器不支持的指令
+    TEST_UNSUPPORTED = "W0901" in Test;
+
+    /// 无法读取的蓝图或处理器配置
+    SCHEMATIC_PARSE = "E1001" in Schematic;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn unique_test() {
+        let mut names = HashSet::new();
+        for code in ALL {
+            assert!(names.insert(code.name), "duplicate code {code}");
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! 编译器各个阶段共用的结构化诊断
+//!
+//! 每个诊断都带有一个[`Code`], 以及严重程度, 主要位置, 次要位置,
+//! 附注与修改建议, 既可以以人类可读的形式输出,
+//! 也可以通过[`Diagnostic::to_json`]输出给CI之类的工具解析
+
+pub mod codes;
+
+use std::{
+    fmt::{self, Display, Write},
+    rc::Rc,
+};
+
+pub use codes::Code;
+
+/// 诊断产生的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// 解析与导入Bang源码
+    Parse,
+    /// 编译Bang源码, 包括编译期间输出的日志
+    Compile,
+    /// 构建与编译TagCode
+    TagCode,
+    /// 解析逻辑语言
+    ParseLogic,
+    /// 解析MdtParenCode
+    ParseParen,
+    /// 将逻辑语言转换为Bang
+    LogicToBang,
+    /// 逻辑语言的lint
+    Lint,
+    /// 模拟执行逻辑语言
+    Emulate,
+    /// 在模拟器中运行测试块
+    Test,
+    /// 读取蓝图
+    Schematic,
+}
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Compile => "compile",
+            Stage::TagCode => "tag-code",
+            Stage::ParseLogic => "parse-logic",
+            Stage::ParseParen => "parse-paren",
+            Stage::LogicToBang => "logic-to-bang",
+            Stage::Lint => "lint",
+            Stage::Emulate => "emulate",
+            Stage::Test => "test",
+            Stage::Schematic => "schematic",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+/// 源码中的一段范围, 行列都从1开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: Option<Rc<str>>,
+    pub start: (u32, u32),
+    /// 对于单个位置与起始相同
+    pub end: (u32, u32),
+    pub label: Option<String>,
+}
+impl Span {
+    pub fn new(
+        file: Option<Rc<str>>,
+        start: (u32, u32),
+        end: (u32, u32),
+    ) -> Self {
+        Self { file, start, end, label: None }
+    }
+
+    pub fn point(file: Option<Rc<str>>, line: u32, column: u32) -> Self {
+        Self::new(file, (line, column), (line, column))
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+}
+impl Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+        write!(f, "{}:{}", self.start.0, self.start.1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: Code,
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Option<Span>,
+    pub secondary: Vec<Span>,
+    pub notes: Vec<String>,
+    pub suggestion: Option<String>,
+    /// 产生者自行渲染的人类可读文本, 为空时使用[`Display`]的默认格式
+    pub rendered: Option<String>,
+}
+impl Diagnostic {
+    pub fn new(
+        severity: Severity,
+        code: Code,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            code,
+            severity,
+            message: message.into(),
+            primary: None,
+            secondary: vec![],
+            notes: vec![],
+            suggestion: None,
+            rendered: None,
+        }
+    }
+
+    pub fn error(code: Code, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: Code, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    pub fn info(code: Code, message: impl Into<String>) -> Self {
+        Self::new(Severity::Info, code, message)
+    }
+
+    pub fn with_primary(mut self, span: impl Into<Option<Span>>) -> Self {
+        self.primary = span.into();
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span) -> Self {
+        self.secondary.push(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    pub fn with_rendered(mut self, rendered: impl Into<String>) -> Self {
+        self.rendered = Some(rendered.into());
+        self
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.code.stage
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn spans_mut(&mut self) -> impl Iterator<Item = &mut Span> {
+        self.primary.iter_mut().chain(&mut self.secondary)
+    }
+
+    /// 为没有文件的位置设置文件, 用于产生者并不知道其所处理的源的文件时
+    pub fn fill_file(&mut self, file: &Rc<str>) {
+        self.spans_mut()
+            .filter(|span| span.file.is_none())
+            .for_each(|span| span.file = Some(file.clone()));
+    }
+
+    /// 输出为单行的JSON对象
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push('{');
+        json_field(&mut out, "code", self.code.name);
+        out.push(',');
+        json_field(&mut out, "stage", self.stage().as_str());
+        out.push(',');
+        json_field(&mut out, "severity", self.severity.as_str());
+        out.push(',');
+        json_field(&mut out, "message", &self.message);
+        out.push_str(",\"primary\":");
+        match &self.primary {
+            Some(span) => json_span(&mut out, span),
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"secondary\":[");
+        for (i, span) in self.secondary.iter().enumerate() {
+            if i != 0 { out.push(',') }
+            json_span(&mut out, span);
+        }
+        out.push_str("],\"notes\":[");
+        for (i, note) in self.notes.iter().enumerate() {
+            if i != 0 { out.push(',') }
+            json_str(&mut out, note);
+        }
+        out.push(']');
+        out.push_str(",\"suggestion\":");
+        json_opt_str(&mut out, self.suggestion.as_deref());
+        out.push_str(",\"rendered\":");
+        json_str(&mut out, &self.to_string());
+        out.push('}');
+        out
+    }
+}
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(rendered) = &self.rendered {
+            return f.write_str(rendered);
+        }
+        f.write_str(&self.message)?;
+        for note in &self.notes {
+            write!(f, "\n{note}")?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n建议: {suggestion}")?;
+        }
+        Ok(())
+    }
+}
+
+fn json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if ch.is_control() => {
+                write!(out, "\\u{:04x}", u32::from(ch)).unwrap()
+            },
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+fn json_opt_str(out: &mut String, s: Option<&str>) {
+    match s {
+        Some(s) => json_str(out, s),
+        None => out.push_str("null"),
+    }
+}
+
+fn json_field(out: &mut String, name: &str, value: &str) {
+    json_str(out, name);
+    out.push(':');
+    json_str(out, value);
+}
+
+fn json_span(out: &mut String, span: &Span) {
+    out.push_str("{\"file\":");
+    json_opt_str(out, span.file.as_deref());
+    write!(out,
+        ",\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}",
+        span.start.0, span.start.1,
+        span.end.0, span.end.1,
+    ).unwrap();
+    out.push_str(",\"label\":");
+    json_opt_str(out, span.label.as_deref());
+    out.push('}');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_test() {
+        let diag = Diagnostic::error(codes::IMPORT_NOT_FOUND, "msg")
+            .with_note("a")
+            .with_note("b")
+            .with_suggestion("c");
+        assert_eq!(diag.to_string(), "msg\na\nb\n建议: c");
+        let diag = diag.with_rendered("x");
+        assert_eq!(diag.to_string(), "x");
+    }
+
+    #[test]
+    fn json_test() {
+        let diag = Diagnostic::warning(codes::LINT_NEVER_USED, "a\"b\\\n\x1b")
+            .with_primary(Span::new(Some("f".into()), (1, 2), (1, 4))
+                .with_label("x"))
+            .with_secondary(Span::point(None, 3, 1));
+        assert_eq!(diag.to_json(), concat!(
+            r#"{"code":"L0708","stage":"lint","severity":"warning","#,
+            r#""message":"a\"b\\\n\u001b","#,
+            r#""primary":{"file":"f","line":1,"column":2,"#,
+            r#""end_line":1,"end_column":4,"label":"x"},"#,
+            r#""secondary":[{"file":null,"line":3,"column":1,"#,
+            r#""end_line":3,"end_column":1,"label":null}],"#,
+            r#""notes":[],"suggestion":null,"#,
+            r#""rendered":"a\"b\\\n\u001b"}"#,
+        ));
+    }
+
+    #[test]
+    fn fill_file_test() {
+        let mut diag = Diagnostic::error(codes::PARSE_INVALID_TOKEN, "")
+            .with_primary(Span::point(None, 1, 1))
+            .with_secondary(Span::point(Some("a".into()), 1, 1));
+        diag.fill_file(&"b".into());
+        assert_eq!(diag.primary.unwrap().file.as_deref(), Some("b"));
+        assert_eq!(diag.secondary[0].file.as_deref(), Some("a"));
+    }
+}
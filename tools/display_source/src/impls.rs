@@ -411,6 +411,7 @@ impl DisplaySource for Expand {
     fn display_source(&self, meta: &mut DisplaySourceMeta) {
         self.0
             .iter()
+            .filter(|line| !line.is_location())
             .for_each(|line| {
                 line.display_source(meta);
                 meta.add_lf();
@@ -421,6 +422,7 @@ impl DisplaySource for InlineBlock {
     fn display_source(&self, meta: &mut DisplaySourceMeta) {
         self.0
             .iter()
+            .filter(|line| !line.is_location())
             .for_each(|line| {
                 line.display_source(meta);
                 meta.add_lf();
@@ -511,6 +513,7 @@ impl DisplaySource for LogicLine {
                 meta.push("}");
             },
             Self::Ignore => meta.push("{} # ignore line"),
+            Self::Location(_) => (),
             Self::SetArgs(args) => {
                 meta.do_insert_first("# ".into(), |meta| {
                     meta.push("setArgs");
@@ -0,0 +1,607 @@
+//! 逻辑语言的模拟执行器
+//!
+//! 可以在不进入游戏的情况下, 离线的运行编译得到的逻辑语言,
+//! 并且查看其输出的信息与最终的变量状态
+//!
+//! 仅模拟了处理器自身的行为, 例如`set` `op` `jump` `print` `printflush`,
+//! 以及内存元的`read` `write`, 其它与世界交互的指令会被视作空指令,
+//! 例如`sensor`不会改变其结果变量
+//!
+//! 游戏中执行`end`或执行到末尾后会回到首行继续执行, 而默认配置下会停止,
+//! 见[`Config::halt_on_end`]
+//!
+//! 执行是确定性的, 随机数使用固定的种子, 时间仅由`wait`推进
+//!
+//! 另外支持了仅用于测试的扩展指令`assert`与`assertprint`, 见[`Instr`]
+
+mod program;
+mod value;
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    rc::Rc,
+};
+
+pub use program::{Cond, Error, Instr, OpMethod, Operand, Program};
+pub use value::Value;
+
+/// 游戏中信息缓冲区的最大长度
+pub const MAX_TEXT_BUFFER: usize = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// 最多执行的指令数
+    pub max_steps: u64,
+    /// 是否在执行`end`或是执行到程序末尾时停止,
+    /// 否则会像游戏中一样从头开始继续执行
+    pub halt_on_end: bool,
+    /// `rand`所使用的随机数种子
+    pub seed: u64,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_steps: 100_000,
+            halt_on_end: true,
+            seed: 0x2545f4914f6cdd1d,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    /// 执行了`end`或是执行到了程序末尾
+    End,
+    /// 执行了`stop`
+    Stop,
+    /// 达到了[`Config::max_steps`]
+    StepLimit,
+    /// 断言失败, 见[`Emulator::assert_failure`]
+    AssertFailed,
+}
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Running => "running",
+            Status::End => "end",
+            Status::Stop => "stop",
+            Status::StepLimit => "step limit",
+            Status::AssertFailed => "assert failed",
+        })
+    }
+}
+
+/// 失败的断言
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertFailure {
+    /// 断言指令所携带的编号
+    pub id: Value,
+    /// 对于`assertprint`, 为期望的与实际的输出
+    pub print: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Emulator {
+    program: Program,
+    config: Config,
+    vars: Vec<Value>,
+    counter: usize,
+    steps: u64,
+    time: f64,
+    rng: u64,
+    print_buffer: String,
+    /// 信息板与其内容, 按照首次刷新的顺序
+    messages: Vec<(Rc<str>, String)>,
+    cells: HashMap<Rc<str>, Vec<f64>>,
+    status: Status,
+    assert_failure: Option<AssertFailure>,
+}
+impl Emulator {
+    pub fn new(program: Program, config: Config) -> Self {
+        Self {
+            vars: vec![Value::Null; program.vars().len()],
+            program,
+            rng: config.seed | 1,
+            config,
+            counter: 0,
+            steps: 0,
+            time: 0.0,
+            print_buffer: String::new(),
+            messages: vec![],
+            cells: HashMap::new(),
+            status: Status::Running,
+            assert_failure: None,
+        }
+    }
+
+    /// 执行到停止, 返回停止的原因
+    pub fn run(&mut self) -> Status {
+        while self.status == Status::Running {
+            self.step();
+        }
+        self.status
+    }
+
+    /// 执行一条指令, 已经停止时不产生任何效果
+    pub fn step(&mut self) -> Status {
+        if self.status != Status::Running {
+            return self.status;
+        }
+        if self.counter >= self.program.len() {
+            self.counter = 0;
+            if self.config.halt_on_end || self.program.is_empty() {
+                self.status = Status::End;
+                return self.status;
+            }
+        }
+        if self.steps >= self.config.max_steps {
+            self.status = Status::StepLimit;
+            return self.status;
+        }
+        self.steps += 1;
+        let instr = self.program.instrs()[self.counter].clone();
+        self.counter += 1;
+        self.exec(instr);
+        self.status
+    }
+
+    fn exec(&mut self, instr: Instr) {
+        match instr {
+            Instr::Set(result, value) => {
+                let value = self.get(&value);
+                self.set(&result, value);
+            },
+            Instr::Op(method, result, a, b) => {
+                let (a, b) = (self.get(&a), self.get(&b));
+                let value = method.eval(&a, &b, || self.rand());
+                self.set(&result, value);
+            },
+            Instr::Jump(target, cond, a, b) => {
+                if cond.test(&self.get(&a), &self.get(&b)) {
+                    self.counter = target;
+                }
+            },
+            Instr::Print(value) => {
+                let value = self.get(&value).to_string();
+                let rest = MAX_TEXT_BUFFER
+                    .saturating_sub(self.print_buffer.chars().count());
+                self.print_buffer.extend(value.chars().take(rest));
+            },
+            Instr::PrintFlush(target) => {
+                let text = std::mem::take(&mut self.print_buffer);
+                if let Some(name) = self.get(&target).as_obj() {
+                    match self.messages.iter_mut().find(|(n, _)| n == name) {
+                        Some((_, message)) => *message = text,
+                        None => self.messages.push((name.clone(), text)),
+                    }
+                }
+            },
+            Instr::Read(result, cell, addr) => {
+                let addr = self.get(&addr).num();
+                if let Some(&n) = self.cell_mut(&cell)
+                    .and_then(|cell| cell.get(addr as usize).filter(|_| addr >= 0.0))
+                {
+                    self.set(&result, Value::from_num(n));
+                }
+            },
+            Instr::Write(value, cell, addr) => {
+                let value = self.get(&value).num();
+                let addr = self.get(&addr).num();
+                if let Some(slot) = self.cell_mut(&cell)
+                    .and_then(|cell| cell.get_mut(addr as usize).filter(|_| addr >= 0.0))
+                {
+                    *slot = value;
+                }
+            },
+            Instr::Wait(sec) => {
+                self.time += self.get(&sec).num().max(0.0) * 1000.0;
+            },
+            Instr::End => {
+                self.counter = 0;
+                if self.config.halt_on_end {
+                    self.status = Status::End;
+                }
+            },
+            Instr::Stop => {
+                self.counter -= 1;
+                self.status = Status::Stop;
+            },
+            Instr::Assert(id) => {
+                self.fail(AssertFailure { id: self.get(&id), print: None });
+            },
+            Instr::AssertPrint(id, text) => {
+                let expected = self.get(&text).to_string();
+                let actual = std::mem::take(&mut self.print_buffer);
+                if expected != actual {
+                    let print = Some((expected, actual));
+                    self.fail(AssertFailure { id: self.get(&id), print });
+                }
+            },
+            Instr::Noop | Instr::Unsupported => (),
+        }
+    }
+
+    /// 停止在失败的断言处
+    fn fail(&mut self, failure: AssertFailure) {
+        self.counter -= 1;
+        self.status = Status::AssertFailed;
+        self.assert_failure = Some(failure);
+    }
+
+    fn get(&self, operand: &Operand) -> Value {
+        match operand {
+            Operand::Const(value) => value.clone(),
+            &Operand::Var(slot) => self.vars[slot].clone(),
+            Operand::Counter => Value::Num(self.counter as f64),
+            Operand::Time => Value::Num(self.time),
+            Operand::Tick => Value::Num(self.time * 60.0 / 1000.0),
+        }
+    }
+
+    /// 常量与内建变量不可被赋值, 除了`@counter`
+    fn set(&mut self, operand: &Operand, value: Value) {
+        match operand {
+            &Operand::Var(slot) => self.vars[slot] = value,
+            Operand::Counter => {
+                let n = value.num();
+                self.counter = if n >= 0.0 { n as usize } else { usize::MAX };
+            },
+            Operand::Const(_) | Operand::Time | Operand::Tick => (),
+        }
+    }
+
+    /// 获取内存, 其容量由名称决定, `bank`为512, 其它的为64
+    fn cell_mut(&mut self, cell: &Operand) -> Option<&mut Vec<f64>> {
+        let name = self.get(cell).as_obj()?.clone();
+        let size = if name.starts_with("bank") { 512 } else { 64 };
+        Some(self.cells.entry(name).or_insert_with(|| vec![0.0; size]))
+    }
+
+    /// xorshift64*, 返回`0..1`
+    fn rand(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let n = self.rng.wrapping_mul(0x2545f4914f6cdd1d);
+        (n >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn assert_failure(&self) -> Option<&AssertFailure> {
+        self.assert_failure.as_ref()
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn counter(&self) -> usize {
+        self.counter
+    }
+
+    /// 模拟的时间, 单位为毫秒
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// 还未被`printflush`的输出
+    pub fn print_buffer(&self) -> &str {
+        &self.print_buffer
+    }
+
+    pub fn messages(&self) -> &[(Rc<str>, String)] {
+        &self.messages
+    }
+
+    pub fn message(&self, name: &str) -> Option<&str> {
+        self.messages.iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, message)| &**message)
+    }
+
+    pub fn cell(&self, name: &str) -> Option<&[f64]> {
+        self.cells.get(name).map(Vec::as_slice)
+    }
+
+    pub fn var(&self, name: &str) -> Option<&Value> {
+        let slot = self.program.vars().iter().position(|var| &**var == name)?;
+        Some(&self.vars[slot])
+    }
+
+    /// 所有的变量与其值, 按照其在程序中首次出现的顺序
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.program.vars().iter()
+            .map(|var| &**var)
+            .zip(&self.vars)
+    }
+}
+/// 执行的报告, 包括状态, 各个信息板与缓冲区的输出, 非零的内存与变量
+impl Display for Emulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn indent(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+            text.lines().try_for_each(|line| writeln!(f, "    {line}"))
+        }
+        writeln!(f, "status: {}, steps: {}, time: {}ms",
+            self.status, self.steps, self.time)?;
+        for (name, message) in &self.messages {
+            writeln!(f, "{name}:")?;
+            indent(f, message)?;
+        }
+        if !self.print_buffer.is_empty() {
+            writeln!(f, "print buffer:")?;
+            indent(f, &self.print_buffer)?;
+        }
+        let mut cells = self.cells.iter().collect::<Vec<_>>();
+        cells.sort_by_key(|&(name, _)| name);
+        for (name, cell) in cells {
+            let values = cell.iter()
+                .enumerate()
+                .filter(|&(_, &n)| n != 0.0)
+                .map(|(i, &n)| format!("{i}: {}", Value::Num(n)))
+                .collect::<Vec<_>>();
+            if !values.is_empty() {
+                writeln!(f, "{name}: [{}]", values.join(", "))?;
+            }
+        }
+        writeln!(f, "vars:")?;
+        for (name, value) in self.vars() {
+            writeln!(f, "    {name} = {}", value.repr())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tag_code::logic_parser::parser;
+
+    use super::*;
+
+    fn run_with(src: &str, config: Config) -> Emulator {
+        let lines = parser::lines(src).unwrap();
+        let mut emulator = Emulator::new(Program::new(&lines).unwrap(), config);
+        emulator.run();
+        emulator
+    }
+
+    fn run(src: &str) -> Emulator {
+        run_with(src, Config::default())
+    }
+
+    #[test]
+    fn basic_test() {
+        let emu = run(r#"
+            set a 1
+            op add b a 2
+            op mul c b "x"
+            print "b="
+            print b
+            printflush message1
+            print c
+        "#);
+        assert_eq!(emu.status(), Status::End);
+        assert_eq!(emu.steps(), 7);
+        assert_eq!(emu.var("b"), Some(&Value::Num(3.0)));
+        assert_eq!(emu.var("c"), Some(&Value::Num(3.0)));
+        assert_eq!(emu.message("message1"), Some("b=3"));
+        assert_eq!(emu.print_buffer(), "3");
+    }
+
+    #[test]
+    fn jump_test() {
+        let emu = run(r#"
+            set i 0
+            loop:
+            op add i i 1
+            jump loop lessThan i 10
+            jump 5 always
+            set i -1
+            set j i
+        "#);
+        assert_eq!(emu.var("i"), Some(&Value::Num(10.0)));
+        assert_eq!(emu.var("j"), Some(&Value::Num(10.0)));
+        for (cond, a, b, expected) in [
+            ("equal", "1", "1.0000001", true),
+            ("notEqual", "null", "0", false),
+            ("lessThan", "1", "2", true),
+            ("lessThanEq", "2", "2", true),
+            ("greaterThan", "1", "2", false),
+            ("greaterThanEq", "2", "2", true),
+            ("strictEqual", "null", "0", false),
+            ("always", "0", "0", true),
+        ] {
+            let emu = run(&format!("jump 2 {cond} {a} {b}\nset x 1\nend"));
+            let expected = if expected { Value::Null } else { Value::Num(1.0) };
+            assert_eq!(emu.var("x"), Some(&expected), "{cond}");
+        }
+    }
+
+    #[test]
+    fn counter_test() {
+        let emu = run(r#"
+            set a @counter
+            op add @counter @counter 1
+            set b 1
+            set c 2
+        "#);
+        assert_eq!(emu.var("a"), Some(&Value::Num(1.0)));
+        assert_eq!(emu.var("b"), Some(&Value::Null));
+        assert_eq!(emu.var("c"), Some(&Value::Num(2.0)));
+    }
+
+    #[test]
+    fn op_test() {
+        let emu = run(r#"
+            op div a 1 0
+            op idiv b 7 2
+            op emod c -1 3
+            op shl d 1 4
+            op not e 0
+            op angle f 0 1
+            op angleDiff g 350 10
+            op len h 3 4
+            op sin i 90
+            op equal j "a" "a"
+            op strictEqual k 1 "a"
+            op round l 2.5
+            op log m -1
+            op noise n 1.5 2.5
+            op rand o 10
+        "#);
+        let num = |name| emu.var(name).unwrap().num();
+        assert_eq!(num("a"), 0.0);
+        assert_eq!(num("b"), 3.0);
+        assert_eq!(num("c"), 2.0);
+        assert_eq!(num("d"), 16.0);
+        assert_eq!(num("e"), -1.0);
+        assert_eq!(num("f"), 90.0);
+        assert_eq!(num("g"), 20.0);
+        assert_eq!(num("h"), 5.0);
+        assert!((num("i") - 1.0).abs() < 1e-9);
+        assert_eq!(num("j"), 1.0);
+        assert_eq!(num("k"), 0.0);
+        assert_eq!(num("l"), 3.0);
+        assert_eq!(num("m"), 0.0);
+        assert!((-1.0..=1.0).contains(&num("n")));
+        assert!((0.0..10.0).contains(&num("o")));
+        assert_eq!(run("op rand o 10").var("o"), emu.var("o"));
+        for method in OpMethod::ALL {
+            let src = format!("op {} x 2 3", method.name());
+            run(&src);
+        }
+    }
+
+    #[test]
+    fn memory_test() {
+        let emu = run(r#"
+            write 5 cell1 3
+            write 6 cell1 64
+            write 7 bank1 511
+            read a cell1 3
+            set b 9
+            read b cell1 -1
+            set c cell1
+            read d c 3
+        "#);
+        assert_eq!(emu.cell("cell1").unwrap()[3], 5.0);
+        assert_eq!(emu.cell("cell1").unwrap().len(), 64);
+        assert_eq!(emu.cell("bank1").unwrap()[511], 7.0);
+        assert_eq!(emu.var("a"), Some(&Value::Num(5.0)));
+        assert_eq!(emu.var("b"), Some(&Value::Num(9.0)));
+        assert_eq!(emu.var("d"), Some(&Value::Num(5.0)));
+    }
+
+    #[test]
+    fn status_test() {
+        let emu = run("set a 1\nstop\nset a 2");
+        assert_eq!(emu.status(), Status::Stop);
+        assert_eq!(emu.var("a"), Some(&Value::Num(1.0)));
+
+        let emu = run("op add a a 1\nend\nset a 0");
+        assert_eq!(emu.status(), Status::End);
+        assert_eq!(emu.var("a"), Some(&Value::Num(1.0)));
+
+        let config = Config { max_steps: 10, halt_on_end: false, ..Default::default() };
+        let emu = run_with("op add a a 1\nend\nset a 0", config);
+        assert_eq!(emu.status(), Status::StepLimit);
+        assert_eq!(emu.steps(), 10);
+        assert_eq!(emu.var("a"), Some(&Value::Num(5.0)));
+
+        let emu = run("wait 1.5\nset t @time");
+        assert_eq!(emu.var("t"), Some(&Value::Num(1500.0)));
+        assert_eq!(run("").status(), Status::End);
+    }
+
+    #[test]
+    fn end_test() {
+        let program = || {
+            let lines = parser::lines("set a 1\nend\nset a 2").unwrap();
+            Program::new(&lines).unwrap()
+        };
+
+        // 默认在`end`处停止, 不会回到首行
+        let mut emu = Emulator::new(program(), Config::default());
+        assert_eq!(emu.step(), Status::Running);
+        assert_eq!(emu.step(), Status::End);
+        assert_eq!(emu.step(), Status::End);
+        assert_eq!(emu.steps(), 2);
+        assert_eq!(emu.var("a"), Some(&Value::Num(1.0)));
+
+        // 不在`end`处停止时回到首行继续执行
+        let config = Config { halt_on_end: false, ..Default::default() };
+        let mut emu = Emulator::new(program(), config);
+        emu.step();
+        emu.step();
+        assert_eq!(emu.counter(), 0);
+        assert_eq!(emu.step(), Status::Running);
+        assert_eq!(emu.counter(), 1);
+        assert_eq!(emu.steps(), 3);
+        assert_eq!(emu.var("a"), Some(&Value::Num(1.0)));
+    }
+
+    #[test]
+    fn assert_test() {
+        let emu = run("print 1\nassertprint 0 \"1\"\njump 4 always\nassert 1\nset a 1");
+        assert_eq!(emu.status(), Status::End);
+        assert_eq!(emu.print_buffer(), "");
+        assert_eq!(emu.assert_failure(), None);
+
+        let emu = run("set a 2\nprint a\nassertprint 3 \"1\"\nset a 3");
+        assert_eq!(emu.status(), Status::AssertFailed);
+        assert_eq!(emu.counter(), 2);
+        assert_eq!(emu.var("a"), Some(&Value::Num(2.0)));
+        assert_eq!(emu.assert_failure(), Some(&AssertFailure {
+            id: Value::Num(3.0),
+            print: Some(("1".into(), "2".into())),
+        }));
+
+        let emu = run("assert 5\nset a 3");
+        assert_eq!(emu.status(), Status::AssertFailed);
+        assert_eq!(emu.assert_failure().unwrap().id, Value::Num(5.0));
+    }
+
+    #[test]
+    fn program_test() {
+        let lines = parser::lines("sensor a b @x\njump x always").unwrap();
+        let err = Program::new(&lines).unwrap_err();
+        assert_eq!(err.value, Error::UnknownLabel("x".into()));
+
+        let lines = parser::lines("op foo a b c").unwrap();
+        let err = Program::new(&lines).unwrap_err();
+        assert_eq!(err.value, Error::UnknownOp("foo".into()));
+
+        let lines = parser::lines("sensor a b @x\nset a 1").unwrap();
+        let program = Program::new(&lines).unwrap();
+        assert_eq!(program.unsupported().len(), 1);
+        assert_eq!(&*program.unsupported()[0].value, "sensor");
+        assert_eq!(program.vars().len(), 1);
+    }
+
+    #[test]
+    fn report_test() {
+        let emu = run(r#"
+            print "a\nb"
+            printflush message1
+            print 1.5
+            write 2 cell1 1
+            set x "s"
+            set y @copper
+        "#);
+        assert_eq!(emu.to_string(), "\
+            status: end, steps: 6, time: 0ms\n\
+            message1:\n    a\n    b\n\
+            print buffer:\n    1.5\n\
+            cell1: [1: 2]\n\
+            vars:\n    x = \"s\"\n    y = @copper\n\
+        ");
+    }
+}
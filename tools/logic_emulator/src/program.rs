@@ -0,0 +1,416 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    rc::Rc,
+};
+
+use tag_code::logic_parser::{Args, IdxBox, ParseLine, ParseLines};
+use var_utils::{AsVarType, Var, VarType};
+
+use crate::Value;
+
+macro_rules! named_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $( $variant:ident = $str:literal ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $( $variant ),*
+        }
+        impl $name {
+            pub const ALL: &[Self] = &[$( Self::$variant ),*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $( Self::$variant => $str ),*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<Self> {
+                Some(match name {
+                    $( $str => Self::$variant, )*
+                    _ => return None,
+                })
+            }
+        }
+    };
+}
+
+named_enum! {
+    /// `op`的所有运算
+    pub enum OpMethod {
+        Add = "add", Sub = "sub", Mul = "mul", Div = "div", Idiv = "idiv",
+        Mod = "mod", EMod = "emod", Pow = "pow",
+        Equal = "equal", NotEqual = "notEqual", Land = "land",
+        LessThan = "lessThan", LessThanEq = "lessThanEq",
+        GreaterThan = "greaterThan", GreaterThanEq = "greaterThanEq",
+        StrictEqual = "strictEqual",
+        Shl = "shl", Shr = "shr", UShr = "ushr",
+        Or = "or", And = "and", Xor = "xor", Not = "not",
+        Max = "max", Min = "min", Angle = "angle", AngleDiff = "angleDiff",
+        Len = "len", Noise = "noise", Abs = "abs", Sign = "sign",
+        Log = "log", LogN = "logn", Log10 = "log10",
+        Floor = "floor", Ceil = "ceil", Round = "round", Sqrt = "sqrt",
+        Rand = "rand",
+        Sin = "sin", Cos = "cos", Tan = "tan",
+        Asin = "asin", Acos = "acos", Atan = "atan",
+    }
+}
+impl OpMethod {
+    /// 计算结果, `rand`需要由调用者提供随机数
+    pub fn eval(&self, a: &Value, b: &Value, rand: impl FnOnce() -> f64) -> Value {
+        let (x, y) = (a.num(), b.num());
+        let int = |n: f64| n as i64;
+        Value::from_num(match self {
+            OpMethod::Add => x + y,
+            OpMethod::Sub => x - y,
+            OpMethod::Mul => x * y,
+            OpMethod::Div => x / y,
+            OpMethod::Idiv => (x / y).floor(),
+            OpMethod::Mod => x % y,
+            OpMethod::EMod => (x % y + y) % y,
+            OpMethod::Pow => x.powf(y),
+
+            OpMethod::Equal => return Value::from_bool(a.loose_eq(b)),
+            OpMethod::NotEqual => return Value::from_bool(!a.loose_eq(b)),
+            OpMethod::StrictEqual => return Value::from_bool(a.strict_eq(b)),
+            OpMethod::Land => return Value::from_bool(x != 0.0 && y != 0.0),
+            OpMethod::LessThan => return Value::from_bool(x < y),
+            OpMethod::LessThanEq => return Value::from_bool(x <= y),
+            OpMethod::GreaterThan => return Value::from_bool(x > y),
+            OpMethod::GreaterThanEq => return Value::from_bool(x >= y),
+
+            OpMethod::Shl => int(x).wrapping_shl(int(y) as u32) as f64,
+            OpMethod::Shr => int(x).wrapping_shr(int(y) as u32) as f64,
+            OpMethod::UShr => {
+                (int(x) as u64).wrapping_shr(int(y) as u32) as i64 as f64
+            },
+            OpMethod::Or => (int(x) | int(y)) as f64,
+            OpMethod::And => (int(x) & int(y)) as f64,
+            OpMethod::Xor => (int(x) ^ int(y)) as f64,
+            OpMethod::Not => !int(x) as f64,
+
+            OpMethod::Max => x.max(y),
+            OpMethod::Min => x.min(y),
+            OpMethod::Angle => y.atan2(x).to_degrees().rem_euclid(360.0),
+            OpMethod::AngleDiff => {
+                let diff = (x - y).rem_euclid(360.0);
+                diff.min(360.0 - diff)
+            },
+            OpMethod::Len => x.hypot(y),
+            OpMethod::Noise => noise(x, y),
+            OpMethod::Abs => x.abs(),
+            OpMethod::Sign if x == 0.0 => 0.0,
+            OpMethod::Sign => x.signum(),
+            OpMethod::Log => x.ln(),
+            OpMethod::LogN => x.ln() / y.ln(),
+            OpMethod::Log10 => x.log10(),
+            OpMethod::Floor => x.floor(),
+            OpMethod::Ceil => x.ceil(),
+            OpMethod::Round => (x + 0.5).floor(),
+            OpMethod::Sqrt => x.sqrt(),
+            OpMethod::Rand => rand() * x,
+            OpMethod::Sin => x.to_radians().sin(),
+            OpMethod::Cos => x.to_radians().cos(),
+            OpMethod::Tan => x.to_radians().tan(),
+            OpMethod::Asin => x.asin().to_degrees(),
+            OpMethod::Acos => x.acos().to_degrees(),
+            OpMethod::Atan => x.atan().to_degrees(),
+        })
+    }
+}
+
+named_enum! {
+    /// `jump`的所有条件
+    pub enum Cond {
+        Equal = "equal", NotEqual = "notEqual",
+        LessThan = "lessThan", LessThanEq = "lessThanEq",
+        GreaterThan = "greaterThan", GreaterThanEq = "greaterThanEq",
+        StrictEqual = "strictEqual", Always = "always",
+    }
+}
+impl Cond {
+    pub fn test(&self, a: &Value, b: &Value) -> bool {
+        let (x, y) = (a.num(), b.num());
+        match self {
+            Cond::Equal => a.loose_eq(b),
+            Cond::NotEqual => !a.loose_eq(b),
+            Cond::LessThan => x < y,
+            Cond::LessThanEq => x <= y,
+            Cond::GreaterThan => x > y,
+            Cond::GreaterThanEq => x >= y,
+            Cond::StrictEqual => a.strict_eq(b),
+            Cond::Always => true,
+        }
+    }
+}
+
+/// 确定性的二维值噪声, 范围为`-1..=1`
+///
+/// 与游戏中所使用的单形噪声并不相同, 仅保证相同的输入得到相同的输出
+fn noise(x: f64, y: f64) -> f64 {
+    fn hash(x: i64, y: i64) -> f64 {
+        let mut h = (x as u64).wrapping_mul(0x9e3779b97f4a7c15)
+            ^ (y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51afd7ed558ccd);
+        h ^= h >> 33;
+        (h >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (sx, sy) = (tx * tx * (3.0 - 2.0 * tx), ty * ty * (3.0 - 2.0 * ty));
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    lerp(
+        lerp(hash(ix, iy), hash(ix + 1, iy), sx),
+        lerp(hash(ix, iy + 1), hash(ix + 1, iy + 1), sx),
+        sy,
+    )
+}
+
+/// 指令的操作数
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Const(Value),
+    /// 变量在变量表中的索引
+    Var(usize),
+    Counter,
+    /// 模拟的时间, 单位为毫秒
+    Time,
+    Tick,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Set(Operand, Operand),
+    Op(OpMethod, Operand, Operand, Operand),
+    /// 目标为指令的索引
+    Jump(usize, Cond, Operand, Operand),
+    Print(Operand),
+    PrintFlush(Operand),
+    /// 结果, 内存, 地址
+    Read(Operand, Operand, Operand),
+    /// 值, 内存, 地址
+    Write(Operand, Operand, Operand),
+    Wait(Operand),
+    End,
+    Stop,
+    Noop,
+    /// 仅用于测试的扩展指令`assert id`, 执行到时断言失败
+    Assert(Operand),
+    /// 仅用于测试的扩展指令`assertprint id text`,
+    /// 比较并清空输出缓冲区, 不相同时断言失败
+    AssertPrint(Operand, Operand),
+    /// 不被支持的指令, 执行时不产生任何效果
+    Unsupported,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    UnknownLabel(String),
+    UnknownOp(String),
+    UnknownCond(String),
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownLabel(label) => write!(f, "未找到跳转的目标标记: {label}"),
+            Error::UnknownOp(op) => write!(f, "未知的op运算: {op}"),
+            Error::UnknownCond(cond) => write!(f, "未知的跳转条件: {cond}"),
+        }
+    }
+}
+impl std::error::Error for Error { }
+
+/// 带有固定名称的建筑链接的前缀, 例如`cell1`
+const LINK_PREFIXES: &[&str] = &[
+    "cell", "bank", "message", "switch", "display", "processor",
+];
+
+fn is_link(name: &str) -> bool {
+    LINK_PREFIXES.iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+    })
+}
+
+/// 从逻辑语言构建的, 可被模拟执行的程序
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    instrs: Vec<Instr>,
+    /// 每条指令在源码中的位置
+    indexs: Vec<usize>,
+    vars: Vec<Var>,
+    unsupported: Vec<IdxBox<Var>>,
+}
+impl Program {
+    pub fn new(lines: &ParseLines<'_>) -> Result<Self, IdxBox<Error>> {
+        let mut labels = HashMap::new();
+        let mut solid = 0;
+        for line in lines.iter() {
+            match &**line {
+                ParseLine::Label(label) => { labels.insert(&**label, solid); },
+                _ => solid += 1,
+            }
+        }
+
+        let mut builder = Builder::default();
+        let mut program = Program::default();
+        for line in lines.iter() {
+            let instr = match &**line {
+                ParseLine::Label(_) => continue,
+                ParseLine::Jump(target, args) => {
+                    let err = |e| line.new_value(e);
+                    let target = match labels.get(&**target) {
+                        Some(&index) => index,
+                        None => target.parse()
+                            .map_err(|_| err(Error::UnknownLabel(target.to_string())))?,
+                    };
+                    let cond = Cond::from_name(args.first())
+                        .ok_or_else(|| err(Error::UnknownCond(args.first().into())))?;
+                    let [a, b] = builder.operands(args, 1);
+                    Instr::Jump(target, cond, a, b)
+                },
+                ParseLine::Args(args) => {
+                    match builder.instr(args) {
+                        Ok(Some(instr)) => instr,
+                        Ok(None) => {
+                            program.unsupported.push(line.new_value(args[0].clone()));
+                            Instr::Unsupported
+                        },
+                        Err(e) => return Err(line.new_value(e)),
+                    }
+                },
+            };
+            program.instrs.push(instr);
+            program.indexs.push(line.index);
+        }
+        program.vars = builder.vars;
+        Ok(program)
+    }
+
+    pub fn instrs(&self) -> &[Instr] {
+        &self.instrs
+    }
+
+    /// 指令在源码中的位置
+    pub fn index_of(&self, instr: usize) -> Option<usize> {
+        self.indexs.get(instr).copied()
+    }
+
+    /// 变量表中的变量名, 按照首次出现的顺序
+    pub fn vars(&self) -> &[Var] {
+        &self.vars
+    }
+
+    /// 所有不被支持的指令的名称与位置, 它们在执行时不会产生任何效果
+    pub fn unsupported(&self) -> &[IdxBox<Var>] {
+        &self.unsupported
+    }
+
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Builder {
+    vars: Vec<Var>,
+    slots: HashMap<Var, usize>,
+}
+impl Builder {
+    fn operand(&mut self, arg: &Var) -> Operand {
+        let value = match &**arg {
+            "@counter" => return Operand::Counter,
+            "@time" => return Operand::Time,
+            "@tick" => return Operand::Tick,
+            "@pi" => Value::Num(std::f64::consts::PI),
+            "@e" => Value::Num(std::f64::consts::E),
+            "@degToRad" => Value::Num(1f64.to_radians()),
+            "@radToDeg" => Value::Num(1f64.to_degrees()),
+            "null" => Value::Null,
+            name if name.starts_with('@') || is_link(name) => {
+                Value::Obj(Rc::from(name))
+            },
+            name => match name.as_var_type() {
+                VarType::Number(n) => Value::Num(n),
+                VarType::String(s) => Value::Str(s.replace("\\n", "\n").into()),
+                VarType::Var(_) => {
+                    let len = self.vars.len();
+                    let slot = *self.slots.entry(arg.clone()).or_insert(len);
+                    if slot == len {
+                        self.vars.push(arg.clone());
+                    }
+                    return Operand::Var(slot);
+                },
+            },
+        };
+        Operand::Const(value)
+    }
+
+    /// 从`start`开始获取多个操作数, 缺少的参数视作`0`
+    fn operands<const N: usize>(&mut self, args: &Args<'_>, start: usize) -> [Operand; N] {
+        std::array::from_fn(|i| match args.get(start + i) {
+            Some(arg) => self.operand(arg),
+            None => Operand::Const(Value::Num(0.0)),
+        })
+    }
+
+    fn instr(&mut self, args: &Args<'_>) -> Result<Option<Instr>, Error> {
+        Ok(Some(match args.first() {
+            "set" => {
+                let [result, value] = self.operands(args, 1);
+                Instr::Set(result, value)
+            },
+            "op" => {
+                let method = args.get(1).map_or("", |s| s.as_str());
+                let method = OpMethod::from_name(method)
+                    .ok_or_else(|| Error::UnknownOp(method.into()))?;
+                let [result, a, b] = self.operands(args, 2);
+                Instr::Op(method, result, a, b)
+            },
+            "print" => {
+                let [value] = self.operands(args, 1);
+                Instr::Print(value)
+            },
+            "printflush" => {
+                let [target] = self.operands(args, 1);
+                Instr::PrintFlush(target)
+            },
+            "read" => {
+                let [result, cell, addr] = self.operands(args, 1);
+                Instr::Read(result, cell, addr)
+            },
+            "write" => {
+                let [value, cell, addr] = self.operands(args, 1);
+                Instr::Write(value, cell, addr)
+            },
+            "wait" => {
+                let [sec] = self.operands(args, 1);
+                Instr::Wait(sec)
+            },
+            // 跳转目标为`-1`的跳转不会被作为跳转解析
+            "jump" | "noop" => Instr::Noop,
+            "end" => Instr::End,
+            "stop" => Instr::Stop,
+            "assert" => {
+                let [id] = self.operands(args, 1);
+                Instr::Assert(id)
+            },
+            "assertprint" => {
+                let [id, text] = self.operands(args, 1);
+                Instr::AssertPrint(id, text)
+            },
+            _ => return Ok(None),
+        }))
+    }
+}
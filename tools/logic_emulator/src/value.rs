@@ -0,0 +1,113 @@
+use std::{
+    fmt::{self, Display},
+    rc::Rc,
+};
+
+/// 逻辑中的一个值, 除了数字外其它的都被视作对象
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Num(f64),
+    Str(Rc<str>),
+    /// 内容或建筑之类的对象, 例如`@copper`与`cell1`
+    Obj(Rc<str>),
+}
+impl Value {
+    /// 与游戏中相同, 非法的数字会被视作0
+    pub fn from_num(n: f64) -> Self {
+        Self::Num(if n.is_finite() { n } else { 0.0 })
+    }
+
+    pub fn from_bool(b: bool) -> Self {
+        Self::Num(if b { 1.0 } else { 0.0 })
+    }
+
+    /// 作为数字使用时的值, 非空的对象为1
+    pub fn num(&self) -> f64 {
+        match self {
+            Value::Null => 0.0,
+            Value::Num(n) => *n,
+            Value::Str(_) | Value::Obj(_) => 1.0,
+        }
+    }
+
+    pub fn is_obj(&self) -> bool {
+        !matches!(self, Value::Num(_))
+    }
+
+    pub fn as_obj(&self) -> Option<&Rc<str>> {
+        if let Self::Obj(v) = self {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
+    /// `equal`的比较, 数字之间允许微小的误差
+    pub fn loose_eq(&self, other: &Self) -> bool {
+        if self.is_obj() && other.is_obj() {
+            self == other
+        } else {
+            (self.num() - other.num()).abs() < 0.000001
+        }
+    }
+
+    /// `strictEqual`的比较, 需要类型相同
+    pub fn strict_eq(&self, other: &Self) -> bool {
+        self.is_obj() == other.is_obj() && self == other
+    }
+
+    /// 带有引号等区分的表示, 用于输出变量状态
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Str(s) => format!("{s:?}"),
+            Value::Obj(name) => name.to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+/// 与`print`所输出的相同
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Num(n) if (n - n.trunc()).abs() < 0.00001 => {
+                write!(f, "{}", *n as i64)
+            },
+            Value::Num(n) => write!(f, "{n}"),
+            Value::Str(s) => f.write_str(s),
+            Value::Obj(name) => {
+                f.write_str(name.strip_prefix('@').unwrap_or(name))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_test() {
+        assert_eq!(Value::Null.to_string(), "null");
+        assert_eq!(Value::Num(2.0).to_string(), "2");
+        assert_eq!(Value::Num(-0.000001).to_string(), "0");
+        assert_eq!(Value::Num(1.5).to_string(), "1.5");
+        assert_eq!(Value::Str("a b".into()).to_string(), "a b");
+        assert_eq!(Value::Obj("@copper".into()).to_string(), "copper");
+        assert_eq!(Value::from_num(f64::NAN), Value::Num(0.0));
+        assert_eq!(Value::from_num(f64::INFINITY), Value::Num(0.0));
+    }
+
+    #[test]
+    fn eq_test() {
+        assert!(Value::Num(1.0).loose_eq(&Value::Num(1.0000001)));
+        assert!(!Value::Num(1.0).strict_eq(&Value::Num(1.0000001)));
+        assert!(Value::Null.loose_eq(&Value::Num(0.0)));
+        assert!(!Value::Null.strict_eq(&Value::Num(0.0)));
+        assert!(Value::Str("a".into()).loose_eq(&Value::Num(1.0)));
+        assert!(!Value::Str("a".into()).loose_eq(&Value::Str("b".into())));
+        assert!(Value::Null.strict_eq(&Value::Null));
+    }
+}
@@ -0,0 +1,201 @@
+//! 对生成代码的常量折叠
+//!
+//! `Op::try_eval_const_num`仅在编译期可以确定值时进行折叠,
+//! 而take与const展开后仍然会留下许多`op add __0 1 2`这样的语句
+//!
+//! 运算使用与游戏中相同的双精度浮点数语义, 例如非法的结果会被视作0,
+//! 位运算在64位整数上进行
+
+use std::mem;
+
+use logic_emulator::{Cond, OpMethod, Value};
+use tag_code::{mdt_logic_split_unwraped, Jump, TagCodes, TagLine};
+use var_utils::{AsVarType, VarType};
+
+/// 仅使用第一个操作数的运算
+const UNARY_METHODS: &[OpMethod] = &[
+    OpMethod::Not, OpMethod::Abs, OpMethod::Sign,
+    OpMethod::Log, OpMethod::Log10,
+    OpMethod::Floor, OpMethod::Ceil, OpMethod::Round, OpMethod::Sqrt,
+    OpMethod::Sin, OpMethod::Cos, OpMethod::Tan,
+    OpMethod::Asin, OpMethod::Acos, OpMethod::Atan,
+];
+
+/// 不进行折叠的运算
+///
+/// - `noise`与`rand`的结果无法在编译期确定
+/// - `angle`, `angleDiff`与`len`在游戏中使用单精度浮点数计算
+const UNFOLDABLE_METHODS: &[OpMethod] = &[
+    OpMethod::Noise, OpMethod::Rand,
+    OpMethod::Angle, OpMethod::AngleDiff, OpMethod::Len,
+];
+
+/// 将操作数均为数字字面量的`op`改写为`set`,
+/// 条件为字面量比较的`jump`改写为`always`或删除, 返回折叠的行数
+///
+/// 被删除的跳转如果被跳转, 那么其标记会被保留给下一行,
+/// 依赖行号的跳转不会被删除, 见[`TagCodes::first_fixed_line`]
+pub fn fold_constants(tag_codes: &mut TagCodes) -> usize {
+    let mut folded = 0;
+    let fixed_line = tag_codes.first_fixed_line();
+    let lines = mem::take(tag_codes.lines_mut());
+    for (i, mut line) in lines.into_iter().enumerate() {
+        match &mut line {
+            TagLine::Line(line) => if let Some(new_line) = fold_op(line) {
+                **line = new_line;
+                folded += 1;
+            },
+            TagLine::Jump(jump) => match fold_jump(jump) {
+                Some(true) => {
+                    **jump = Jump::new_always(jump.0);
+                    folded += 1;
+                },
+                Some(false) if i < fixed_line => {
+                    folded += 1;
+                    if let Some(tag) = line.tag() {
+                        tag_codes.push(TagLine::TagDown(tag));
+                    }
+                    continue;
+                },
+                Some(false) | None => (),
+            },
+            TagLine::TagDown(_) => (),
+        }
+        tag_codes.push(line);
+    }
+    folded
+}
+
+/// 数字字面量的值, `null`虽然被视作数字但并不进行折叠
+fn literal(arg: &str) -> Option<Value> {
+    match arg.as_var_type() {
+        VarType::Number(n) if arg != "null" => Some(Value::Num(n)),
+        _ => None,
+    }
+}
+
+/// 折叠后的`set`语句
+fn fold_op(line: &str) -> Option<String> {
+    let ["op", method, result, a, ref rest @ ..] = mdt_logic_split_unwraped(line)[..] else {
+        return None;
+    };
+    let method = OpMethod::from_name(method)
+        .filter(|method| !UNFOLDABLE_METHODS.contains(method))?;
+    let a = literal(a)?;
+    let b = if UNARY_METHODS.contains(&method) {
+        Value::Num(0.0)
+    } else {
+        literal(rest.first()?)?
+    };
+    let value = method.eval(&a, &b, || unreachable!());
+    let Value::Num(num) = value else { unreachable!() };
+    Some(format!("set {result} {}", fmt_num(num)?))
+}
+
+/// 条件为字面量比较时, 条件是否成立
+fn fold_jump(jump: &Jump) -> Option<bool> {
+    let [cond, a, b] = mdt_logic_split_unwraped(&jump.1)[..] else {
+        return None;
+    };
+    let cond = Cond::from_name(cond).filter(|&cond| cond != Cond::Always)?;
+    Some(cond.test(&literal(a)?, &literal(b)?))
+}
+
+/// 格式化为可以被准确解析回相同值的数字字面量,
+/// 较大的整数使用十六进制以避免过长
+fn fmt_num(num: f64) -> Option<String> {
+    const SMALL: f64 = 999999.0;
+    let lit = if num.fract() == 0.0 && !(-SMALL..=SMALL).contains(&num) {
+        let int = num as i64;
+        if int.is_negative() {
+            format!("0x-{:X}", int.unsigned_abs())
+        } else {
+            format!("0x{int:X}")
+        }
+    } else {
+        num.to_string()
+    };
+    (lit.as_var_type() == VarType::Number(num)).then_some(lit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fold(src: &str) -> (usize, Vec<String>) {
+        let mut tag_codes = TagCodes::from_tag_lines(src);
+        let folded = fold_constants(&mut tag_codes);
+        (folded, tag_codes.compile().unwrap())
+    }
+
+    #[test]
+    fn fold_op_test() {
+        let (folded, lines) = fold("\
+            op add x 1 2\n\
+            op idiv x 7 2\n\
+            op emod x -7 3\n\
+            op div x 1 0\n\
+            op shl x 1 40\n\
+            op ushr x -1 60\n\
+            op not x 0 y\n\
+            op lessThan x 1 2\n\
+            op mul x 0x10 0b11\n\
+            op mul x 1000000 1000000\n\
+        ");
+        assert_eq!(folded, 10);
+        assert_eq!(lines, [
+            "set x 3",
+            "set x 3",
+            "set x 2",
+            "set x 0",
+            "set x 0x10000000000",
+            "set x 15",
+            "set x -1",
+            "set x 1",
+            "set x 48",
+            "set x 0xE8D4A51000",
+        ]);
+
+        // 带有变量, 结果不确定或精度与游戏中不同的运算不会被折叠
+        let (folded, _) = fold("\
+            op add x a 2\n\
+            op add x 1 null\n\
+            op add x 1 \"a\"\n\
+            op noise x 1 2\n\
+            op rand x 1 0\n\
+            op len x 3 4\n\
+        ");
+        assert_eq!(folded, 0);
+    }
+
+    #[test]
+    fn fold_jump_test() {
+        let (folded, lines) = fold("\
+            :a\n\
+            jump :b lessThan 1 2\n\
+            jump :a equal 1 2\n\
+            :b\n\
+            jump :a greaterThan 1 2\n\
+            jump :a strictEqual 2 2\n\
+            jump :a lessThan x 2\n\
+            print 1\n\
+        ");
+        assert_eq!(folded, 4);
+        assert_eq!(lines, [
+            "jump 1 always 0 0",
+            "jump 0 always 0 0",
+            "jump 0 lessThan x 2",
+            "print 1",
+        ]);
+
+        // @counter之后的行依赖相对的行偏移
+        let (folded, lines) = fold("\
+            op add @counter @counter i\n\
+            jump :a equal 1 2\n\
+            :a\n\
+            print 1\n\
+        ");
+        assert_eq!(folded, 0);
+        assert_eq!(lines.len(), 3);
+    }
+}
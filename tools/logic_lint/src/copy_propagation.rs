@@ -0,0 +1,297 @@
+//! 复制传播与临时变量合并
+//!
+//! op-expr与DExp的take常常产生`op add __0 a b`, `set x __0`这样的链,
+//! 合并后为`op add x a b`, 使语句数与手写的相同
+//!
+//! 仅在基本块内进行, 基本块的划分见[`Flow::blocks`]
+
+use std::collections::HashMap;
+
+use tag_code::{mdt_logic_split_unwraped, Tag, TagCodes, TagLine};
+use var_utils::AsVarType;
+
+use crate::flow::{is_temp_var, Flow, FlowLine};
+
+/// 将一个参数替换为另一个值
+#[derive(Debug)]
+struct Edit {
+    line: usize,
+    arg_idx: usize,
+    value: String,
+}
+
+/// 进行复制传播与临时变量合并, 返回传播与合并的次数
+///
+/// 传播后不再被读取的复制需要之后由[`eliminate_dead_stores`]删除
+///
+/// 如果有重复的`Tag`, 返回其行下标及重复`Tag`
+///
+/// [`eliminate_dead_stores`]: crate::dead_store::eliminate_dead_stores
+pub fn optimize_copies(tag_codes: &mut TagCodes) -> Result<usize, (usize, Tag)> {
+    let mut changes = 0;
+    loop {
+        tag_codes.build_tagdown()?;
+        let edits = propagate_copies(tag_codes);
+        let propagated = edits.len();
+        apply_edits(tag_codes, edits, &[]);
+
+        let (edits, removes) = coalesce_temps(tag_codes);
+        let coalesced = edits.len();
+        apply_edits(tag_codes, edits, &removes);
+
+        if propagated + coalesced == 0 { break }
+        changes += propagated + coalesced;
+    }
+    Ok(changes)
+}
+
+/// 复制的来源是否可以被传播, 环境变量例如`@time`的值会随时间改变, 所以不传播
+fn is_propagatable(value: &str) -> bool {
+    !value.as_var_type().is_var() || !value.starts_with('@')
+}
+
+/// `set a b`
+fn as_set<'a>(line: &FlowLine<'a>) -> Option<(&'a str, &'a str)> {
+    match line.args[..] {
+        ["set", to, from] if !line.dynamic => Some((to, from)),
+        _ => None,
+    }
+}
+
+/// 将读取`set __0 a`中`__0`的位置替换为`a`, 直到`__0`或`a`被重新赋值
+fn propagate_copies(tag_codes: &TagCodes) -> Vec<Edit> {
+    let flow = Flow::new(tag_codes);
+    let lines = flow.lines();
+    let mut edits = vec![];
+    for block in flow.blocks(tag_codes) {
+        let mut copies: HashMap<&str, &str> = HashMap::new();
+        for i in block {
+            let line = &lines[i];
+            let Some(useds) = line.useds.as_ref().filter(|_| !line.dynamic) else {
+                copies.clear();
+                continue;
+            };
+            for used in useds {
+                let Some(var) = used.as_read() else { continue };
+                if let Some(&from) = copies.get(var.value()) {
+                    edits.push(Edit { line: i, arg_idx: var.arg_idx(), value: from.into() });
+                }
+            }
+            let from = as_set(line).map(|(_, from)| {
+                copies.get(from).copied().unwrap_or(from)
+            });
+            for var in &line.assigns {
+                copies.retain(|to, from| to != var && from != var);
+            }
+            if let (Some((to, _)), Some(from)) = (as_set(line), from) {
+                if is_temp_var(to) && to != from && is_propagatable(from) {
+                    copies.insert(to, from);
+                }
+            }
+        }
+    }
+    edits
+}
+
+/// 将`op add __0 a b`, `set x __0`合并为`op add x a b`
+///
+/// 要求`__0`之后不再被读取, 并且两者之间没有使用`__0`与`x`
+///
+/// 每行在一轮中只参与一次合并, 依赖行号的行不会被删除,
+/// 见[`TagCodes::first_fixed_line`]
+fn coalesce_temps(tag_codes: &TagCodes) -> (Vec<Edit>, Vec<usize>) {
+    let flow = Flow::new(tag_codes);
+    let liveness = flow.liveness();
+    let lines = flow.lines();
+    let fixed_line = tag_codes.first_fixed_line();
+    let (mut edits, mut removes) = (vec![], vec![]);
+    let is_changed = |edits: &[Edit], removes: &[usize], i: usize| {
+        removes.contains(&i) || edits.iter().any(|edit| edit.line == i)
+    };
+    for block in flow.blocks(tag_codes) {
+        for set in block.start..block.end.min(fixed_line) {
+            let Some((to, tmp)) = as_set(&lines[set]) else { continue };
+            if !is_temp_var(tmp) || to == tmp || to.starts_with('@')
+                || liveness.is_live_out(set, tmp)
+                || is_changed(&edits, &removes, set)
+            {
+                continue;
+            }
+            for def in (block.start..set).rev() {
+                let line = &lines[def];
+                if !line.mentions(tmp) {
+                    if line.mentions(to) { break }
+                    continue;
+                }
+                let Some(useds) = line.useds.as_ref() else { break };
+                let mut assigns = useds.iter()
+                    .filter_map(|used| used.as_assign())
+                    .filter(|var| var.value() == tmp);
+                if let (Some(var), None) = (assigns.next(), assigns.next()) {
+                    if !line.dynamic
+                        && !is_changed(&edits, &removes, def)
+                        && !line.assigns.contains(&to)
+                        && !line.reads.iter().flatten().any(|&var| var == tmp)
+                    {
+                        edits.push(Edit { line: def, arg_idx: var.arg_idx(), value: to.into() });
+                        removes.push(set);
+                    }
+                }
+                break;
+            }
+        }
+    }
+    (edits, removes)
+}
+
+/// 应用替换, 并删除有序的`removes`中的行
+fn apply_edits(tag_codes: &mut TagCodes, mut edits: Vec<Edit>, removes: &[usize]) {
+    edits.sort_by_key(|edit| edit.line);
+    let lines = tag_codes.lines_mut();
+    for edits in edits.chunk_by(|a, b| a.line == b.line) {
+        match &mut lines[edits[0].line] {
+            TagLine::Jump(jump) => {
+                // 跳转的参数中目标与`jump`占据了两个位置
+                jump.1 = replace_args(&jump.1, edits, 2);
+            },
+            TagLine::Line(line) => {
+                **line = replace_args(line, edits, 0);
+            },
+            TagLine::TagDown(_) => unreachable!(),
+        }
+    }
+    let mut removes = removes.iter().peekable();
+    let mut i = 0;
+    lines.retain(|_| {
+        let keep = removes.next_if_eq(&&i).is_none();
+        i += 1;
+        keep
+    });
+}
+
+fn replace_args(line: &str, edits: &[Edit], offset: usize) -> String {
+    let mut args = mdt_logic_split_unwraped(line);
+    for edit in edits {
+        args[edit.arg_idx - offset] = &edit.value;
+    }
+    args.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dead_store::eliminate_dead_stores;
+
+    use super::*;
+
+    fn optimize(src: &str) -> (usize, Vec<String>) {
+        let mut tag_codes = TagCodes::from_tag_lines(src);
+        let changes = optimize_copies(&mut tag_codes).unwrap();
+        eliminate_dead_stores(&mut tag_codes).unwrap();
+        (changes, tag_codes.compile().unwrap())
+    }
+
+    #[test]
+    fn coalesce_test() {
+        let (changes, lines) = optimize("\
+            op add __0 a b\n\
+            set x __0\n\
+            read __1 cell1 x\n\
+            set y __1\n\
+            print y\n\
+        ");
+        assert_eq!(changes, 2);
+        assert_eq!(lines, ["op add x a b", "read y cell1 x", "print y"]);
+
+        // 两者之间使用了x
+        let (changes, lines) = optimize("\
+            op add __0 x 1\n\
+            print x\n\
+            set x __0\n\
+        ");
+        assert_eq!(changes, 0);
+        assert_eq!(lines.len(), 3);
+
+        // 读取自身的语句可以合并
+        let (_, lines) = optimize("\
+            op add __0 x 1\n\
+            set x __0\n\
+        ");
+        assert_eq!(lines, ["op add x x 1"]);
+
+        // 之后仍然被读取
+        let (changes, _) = optimize("\
+            op add __0 a b\n\
+            set x __0\n\
+            print __0\n\
+        ");
+        assert_eq!(changes, 0);
+
+        // 跨越了基本块
+        let (changes, lines) = optimize("\
+            op add __0 a b\n\
+            :a\n\
+            set x __0\n\
+            jump :a lessThan x 3\n\
+        ");
+        assert_eq!(changes, 0);
+        assert_eq!(lines.len(), 3);
+
+        // 被删除的复制同时是另一个合并的赋值
+        let (_, lines) = optimize("\
+            op add __0 a b\n\
+            set __1 __0\n\
+            set __0 5\n\
+            set x __1\n\
+            print x\n\
+            print __0\n\
+        ");
+        assert_eq!(lines, ["op add x a b", "print x", "print 5"]);
+
+        // @counter之后的行依赖相对的行偏移
+        let (changes, lines) = optimize("\
+            op add @counter @counter i\n\
+            op add __0 a b\n\
+            set x __0\n\
+            print x\n\
+        ");
+        assert_eq!(changes, 0);
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn propagate_test() {
+        let (changes, lines) = optimize("\
+            :a\n\
+            set __0 a\n\
+            op add x __0 1\n\
+            jump :a lessThan __0 x\n\
+        ");
+        assert_eq!(changes, 2);
+        assert_eq!(lines, ["op add x a 1", "jump 0 lessThan a x"]);
+
+        // 来源在使用前被重新赋值
+        let (changes, lines) = optimize("\
+            set __0 a\n\
+            set a 2\n\
+            print __0\n\
+        ");
+        assert_eq!(changes, 0);
+        assert_eq!(lines.len(), 3);
+
+        // 值会随时间改变的环境变量
+        let (changes, _) = optimize("\
+            set __0 @time\n\
+            wait 1\n\
+            print __0\n\
+        ");
+        assert_eq!(changes, 0);
+
+        let (_, lines) = optimize("\
+            set __0 \"a b\"\n\
+            set __1 __0\n\
+            print __1\n\
+            print @time\n\
+        ");
+        assert_eq!(lines, ["print \"a b\"", "print @time"]);
+    }
+}
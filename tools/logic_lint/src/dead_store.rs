@@ -0,0 +1,180 @@
+//! 删除对临时变量的无用赋值
+//!
+//! 例如DExp展开后留下的`set __12 x`, 如果`__12`之后不会被读取,
+//! 那么这条语句是可以被删除的
+
+use std::mem;
+
+use tag_code::{Tag, TagCodes, TagLine};
+
+use crate::flow::{is_temp_var, Flow};
+
+/// 除了对结果的赋值之外没有其它作用的语句, 未录入的语句不会被删除
+const PURE_CMDS: &[&str] = &[
+    "set", "op", "lookup", "packcolor", "unpackcolor", "select",
+    "read", "sensor", "getlink", "radar", "uradar", "ulocate",
+    "getblock", "fetch", "getflag", "weathersense",
+];
+
+/// 删除所有结果都是之后不会被读取的临时变量的语句, 返回删除的行数
+///
+/// 使用了`@counter`的语句之后的执行无法分析, 所以此时所有变量都被视作会被读取,
+/// 未录入的语句也被视作读取了所有变量
+///
+/// 依赖行号的行不会被删除, 见[`TagCodes::first_fixed_line`]
+///
+/// 被删除的行如果被跳转, 那么其标记会被保留给下一行
+///
+/// 如果有重复的`Tag`, 返回其行下标及重复`Tag`
+pub fn eliminate_dead_stores(tag_codes: &mut TagCodes) -> Result<usize, (usize, Tag)> {
+    let mut removed = 0;
+    loop {
+        tag_codes.build_tagdown()?;
+        let dead = dead_stores(tag_codes);
+        if dead.is_empty() { break }
+        removed += dead.len();
+
+        let lines = mem::take(tag_codes.lines_mut());
+        let mut dead = dead.into_iter().peekable();
+        for (i, line) in lines.into_iter().enumerate() {
+            if dead.next_if_eq(&i).is_none() {
+                tag_codes.push(line);
+            } else if let Some(tag) = line.tag() {
+                tag_codes.push(TagLine::TagDown(tag));
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// 有序的无用赋值的行
+fn dead_stores(tag_codes: &TagCodes) -> Vec<usize> {
+    let flow = Flow::new(tag_codes);
+    let liveness = flow.liveness();
+    flow.lines()[..tag_codes.first_fixed_line()].iter()
+        .enumerate()
+        .filter(|(i, line)| {
+            !line.dynamic
+                && line.reads.is_some()
+                && PURE_CMDS.contains(&line.cmd())
+                && !line.assigns.is_empty()
+                && line.assigns.iter().all(|var| {
+                    is_temp_var(var) && !liveness.is_live_out(*i, var)
+                })
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eliminate(src: &str) -> (usize, Vec<String>) {
+        let mut tag_codes = TagCodes::from_tag_lines(src);
+        let removed = eliminate_dead_stores(&mut tag_codes).unwrap();
+        (removed, tag_codes.compile().unwrap())
+    }
+
+    #[test]
+    fn eliminate_dead_stores_test() {
+        let (removed, lines) = eliminate("\
+            set __0 a\n\
+            op add __1 __0 1\n\
+            set x __0\n\
+            print x\n\
+        ");
+        assert_eq!(removed, 1);
+        assert_eq!(lines, ["set __0 a", "set x __0", "print x"]);
+
+        // 被删除的语句使另一个临时变量不再被读取
+        let (removed, lines) = eliminate("\
+            set __0 a\n\
+            set __1 __0\n\
+            set y 2\n\
+        ");
+        assert_eq!(removed, 2);
+        assert_eq!(lines, ["set y 2"]);
+
+        // 用户的变量与有副作用的语句不会被删除
+        let (removed, _) = eliminate("\
+            set x 1\n\
+            getlink __0 0\n\
+            spawn @dagger 1 2 3 @sharded __1\n\
+            ucontrol within 1 2 3 __2\n\
+        ");
+        assert_eq!(removed, 1);
+    }
+
+    #[test]
+    fn eliminate_dead_stores_flow_test() {
+        // 循环中被读取的值
+        let (removed, lines) = eliminate("\
+            set __0 0\n\
+            :a\n\
+            print __0\n\
+            op add __0 __0 1\n\
+            jump :a lessThan __0 3\n\
+        ");
+        assert_eq!(removed, 0);
+        assert_eq!(lines.len(), 4);
+
+        // 变量会在end后被保留
+        let (removed, _) = eliminate("\
+            print __0\n\
+            set __0 1\n\
+            end\n\
+        ");
+        assert_eq!(removed, 0);
+
+        // 被删除的行的标记会被保留
+        let (removed, lines) = eliminate("\
+            jump :a equal x 1\n\
+            print 1\n\
+            :a\n\
+            set __0 2\n\
+            print 2\n\
+            stop\n\
+        ");
+        assert_eq!(removed, 1);
+        assert_eq!(lines, ["jump 2 equal x 1", "print 1", "print 2", "stop"]);
+
+        // 使用了@counter与未录入的语句视作读取了所有变量
+        let (removed, _) = eliminate("\
+            set __0 1\n\
+            set @counter x\n\
+            stop\n\
+        ");
+        assert_eq!(removed, 0);
+        let (removed, _) = eliminate("\
+            set __0 1\n\
+            foo bar\n\
+            stop\n\
+        ");
+        assert_eq!(removed, 0);
+
+        // @counter之后的行依赖相对的行偏移
+        let (removed, lines) = eliminate("\
+            set i 1\n\
+            op add @counter @counter i\n\
+            set __0 10\n\
+            set __0 20\n\
+            print \"a\"\n\
+            print \"b\"\n\
+            printflush message1\n\
+            stop\n\
+        ");
+        assert_eq!(removed, 0);
+        assert_eq!(lines.len(), 8);
+    }
+
+    #[test]
+    fn is_temp_var_test() {
+        for var in ["__0", "__12", "__0a9", "___3"] {
+            assert!(is_temp_var(var), "{var}");
+        }
+        for var in ["__", "___", "a", "__a", "_0", "____0", "__0.5", "__x__0"] {
+            assert!(!is_temp_var(var), "{var}");
+        }
+    }
+}
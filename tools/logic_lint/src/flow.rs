@@ -0,0 +1,261 @@
+//! 对[`TagCodes`]的控制流与临时变量的活跃性分析, 供各个优化使用
+//!
+//! 只有编译器生成的临时变量会被分析, 用户的变量总是被视作会被使用的
+
+use std::{collections::HashMap, mem, ops::Range};
+
+use tag_code::{mdt_logic_split_unwraped, TagCodes, TagLine};
+
+use crate::{lints::{get_known_useds, VarUsed}, Line};
+
+/// 是否是编译器生成的临时变量, 例如`__0`, `__0a`与`___0`
+pub fn is_temp_var(name: &str) -> bool {
+    let Some(rest) = name.strip_prefix("__") else { return false };
+    let rest = rest.strip_prefix('_').unwrap_or(rest);
+    rest.starts_with(|ch: char| ch.is_ascii_digit())
+        && rest.chars().all(|ch| ch.is_ascii_alphanumeric())
+}
+
+#[derive(Debug)]
+pub struct FlowLine<'a> {
+    /// 语句的参数, 跳转语句的目标会被替换为`_`
+    pub args: Vec<&'a str>,
+    /// 之后可能执行的行
+    pub succs: Vec<usize>,
+    /// 对参数的使用, 为[`None`]时表示未录入的语句
+    pub useds: Option<Vec<VarUsed<'a>>>,
+    /// 读取的变量, 为[`None`]时表示未录入的语句, 视作可能读取任何变量
+    pub reads: Option<Vec<&'a str>>,
+    pub assigns: Vec<&'a str>,
+    /// 使用了`@counter`, 之后可能执行任何行
+    pub dynamic: bool,
+}
+impl<'a> FlowLine<'a> {
+    fn new(i: usize, args: Vec<&'a str>, succs: Vec<usize>) -> Self {
+        let dynamic = args.contains(&"@counter");
+        let useds = get_known_useds(&Line::from_line(i, &args));
+        let (reads, assigns) = match &useds {
+            Some(useds) => {
+                let reads = useds.iter()
+                    .filter_map(|used| used.as_read().map(|var| var.value()))
+                    .collect();
+                let assigns = useds.iter()
+                    .filter_map(|used| used.as_assign().map(|var| var.value()))
+                    .collect();
+                (Some(reads), assigns)
+            },
+            None => (None, vec![]),
+        };
+        Self { args, succs, useds, reads, assigns, dynamic }
+    }
+
+    pub fn cmd(&self) -> &str {
+        self.args.first().copied().unwrap_or_default()
+    }
+
+    /// 是否读取或写入了变量, 未录入的语句总是返回`true`
+    pub fn mentions(&self, var: &str) -> bool {
+        match &self.reads {
+            Some(reads) => reads.contains(&var) || self.assigns.contains(&var),
+            None => true,
+        }
+    }
+}
+
+/// 每行的控制流信息, 行号与[`TagCodes`]中的下标相同
+#[derive(Debug)]
+pub struct Flow<'a> {
+    lines: Vec<FlowLine<'a>>,
+}
+impl<'a> Flow<'a> {
+    /// 需要已经使用[`TagCodes::build_tagdown`]构建
+    ///
+    /// 顺序执行到末尾与`end`之后会回到首行继续执行, 而变量会被保留
+    pub fn new(tag_codes: &'a TagCodes) -> Self {
+        let lines = tag_codes.lines();
+        let tag_lines = lines.iter()
+            .enumerate()
+            .filter_map(|(i, line)| Some((line.tag()?, i)))
+            .collect::<HashMap<_, _>>();
+        let next = |i: usize| (i + 1) % lines.len();
+        let lines = lines.iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let (args, succs) = match line {
+                    TagLine::Jump(jump) => {
+                        let mut args = vec!["jump", "_"];
+                        args.extend(mdt_logic_split_unwraped(&jump.1));
+                        let mut succs = Vec::from_iter(tag_lines.get(&jump.0).copied());
+                        if !jump.is_always_jump() {
+                            succs.push(next(i));
+                        }
+                        (args, succs)
+                    },
+                    TagLine::Line(line) => {
+                        let args = mdt_logic_split_unwraped(line);
+                        let succs = match args[..] {
+                            ["end", ..] => vec![0],
+                            ["stop", ..] => vec![],
+                            _ => vec![next(i)],
+                        };
+                        (args, succs)
+                    },
+                    TagLine::TagDown(_) => panic!("TagDown in flow, need build_tagdown"),
+                };
+                FlowLine::new(i, args, succs)
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub fn lines(&self) -> &[FlowLine<'a>] {
+        &self.lines
+    }
+
+    /// 将行划分为基本块, 返回每个块的行范围
+    ///
+    /// 带有标记的行, 也就是可能被跳转的行会开始一个新块,
+    /// 跳转, `end`, `stop`, 使用了`@counter`与未录入的语句会结束当前块
+    pub fn blocks(&self, tag_codes: &TagCodes) -> Vec<Range<usize>> {
+        let mut blocks = vec![];
+        let mut start = 0;
+        for (i, line) in self.lines.iter().enumerate() {
+            if i != start && tag_codes.lines()[i].tag().is_some() {
+                blocks.push(start..i);
+                start = i;
+            }
+            let is_end = line.dynamic
+                || line.reads.is_none()
+                || matches!(line.cmd(), "jump" | "end" | "stop");
+            if is_end {
+                blocks.push(start..i+1);
+                start = i+1;
+            }
+        }
+        if start != self.lines.len() {
+            blocks.push(start..self.lines.len());
+        }
+        blocks
+    }
+
+    /// 分析每行之后仍然会被读取的临时变量
+    pub fn liveness(&self) -> Liveness<'a> {
+        let mut vars = HashMap::new();
+        for line in &self.lines {
+            let reads = line.reads.iter().flatten();
+            for &var in reads.chain(&line.assigns) {
+                if is_temp_var(var) {
+                    let id = vars.len();
+                    vars.entry(var).or_insert(id);
+                }
+            }
+        }
+        let all = BitSet::full(vars.len());
+        let empty = BitSet::new(vars.len());
+        let gen_kill = self.lines.iter()
+            .map(|line| {
+                let (mut gen, mut kill) = (empty.clone(), empty.clone());
+                for var in line.reads.iter().flatten() {
+                    if let Some(&id) = vars.get(var) { gen.insert(id) }
+                }
+                for var in &line.assigns {
+                    if let Some(&id) = vars.get(var) { kill.insert(id) }
+                }
+                (gen, kill)
+            })
+            .collect::<Vec<_>>();
+
+        let mut live_out = vec![empty.clone(); self.lines.len()];
+        let mut live_in = vec![empty; self.lines.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, line) in self.lines.iter().enumerate().rev() {
+                let mut out = if line.dynamic { all.clone() } else {
+                    let mut out = BitSet::new(vars.len());
+                    for &succ in &line.succs {
+                        out.union_with(&live_in[succ]);
+                    }
+                    out
+                };
+                let mut in_ = if line.reads.is_none() { all.clone() } else {
+                    let (gen, kill) = &gen_kill[i];
+                    let mut in_ = out.clone();
+                    in_.difference_with(kill);
+                    in_.union_with(gen);
+                    in_
+                };
+                if out != live_out[i] {
+                    mem::swap(&mut out, &mut live_out[i]);
+                    changed = true;
+                }
+                if in_ != live_in[i] {
+                    mem::swap(&mut in_, &mut live_in[i]);
+                    changed = true;
+                }
+            }
+        }
+        Liveness { vars, live_in, live_out }
+    }
+}
+
+/// 每行之后仍然会被读取的临时变量
+#[derive(Debug)]
+pub struct Liveness<'a> {
+    vars: HashMap<&'a str, usize>,
+    live_in: Vec<BitSet>,
+    live_out: Vec<BitSet>,
+}
+impl<'a> Liveness<'a> {
+    /// 在执行该行之前会被之后读取的临时变量
+    pub fn live_in(&self, line: usize) -> impl Iterator<Item = &'a str> + '_ {
+        self.vars.iter()
+            .filter(move |&(_, &id)| self.live_in[line].contains(id))
+            .map(|(&var, _)| var)
+    }
+
+    /// 在执行该行之后仍然会被读取的临时变量
+    pub fn live_out(&self, line: usize) -> impl Iterator<Item = &'a str> + '_ {
+        self.vars.iter()
+            .filter(move |&(_, &id)| self.live_out[line].contains(id))
+            .map(|(&var, _)| var)
+    }
+
+    /// 临时变量在执行完该行之后是否仍然会被读取, 非临时变量总是返回`true`
+    pub fn is_live_out(&self, line: usize, var: &str) -> bool {
+        match self.vars.get(var) {
+            Some(&id) => self.live_out[line].contains(id),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BitSet(Vec<u64>);
+impl BitSet {
+    fn new(len: usize) -> Self {
+        Self(vec![0; len.div_ceil(64)])
+    }
+
+    fn full(len: usize) -> Self {
+        let mut set = Self::new(len);
+        (0..len).for_each(|i| set.insert(i));
+        set
+    }
+
+    fn insert(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64)
+    }
+
+    fn contains(&self, i: usize) -> bool {
+        self.0[i / 64] & 1 << (i % 64) != 0
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        self.0.iter_mut().zip(&other.0).for_each(|(a, b)| *a |= b)
+    }
+
+    fn difference_with(&mut self, other: &Self) {
+        self.0.iter_mut().zip(&other.0).for_each(|(a, b)| *a &= !b)
+    }
+}
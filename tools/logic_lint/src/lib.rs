@@ -1,11 +1,19 @@
 #![allow(clippy::double_must_use)]
+pub mod const_fold;
+pub mod copy_propagation;
+pub mod dead_store;
+pub mod flow;
 pub mod lints;
+pub mod reg_alloc;
+pub mod split;
 
 use core::fmt;
 use std::{borrow::Cow, collections::HashSet, ops::Deref};
 
 use lints::get_useds;
+use diagnostic::Diagnostic;
 use tag_code::mdt_logic_split_unwraped;
+use var_utils::Target;
 
 use crate::lints::{Lint, ShowLint};
 
@@ -15,6 +23,8 @@ const LIGHT_ARGS_END: &str = "\x1b[27m";
 #[derive(Debug)]
 pub struct Line<'a> {
     args: Vec<Var<'a>>,
+    /// 在源码中的行号与该行的文本, 行号从0开始
+    source: Option<(usize, &'a str)>,
 }
 impl<'a> Line<'a> {
     pub fn from_line(lineno: usize, logic_args: &[&'a str]) -> Self {
@@ -24,7 +34,19 @@ impl<'a> Line<'a> {
             .map(|(i, arg)| Var::new(lineno, i, arg))
             .collect();
 
-        Self { args }
+        Self { args, source: None }
+    }
+
+    /// 参数在源码中的行与起止列, 都从1开始
+    pub fn arg_location(&self, arg_idx: usize) -> Option<(u32, u32, u32)> {
+        let (src_lineno, text) = self.source?;
+        let arg = self.args.get(arg_idx)?.value();
+        let offset = (arg.as_ptr() as usize)
+            .checked_sub(text.as_ptr() as usize)
+            .filter(|&offset| offset + arg.len() <= text.len())?;
+        let column = text[..offset].chars().count() as u32 + 1;
+        let end = column + arg.chars().count() as u32;
+        Some((src_lineno as u32 + 1, column, end))
     }
 
     pub fn hint_args(&self, hints: &[usize]) -> Vec<Cow<'_, str>> {
@@ -71,6 +93,7 @@ pub struct Source<'a> {
     /// 如果一行同时对一个量进行了读写,
     /// 那么它将不会出现在这个表中
     readonly_used_vars: HashSet<&'a str>,
+    target: Target,
 }
 impl<'a> Source<'a> {
     #[allow(clippy::should_implement_trait)]
@@ -80,17 +103,23 @@ impl<'a> Source<'a> {
         ][..];
         let mut lineno = 0;
         let lines = s.lines()
-            .map(str::trim_start)
-            .filter(|line| !(line.starts_with('#') || line.is_empty()))
-            .map(mdt_logic_split_unwraped)
-            .filter_map(|args| {
+            .enumerate()
+            .filter(|(_, line)| {
+                let line = line.trim_start();
+                !(line.starts_with('#') || line.is_empty())
+            })
+            .map(|(src_lineno, line)| {
+                (src_lineno, line, mdt_logic_split_unwraped(line))
+            })
+            .filter_map(|(src_lineno, text, args)| {
                 assert_ne!(args.len(), 0,
                     "line {} args count by zero", lineno);
                 match args[..] {
                     [fst] if fst.ends_with(':') => return None,
                     _ => (),
                 }
-                let line = Line::from_line(lineno, &args);
+                let mut line = Line::from_line(lineno, &args);
+                line.source = Some((src_lineno, text));
                 lineno += 1;
                 Some(line)
             })
@@ -119,9 +148,20 @@ impl<'a> Source<'a> {
         Self {
             lines,
             readonly_used_vars,
+            target: Target::default(),
         }
     }
 
+    /// 设置检查时所面向的游戏版本, 默认为[`Target::World`]
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
     /// 返回指定行周围的行, 而不包括指定行
     pub fn view_lines(
         &self,
@@ -148,16 +188,25 @@ impl<'a> Source<'a> {
         self.lines.as_ref()
     }
 
-    pub fn show_lints(&self) {
+    /// 格式化一个lint, 与[`Source::show_lints`]所输出的相同
+    pub fn format_lint(&self, lint: &Lint<'_>) -> String {
         struct LintFmtter<'a>(&'a Source<'a>, &'a Lint<'a>);
         impl fmt::Display for LintFmtter<'_> {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
                 self.1.show_lint(self.0, f)
             }
         }
+        LintFmtter(self, lint).to_string()
+    }
+
+    /// 将一个lint转换为诊断, 其渲染文本与[`Source::format_lint`]相同
+    pub fn lint_diagnostic(&self, lint: &Lint<'_>) -> Diagnostic {
+        lint.to_diagnostic(self)
+    }
+
+    pub fn show_lints(&self) {
         for lint in self.lint() {
-            let fmtter = LintFmtter(self, &lint);
-            eprintln!("{}", fmtter)
+            eprintln!("{}", self.format_lint(&lint))
         }
     }
 
@@ -333,6 +382,51 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn target_test() {
+        let s = "op emod x 1 2\nselect y equal x 0 1 2\nsetrate 1\nprint y";
+        let src = Source::from_str(s);
+        assert!(src.lint().iter().all(|lint| !lint.is_error()));
+        let src = Source::from_str(s).with_target(Target::V8);
+        let errors = src.lint().into_iter()
+            .filter(Lint::is_error)
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec![
+            Lint::new(&Var::new(2, 0, "setrate"), ErrorLint::UnsupportedByTarget {
+                target: Target::V8,
+            }),
+        ]);
+        let src = Source::from_str(s).with_target(Target::V7);
+        let errors = src.lint().iter()
+            .filter(|lint| lint.is_error())
+            .map(|lint| src.lint_diagnostic(lint))
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 3);
+        assert!(errors.iter().all(|diag| {
+            diag.code == diagnostic::codes::LINT_UNSUPPORTED_BY_TARGET
+        }));
+        assert_eq!(errors[0].primary.as_ref().unwrap().label.as_deref(), Some("emod"));
+        assert_eq!(lints::target_unsupported(Target::V7, &["op", "add", "x", "1", "2"]), None);
+        assert_eq!(lints::target_unsupported(Target::V7, &["draw", "reset"]), Some(1));
+    }
+
+    #[test]
+    fn diagnostic_test() {
+        let s = "# comment\nfoo:\n  set x  _1";
+        let src = Source::from_str(s);
+        let diags = src.lint().iter()
+            .map(|lint| src.lint_diagnostic(lint))
+            .collect::<Vec<_>>();
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].code, diagnostic::codes::LINT_NEVER_USED);
+        let span = diags[0].primary.as_ref().unwrap();
+        assert_eq!((span.start, span.end), ((3, 7), (3, 8)));
+        assert_eq!(span.label.as_deref(), Some("x"));
+        let span = diags[1].primary.as_ref().unwrap();
+        assert_eq!((span.start, span.end), ((3, 10), (3, 12)));
+        assert_eq!(diags[1].to_string(), src.format_lint(&src.lint()[1]));
+    }
+
     #[test]
     fn todo_test() {
         let s = r#"
@@ -1,10 +1,11 @@
 #![allow(clippy::double_must_use)]
 
 use core::fmt;
-use std::vec;
+use std::{ops::Deref, vec};
 
+use diagnostic::{codes, Code, Diagnostic, Severity, Span};
 use lazy_regex::regex_is_match;
-use var_utils::{AsVarType, VarType};
+use var_utils::{AsVarType, Target, VarType};
 
 use crate::{Line, Source, Var};
 
@@ -233,20 +234,30 @@ thread_local! {
     };
 }
 pub fn get_useds<'a>(line: &Line<'a>) -> Option<Vec<VarUsed<'a>>> {
-    LINE_PAT.with(|pats| {
-        pats.iter()
-            .find_map(|pat| {
-                let mut useds = vec![];
-                for (pat, var) in pat.iter().zip(line.args()) {
-                    match pat.pat(*var) {
-                        Ok(Some(used)) => useds.push(used),
-                        Ok(None) => {},
-                        Err(()) => return None,
-                    }
+    LINE_PAT.with(|pats| match_useds(pats, line))
+}
+
+/// 同[`get_useds`], 但是对于未录入的语句返回[`None`], 而不是将参数视作使用
+pub fn get_known_useds<'a>(line: &Line<'a>) -> Option<Vec<VarUsed<'a>>> {
+    LINE_PAT.with(|pats| match_useds(&pats[..pats.len()-1], line))
+}
+
+fn match_useds<'a>(
+    pats: &[Vec<VarPat>],
+    line: &Line<'a>,
+) -> Option<Vec<VarUsed<'a>>> {
+    pats.iter()
+        .find_map(|pat| {
+            let mut useds = vec![];
+            for (pat, var) in pat.iter().zip(line.args()) {
+                match pat.pat(*var) {
+                    Ok(Some(used)) => useds.push(used),
+                    Ok(None) => {},
+                    Err(()) => return None,
                 }
-                useds.into()
-            })
-    })
+            }
+            useds.into()
+        })
 }
 
 fn vec_optiter<T>(value: Option<Vec<T>>) -> vec::IntoIter<T> {
@@ -359,6 +370,10 @@ const OP_METHODS: &[&str] = &[
     "floor", "ceil", "round", "sqrt", "rand", "sin", "cos",
     "tan", "asin", "acos", "atan",
 ];
+/// v8中加入的操作符
+///
+/// 对照Mindustry的`mindustry.logic.LogicOp`, 在v146中没有这些枚举项
+const V8_OP_METHODS: &[&str] = &["emod", "ushr", "logn"];
 const JUMP_METHODS: &[&str] = &[
     "equal", "notEqual", "lessThan", "lessThanEq",
     "greaterThan", "greaterThanEq", "strictEqual",
@@ -371,6 +386,29 @@ const UNIT_CONTROL_METHODS: &[&str] = &[
     "flag", "build", "getBlock", "within", "unbind",
     "deconstruct",
 ];
+/// v8中加入的单位控制方法
+///
+/// 对照Mindustry的`mindustry.logic.LUnitControl`,
+/// v146中的最后一项为`unbind`, `deconstruct`在v8中加入
+const V8_UNIT_CONTROL_METHODS: &[&str] = &["deconstruct"];
+const DRAW_MODES: &[&str] = &[
+    "clear", "color", "col", "stroke", "line", "rect",
+    "lineRect", "poly", "linePoly", "triangle", "image",
+    "print", "translate", "scale", "rotate", "reset",
+];
+/// v8中加入的绘制方法
+///
+/// 对照Mindustry的`mindustry.logic.LogicDisplay.GraphicsType`,
+/// v146中的最后一项为`image`
+const V8_DRAW_MODES: &[&str] = &[
+    "print", "translate", "scale", "rotate", "reset",
+];
+const LOOKUP_TYPES: &[&str] = &["block", "unit", "item", "liquid", "team"];
+/// v8中加入的查找类型
+///
+/// 对照Mindustry的`mindustry.logic.LStatements.LookupStatement`,
+/// v146中仅可查找`block`, `unit`, `item`与`liquid`
+const V8_LOOKUP_TYPES: &[&str] = &["team"];
 const FETCH_METHODS: &[&str] = &[
     "unit",     "unitCount",
     "player",   "playerCount",
@@ -381,9 +419,62 @@ const BLOCK_FLAG: &[&str] = &[
     "core", "storage", "generator", "turret", "factory",
     "repair", "rally", "battery", "reactor", "drill", "shield",
 ];
+/// v8中加入的指令
+const V8_INSTRUCTIONS: &[&str] = &["select", "unpackcolor", "format", "localeprint"];
+/// 仅世界处理器可用的指令
+const WORLD_INSTRUCTIONS: &[&str] = &[
+    "getblock", "setblock", "spawn", "status", "weathersense", "weatherset",
+    "spawnwave", "setrule", "message", "cutscene", "effect", "explosion",
+    "setrate", "fetch", "sync", "getflag", "setflag", "setprop",
+    "playsound", "setmarker", "makemarker", "localeprint",
+];
+
+/// 检查一行逻辑能否在目标版本中使用,
+/// 返回首个不被目标支持的参数的下标, 例如指令本身或操作符
+pub fn target_unsupported<S>(target: Target, args: &[S]) -> Option<usize>
+where S: Deref<Target = str>,
+{
+    let [cmd, args @ ..] = args else { return None };
+    let cmd = &**cmd;
+    if !target.is_world() && WORLD_INSTRUCTIONS.contains(&cmd)
+        || !target.is_v8() && V8_INSTRUCTIONS.contains(&cmd)
+    {
+        return Some(0);
+    }
+    if target.is_v8() { return None }
+    let v8_opers = match cmd {
+        "op" => V8_OP_METHODS,
+        "ucontrol" => V8_UNIT_CONTROL_METHODS,
+        "draw" => V8_DRAW_MODES,
+        "lookup" => V8_LOOKUP_TYPES,
+        _ => return None,
+    };
+    args.first()
+        .filter(|oper| v8_opers.contains(&&***oper))
+        .map(|_| 1)
+}
+
+#[must_use]
+fn check_target<'a>(
+    src: &'a crate::Source<'a>,
+    line: &'a crate::Line<'a>,
+) -> Option<Lint<'a>> {
+    let target = src.target();
+    let arg_idx = target_unsupported(target, line.args())?;
+    Lint::new(&line.args()[arg_idx], ErrorLint::UnsupportedByTarget { target }).into()
+}
+
+pub fn lint<'a>(
+    src: &'a crate::Source<'a>,
+    line: &'a crate::Line<'a>,
+) -> Vec<Lint<'a>> {
+    let mut lints = Vec::from_iter(check_target(src, line));
+    lints.extend(lint_args(src, line));
+    lints
+}
 
 make_lints! {
-    pub fn lint<'a>(src, line) -> Lint<'a>;
+    fn lint_args<'a>(src, line) -> Lint<'a>;
     let lints;
     "set" | "getlink" (2) {
         if let [_, result, ..] = line.args() {
@@ -406,7 +497,7 @@ make_lints! {
     }
     "lookup" (3) {
         if let [_, mode, result, index, ..] = line.args() {
-            lints.extend(check_oper(mode, &["block", "unit", "item", "liquid", "team"]));
+            lints.extend(check_oper(mode, LOOKUP_TYPES));
             lints.extend(check_assign_var(src, line, result));
             lints.extend(check_vars(src, line, [index]));
         }
@@ -470,11 +561,7 @@ make_lints! {
     }
     "draw" (7) {
         if let [_, mode, args @ ..] = line.args() {
-            lints.extend(check_oper(mode, &[
-               "clear", "color", "col", "stroke", "line", "rect",
-               "lineRect", "poly", "linePoly", "triangle", "image",
-               "print", "translate", "scale", "rotate", "reset",
-            ]));
+            lints.extend(check_oper(mode, DRAW_MODES));
             lints.extend(check_vars(src, line, args));
         }
     }
@@ -661,6 +748,33 @@ impl<'a> Lint<'a> {
     pub fn new(arg: &'a Var<'a>, msg: impl Into<LintType>) -> Self {
         Self { arg, msg: msg.into() }
     }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self.msg, LintType::Error(_))
+    }
+
+    pub fn to_diagnostic(&self, src: &Source<'_>) -> Diagnostic {
+        struct MsgFmtter<'a>(&'a Source<'a>, &'a LintType);
+        impl fmt::Display for MsgFmtter<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.1.show_lint(self.0, f)
+            }
+        }
+        let message = MsgFmtter(src, &self.msg).to_string();
+        let severity = match self.msg {
+            LintType::Warning(_) => Severity::Warning,
+            LintType::Error(_) => Severity::Error,
+        };
+        let span = src.lines()[self.arg.lineno()]
+            .arg_location(self.arg.arg_idx())
+            .map(|(line, start, end)| {
+                Span::new(None, (line, start), (line, end))
+                    .with_label(self.arg.value())
+            });
+        Diagnostic::new(severity, self.msg.code(), message)
+            .with_primary(span)
+            .with_rendered(src.format_lint(self))
+    }
 }
 impl ShowLint for Lint<'_> {
     fn show_lint(
@@ -710,6 +824,26 @@ impl LintType {
             LintType::Error(_) => color_str!(1 91: "Error"),
         }
     }
+
+    pub fn code(&self) -> Code {
+        match self {
+            LintType::Warning(warn) => match warn {
+                WarningLint::UsedDoubleUnderline => codes::LINT_USED_DOUBLE_UNDERLINE,
+                WarningLint::UsedRawArgs => codes::LINT_USED_RAW_ARGS,
+                WarningLint::ArgsCountNotMatch { .. } => codes::LINT_ARGS_COUNT_NOT_MATCH,
+                WarningLint::AssignLiteral => codes::LINT_ASSIGN_LITERAL,
+                WarningLint::SuspectedConstant => codes::LINT_SUSPECTED_CONSTANT,
+                WarningLint::SuspectedVarCmd => codes::LINT_SUSPECTED_VAR_CMD,
+                WarningLint::SuspectedValueCmd => codes::LINT_SUSPECTED_VALUE_CMD,
+                WarningLint::NeverUsed => codes::LINT_NEVER_USED,
+                WarningLint::NoTargetJump => codes::LINT_NO_TARGET_JUMP,
+            },
+            LintType::Error(err) => match err {
+                ErrorLint::InvalidOper { .. } => codes::LINT_INVALID_OPER,
+                ErrorLint::UnsupportedByTarget { .. } => codes::LINT_UNSUPPORTED_BY_TARGET,
+            },
+        }
+    }
 }
 impl From<WarningLint> for LintType {
     fn from(value: WarningLint) -> Self {
@@ -793,6 +927,10 @@ pub enum ErrorLint {
     InvalidOper {
         expected: &'static [&'static str],
     },
+    /// 指令或操作符在目标版本中不可用
+    UnsupportedByTarget {
+        target: Target,
+    },
 }
 impl ShowLint for ErrorLint {
     fn show_lint(
@@ -804,6 +942,9 @@ impl ShowLint for ErrorLint {
             ErrorLint::InvalidOper { expected } => {
                 write!(f, "无效的操作符, 预期: [{}]", expected.join(" "))?
             },
+            ErrorLint::UnsupportedByTarget { target } => {
+                write!(f, "在目标版本{target}中不可用")?
+            },
         }
         Ok(())
     }
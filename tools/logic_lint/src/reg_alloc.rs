@@ -0,0 +1,205 @@
+//! 基于活跃性的临时变量分配
+//!
+//! 每次获取临时变量都会得到一个新的`__N`, 较大的程序会因此产生大量的变量,
+//! 这里将生命周期不重叠的临时变量合并为同一个名称
+//!
+//! 仅重命名编译器生成的`__N`, 不会改变任何用户可见的名称
+
+use std::collections::{HashMap, HashSet};
+
+use tag_code::{mdt_logic_split_unwraped, Tag, TagCodes, TagLine};
+
+use crate::flow::{is_temp_var, Flow};
+
+/// 可以被重命名的临时变量, 不包括`___N`
+fn is_allocatable(name: &str) -> bool {
+    is_temp_var(name) && !name.starts_with("___")
+}
+
+/// 重命名临时变量使其尽可能复用相同的名称, 返回减少的变量数
+///
+/// 每个名称会使用分配到该名称的变量中首个出现的变量的名称,
+/// 所以不会与未被重命名的变量冲突
+///
+/// 出现在未录入语句中的临时变量无法确定其是否被赋值, 所以不会被重命名
+///
+/// 如果有重复的`Tag`, 返回其行下标及重复`Tag`
+pub fn reuse_temps(tag_codes: &mut TagCodes) -> Result<usize, (usize, Tag)> {
+    tag_codes.build_tagdown()?;
+    let renames = allocate(tag_codes);
+    let saved = renames.values().collect::<HashSet<_>>().len();
+    let saved = renames.len() - saved;
+    let renames = renames.into_iter()
+        .filter(|(from, to)| from != to)
+        .collect::<HashMap<_, _>>();
+    if renames.is_empty() {
+        return Ok(saved);
+    }
+    let rename = |line: &str| {
+        mdt_logic_split_unwraped(line).into_iter()
+            .map(|arg| renames.get(arg).map_or(arg, String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    for line in tag_codes.lines_mut() {
+        match line {
+            TagLine::Jump(jump) => jump.1 = rename(&jump.1),
+            TagLine::Line(line) => **line = rename(line),
+            TagLine::TagDown(_) => unreachable!(),
+        }
+    }
+    Ok(saved)
+}
+
+/// 每个可重命名的临时变量所分配到的名称
+fn allocate(tag_codes: &TagCodes) -> HashMap<String, String> {
+    let flow = Flow::new(tag_codes);
+    let liveness = flow.liveness();
+    let lines = flow.lines();
+
+    let mut pinned = HashSet::new();
+    let mut order = vec![];
+    for line in lines {
+        if line.reads.is_none() {
+            pinned.extend(line.args.iter().copied().filter(|arg| is_allocatable(arg)));
+        }
+        let reads = line.reads.iter().flatten();
+        for &var in reads.chain(&line.assigns) {
+            if is_allocatable(var) && !order.contains(&var) {
+                order.push(var);
+            }
+        }
+    }
+    order.retain(|var| !pinned.contains(var));
+
+    // 被赋值时与之后仍然会被读取的变量冲突, 初始时同时存活的变量之间也冲突
+    let mut conflicts: HashMap<&str, HashSet<&str>> = HashMap::new();
+    let mut add_conflict = |a, b| if a != b {
+        conflicts.entry(a).or_default().insert(b);
+        conflicts.entry(b).or_default().insert(a);
+    };
+    for (i, line) in lines.iter().enumerate() {
+        for &def in &line.assigns {
+            liveness.live_out(i).for_each(|var| add_conflict(def, var));
+        }
+    }
+    if !lines.is_empty() {
+        let entry = liveness.live_in(0).collect::<Vec<_>>();
+        for (i, &a) in entry.iter().enumerate() {
+            entry[i+1..].iter().for_each(|&b| add_conflict(a, b));
+        }
+    }
+
+    let mut slots: Vec<&str> = vec![];
+    let mut assigned: HashMap<&str, usize> = HashMap::new();
+    for var in order {
+        let used = conflicts.get(var).into_iter().flatten()
+            .filter_map(|other| assigned.get(other))
+            .collect::<HashSet<_>>();
+        let slot = (0..).find(|slot| !used.contains(slot)).unwrap();
+        if slot == slots.len() {
+            slots.push(var);
+        }
+        assigned.insert(var, slot);
+    }
+    assigned.into_iter()
+        .map(|(var, slot)| (var.to_owned(), slots[slot].to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reuse(src: &str) -> (usize, Vec<String>) {
+        let mut tag_codes = TagCodes::from_tag_lines(src);
+        let saved = reuse_temps(&mut tag_codes).unwrap();
+        (saved, tag_codes.compile().unwrap())
+    }
+
+    #[test]
+    fn reuse_temps_test() {
+        let (saved, lines) = reuse("\
+            read __0 cell1 0\n\
+            print __0\n\
+            read __1 cell1 1\n\
+            print __1\n\
+            op add __2 __1 1\n\
+            print __2\n\
+        ");
+        assert_eq!(saved, 2);
+        assert_eq!(lines, [
+            "read __0 cell1 0",
+            "print __0",
+            "read __0 cell1 1",
+            "print __0",
+            "op add __0 __0 1",
+            "print __0",
+        ]);
+
+        // 生命周期重叠
+        let (saved, _) = reuse("\
+            read __0 cell1 0\n\
+            read __1 cell1 1\n\
+            op add x __0 __1\n\
+        ");
+        assert_eq!(saved, 0);
+
+        // 跨越循环存活的变量
+        let (saved, lines) = reuse("\
+            set __0 0\n\
+            :a\n\
+            read __1 cell1 __0\n\
+            print __1\n\
+            op add __0 __0 1\n\
+            jump :a lessThan __0 10\n\
+        ");
+        assert_eq!(saved, 0);
+        assert_eq!(lines[1], "read __1 cell1 __0");
+    }
+
+    #[test]
+    fn reuse_temps_keep_test() {
+        // 用户变量与`___N`不会被重命名
+        let (saved, lines) = reuse("\
+            set __0 1\n\
+            print __0\n\
+            set x 2\n\
+            print x\n\
+            set ___1 3\n\
+            print ___1\n\
+            set __3 5\n\
+            print __3\n\
+        ");
+        assert_eq!(saved, 1);
+        assert_eq!(lines, [
+            "set __0 1",
+            "print __0",
+            "set x 2",
+            "print x",
+            "set ___1 3",
+            "print ___1",
+            "set __0 5",
+            "print __0",
+        ]);
+
+        // 未录入语句中的变量
+        let (saved, lines) = reuse("\
+            set __0 1\n\
+            foo __0\n\
+            set __1 2\n\
+            print __1\n\
+        ");
+        assert_eq!(saved, 0);
+        assert_eq!(lines[1], "foo __0");
+
+        // 初始时同时存活的变量
+        let (saved, _) = reuse("\
+            print __0\n\
+            print __1\n\
+            set __0 1\n\
+            set __1 2\n\
+        ");
+        assert_eq!(saved, 0);
+    }
+}
@@ -0,0 +1,407 @@
+//! 将程序在标记处拆分为多个处理器上运行的程序
+//!
+//! 标记为注释行`# split NAME`, 在Bang中可以写作`'#' split NAME;`,
+//! 不进行拆分时它只是一行注释, 名称可以省略, 省略时为`partN`
+//!
+//! 各处理器通过同一个内存元轮流运行, 其第0格为当前轮到的处理器序号:
+//! 每个处理器等待轮到自己, 从内存元中读取共享变量, 运行自身的部分,
+//! 然后写回共享变量并交给下一个处理器, 最后一个处理器交回给首个处理器
+//!
+//! 内存元只能存储数字, 所以在处理器之间传递的变量只能是数字,
+//! 而使用`@counter`的跳转也不能跨越拆分处
+
+use std::{collections::{BTreeSet, HashMap, HashSet}, fmt::{self, Display}};
+
+use tag_code::{mdt_logic_split_unwraped, Jump, Tag, TagCodes, TagLine};
+use var_utils::AsVarType;
+
+use crate::{lints::get_known_useds, Line};
+
+/// 等待轮到当前处理器时读取到的序号
+const TURN_VAR: &str = "__split_turn";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    /// 跳转到了其它部分中的标记, 部分的名称与跳转目标
+    CrossJump { from: String, tag: Tag },
+    /// 使用了`end`, 其会回到当前处理器的首行而不是首个处理器
+    End { part: String },
+    /// 共享变量过多, 内存元中放不下
+    Capacity { vars: usize, capacity: usize },
+}
+impl Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CrossJump { from, tag } => {
+                write!(f, "部分 {from} 中的跳转的目标 :{tag} 在其它部分中")
+            },
+            Self::End { part } => {
+                write!(f, "部分 {part} 中使用了 end, 拆分后无法回到首个处理器")
+            },
+            Self::Capacity { vars, capacity } => {
+                write!(f, "需要在处理器之间传递 {vars} 个变量, 超过了内存元的 {} 格容量",
+                    capacity - 1)
+            },
+        }
+    }
+}
+
+/// 拆分后在一个处理器中运行的部分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPart {
+    pub name: String,
+    pub tag_codes: TagCodes,
+    /// 需要链接到该处理器的建筑, 包括内存元
+    pub links: Vec<String>,
+    /// 开始时从内存元中读取的变量
+    pub receives: Vec<String>,
+    /// 结束时写回内存元的变量
+    pub sends: Vec<String>,
+}
+
+/// 拆分后的各个部分, 以及共享变量在内存元中的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Split {
+    pub cell: String,
+    pub parts: Vec<SplitPart>,
+    pub slots: Vec<(String, usize)>,
+}
+/// 描述每个处理器需要的链接与传递的变量
+impl Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "cell {}", self.cell)?;
+        for (slot, (var, i)) in self.slots.iter().enumerate() {
+            debug_assert_eq!(slot + 1, *i);
+            writeln!(f, "slot {i} {var}")?;
+        }
+        for (i, part) in self.parts.iter().enumerate() {
+            writeln!(f, "processor {i} {} ({} lines)", part.name, part.tag_codes.count_no_tag())?;
+            for (kind, vars) in [
+                ("links", &part.links),
+                ("receives", &part.receives),
+                ("sends", &part.sends),
+            ] {
+                if !vars.is_empty() {
+                    writeln!(f, "    {kind}: {}", vars.join(" "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 内存元的格数, 内存库为512格
+pub fn cell_capacity(cell: &str) -> usize {
+    if cell.starts_with("bank") { 512 } else { 64 }
+}
+
+/// 是否是拆分标记, 返回其名称
+fn as_marker(line: &TagLine) -> Option<Option<&str>> {
+    let comment = line.as_line()?.strip_prefix('#')?;
+    match comment.split_whitespace().collect::<Vec<_>>()[..] {
+        ["split"] => Some(None),
+        ["split", name] => Some(Some(name)),
+        _ => None,
+    }
+}
+
+/// 一行读取与写入的变量, 未录入的语句视作读取并写入了所有变量参数
+fn line_useds(line: &TagLine) -> (Vec<&str>, Vec<&str>) {
+    let args = match line {
+        TagLine::Jump(jump) => {
+            let mut args = vec!["jump", "_"];
+            args.extend(mdt_logic_split_unwraped(&jump.1));
+            args
+        },
+        TagLine::Line(line) => mdt_logic_split_unwraped(line),
+        TagLine::TagDown(_) => return (vec![], vec![]),
+    };
+    let is_var = |var: &&str| var.as_var_type().is_var() && !var.starts_with('@');
+    let Some(useds) = get_known_useds(&Line::from_line(0, &args)) else {
+        let vars = args[1..].iter().copied().filter(is_var).collect::<Vec<_>>();
+        return (vars.clone(), vars);
+    };
+    let reads = useds.iter()
+        .filter_map(|used| used.as_read().map(|var| var.value()))
+        .filter(is_var)
+        .collect();
+    let assigns = useds.iter()
+        .filter_map(|used| used.as_assign().map(|var| var.value()))
+        .filter(is_var)
+        .collect();
+    (reads, assigns)
+}
+
+/// 看起来像建筑链接名称的变量, 例如`cell1`与`message2`
+fn is_link_name(name: &str) -> bool {
+    let digits = name.trim_start_matches(|ch: char| ch.is_ascii_lowercase());
+    digits.len() != name.len()
+        && !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// 尚未生成通信代码的部分
+struct RawPart {
+    name: Option<String>,
+    lines: Vec<TagLine>,
+    reads: HashSet<String>,
+    assigns: HashSet<String>,
+}
+impl RawPart {
+    fn new(name: Option<&str>) -> Self {
+        Self {
+            name: name.map(Into::into),
+            lines: vec![],
+            reads: HashSet::new(),
+            assigns: HashSet::new(),
+        }
+    }
+
+    /// 末尾的标记, 跳转到这些标记等于跳转到下一个部分的开头
+    fn tail_tags(&self) -> Vec<Tag> {
+        let tags = self.lines.iter()
+            .rev()
+            .map_while(|line| line.as_tag_down().copied())
+            .collect::<Vec<_>>();
+        tags.into_iter().rev().collect()
+    }
+}
+
+/// 在`# split NAME`处拆分程序, 没有标记时仅有一个部分并且不生成通信代码
+///
+/// 跳转到拆分处的标记时, 在跳转所在的部分中为跳转到该部分的结尾,
+/// 在之后的部分中为跳转到该部分的开头
+pub fn split_program(tag_codes: &TagCodes, cell: &str) -> Result<Split, SplitError> {
+    let mut tag_codes = tag_codes.clone();
+    tag_codes.tag_up();
+
+    let mut parts = vec![RawPart::new(None)];
+    for line in tag_codes.lines() {
+        let first = parts.len() == 1;
+        let part = parts.last_mut().unwrap();
+        if let Some(name) = as_marker(line) {
+            if first && part.lines.iter().all(TagLine::is_tag_down) {
+                part.name = name.map(Into::into);
+            } else {
+                parts.push(RawPart::new(name));
+            }
+            continue;
+        }
+        let (reads, assigns) = line_useds(line);
+        part.reads.extend(reads.into_iter().map(Into::into));
+        part.assigns.extend(assigns.into_iter().map(Into::into));
+        part.lines.push(line.clone());
+    }
+    for (i, part) in parts.iter_mut().enumerate() {
+        part.name.get_or_insert_with(|| format!("part{i}"));
+    }
+    if parts.len() == 1 {
+        return Ok(single_part(parts.pop().unwrap(), cell));
+    }
+
+    check_parts(&parts)?;
+
+    // 在一个部分中被写入, 在另一个部分中被读取的变量
+    let all_parts = &parts;
+    let shared = parts.iter().enumerate()
+        .flat_map(|(i, part)| part.assigns.iter().filter(move |var| {
+            all_parts.iter().enumerate()
+                .any(|(j, other)| i != j && other.reads.contains(*var))
+        }))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let capacity = cell_capacity(cell);
+    if shared.len() + 1 > capacity {
+        return Err(SplitError::Capacity { vars: shared.len(), capacity });
+    }
+    let slots = shared.iter().cloned().zip(1..).collect::<Vec<_>>();
+    let slot_of = slots.iter().cloned().collect::<HashMap<_, _>>();
+    let all_assigns = parts.iter()
+        .flat_map(|part| part.assigns.iter().cloned())
+        .collect::<HashSet<_>>();
+
+    let wait_tag = tag_codes.lines().iter()
+        .flat_map(|line| [line.as_tag_down().copied(), line.as_jump().map(|jump| jump.0)])
+        .flatten()
+        .max()
+        .map_or(0, |tag| tag + 1);
+    let boundaries = parts.iter().map(RawPart::tail_tags).collect::<Vec<_>>();
+    let count = parts.len();
+    let parts = parts.into_iter().enumerate().map(|(i, part)| {
+        // 部分中写入的变量也需要先读取, 否则未被写入时会写回旧值
+        let receives = shared.iter()
+            .filter(|&var| part.reads.contains(var) || part.assigns.contains(var))
+            .cloned()
+            .collect::<Vec<_>>();
+        let sends = shared.iter()
+            .filter(|&var| part.assigns.contains(var))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut lines = vec![
+            TagLine::TagDown(wait_tag),
+            format!("read {TURN_VAR} {cell} 0").into(),
+            Jump(wait_tag, format!("notEqual {TURN_VAR} {i}")).into(),
+        ];
+        lines.extend(receives.iter().map(|var| {
+            format!("read {var} {cell} {}", slot_of[var]).into()
+        }));
+        let prev = (i + count - 1) % count;
+        lines.extend(boundaries[prev].iter().map(|&tag| TagLine::TagDown(tag)));
+        lines.extend(part.lines);
+        lines.extend(sends.iter().map(|var| {
+            format!("write {var} {cell} {}", slot_of[var]).into()
+        }));
+        lines.push(format!("write {} {cell} 0", (i + 1) % count).into());
+
+        SplitPart {
+            name: part.name.unwrap(),
+            tag_codes: lines.into(),
+            links: links(&part.reads, &all_assigns, Some(cell)),
+            receives,
+            sends,
+        }
+    }).collect();
+    Ok(Split { cell: cell.into(), parts, slots })
+}
+
+fn single_part(part: RawPart, cell: &str) -> Split {
+    Split {
+        cell: cell.into(),
+        parts: vec![SplitPart {
+            links: links(&part.reads, &part.assigns, None),
+            name: part.name.unwrap(),
+            tag_codes: part.lines.into(),
+            receives: vec![],
+            sends: vec![],
+        }],
+        slots: vec![],
+    }
+}
+
+/// 检查跳转都在所在的部分中, 并且没有使用`end`
+fn check_parts(parts: &[RawPart]) -> Result<(), SplitError> {
+    for (i, part) in parts.iter().enumerate() {
+        let prev = &parts[(i + parts.len() - 1) % parts.len()];
+        let tags = part.lines.iter()
+            .filter_map(TagLine::as_tag_down)
+            .chain(&prev.tail_tags())
+            .copied()
+            .collect::<HashSet<_>>();
+        let name = part.name.clone().unwrap();
+        for line in &part.lines {
+            if let Some(jump) = line.as_jump() {
+                let tag = jump.data().0;
+                if !tags.contains(&tag) {
+                    return Err(SplitError::CrossJump { from: name, tag });
+                }
+            }
+            if line.as_line().is_some_and(|line| {
+                mdt_logic_split_unwraped(line).first() == Some(&"end")
+            }) {
+                return Err(SplitError::End { part: name });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 部分中读取的从未被写入的建筑链接
+fn links(reads: &HashSet<String>, assigns: &HashSet<String>, cell: Option<&str>) -> Vec<String> {
+    let links = reads.iter()
+        .filter(|&var| is_link_name(var) && !assigns.contains(var))
+        .map(String::as_str)
+        .chain(cell)
+        .collect::<BTreeSet<_>>();
+    links.into_iter().map(Into::into).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_src(src: &str) -> Result<Split, SplitError> {
+        split_program(&TagCodes::from_tag_lines(src), "cell1")
+    }
+
+    fn compile(part: &mut SplitPart) -> Vec<String> {
+        part.tag_codes.compile().unwrap()
+    }
+
+    #[test]
+    fn split_test() {
+        let mut split = split_src("\
+            read x cell2 0\n\
+            op add y x 1\n\
+            set z 2\n\
+            # split show\n\
+            print y\n\
+            printflush message1\n\
+        ").unwrap();
+        assert_eq!(split.slots, [("y".into(), 1)]);
+        assert_eq!(split.parts.len(), 2);
+        assert_eq!(split.parts[0].name, "part0");
+        assert_eq!(split.parts[0].links, ["cell1", "cell2"]);
+        assert_eq!(compile(&mut split.parts[0]), [
+            "read __split_turn cell1 0",
+            "jump 0 notEqual __split_turn 0",
+            "read y cell1 1",
+            "read x cell2 0",
+            "op add y x 1",
+            "set z 2",
+            "write y cell1 1",
+            "write 1 cell1 0",
+        ]);
+        assert_eq!(split.parts[1].name, "show");
+        assert_eq!(split.parts[1].receives, ["y"]);
+        assert!(split.parts[1].sends.is_empty());
+        assert_eq!(compile(&mut split.parts[1]), [
+            "read __split_turn cell1 0",
+            "jump 0 notEqual __split_turn 1",
+            "read y cell1 1",
+            "print y",
+            "printflush message1",
+            "write 0 cell1 0",
+        ]);
+
+        // 没有拆分标记
+        let mut split = split_src("print 1").unwrap();
+        assert_eq!(split.parts.len(), 1);
+        assert_eq!(compile(&mut split.parts[0]), ["print 1"]);
+    }
+
+    #[test]
+    fn split_jump_test() {
+        // 跳转到拆分处的标记在两边分别为结尾与开头
+        let mut split = split_src("\
+            jump :a lessThan x 2\n\
+            print 1\n\
+            :a\n\
+            # split\n\
+            print 2\n\
+            jump :a always 0 0\n\
+        ").unwrap();
+        assert_eq!(split.parts[1].name, "part1");
+        assert_eq!(compile(&mut split.parts[0])[2..], [
+            "jump 4 lessThan x 2",
+            "print 1",
+            "write 1 cell1 0",
+        ]);
+        assert_eq!(compile(&mut split.parts[1])[2..], [
+            "print 2",
+            "jump 2 always 0 0",
+            "write 0 cell1 0",
+        ]);
+
+        let err = split_src("\
+            :a\n\
+            print 1\n\
+            # split b\n\
+            jump :a lessThan x 2\n\
+        ").unwrap_err();
+        assert_eq!(err, SplitError::CrossJump { from: "b".into(), tag: 0 });
+
+        let err = split_src("print 1\n# split\nend").unwrap_err();
+        assert_eq!(err, SplitError::End { part: "part1".into() });
+    }
+}
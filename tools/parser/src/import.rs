@@ -0,0 +1,315 @@
+//! 对源顶部的导入进行加载
+//!
+//! 例如 `import "std/function";`, 会在导入者所在目录与搜索路径中寻找
+//! `std/function.mdtlbl`, 每个文件仅被加载一次,
+//! 被导入的文件的代码会被放置于导入者的代码之前
+//!
+//! 其中`std/`命名空间是保留的, 只会加载嵌入的标准库, 见[`std_lib`]
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    env,
+    fmt::{self, Display},
+    fs,
+    io,
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use syntax::{
+    codes,
+    Diagnostic,
+    Expand,
+    LogicLine,
+    Meta,
+    SourceMap,
+    SourcePos,
+    Span,
+};
+use tag_code::logic_parser::IdxBox;
+
+use crate::{parse_err_diagnostic, std_lib, TopLevelParser};
+
+const MAX_INVALID_TOKEN_VIEW: usize = 5;
+
+/// 搜索路径所使用的环境变量, 格式与`PATH`相同
+pub const PATH_ENV: &str = "MDTLBL_PATH";
+
+/// 默认的源文件扩展名, 导入的路径没有扩展名时会被补全
+pub const EXTENSION: &str = "mdtlbl";
+
+#[derive(Debug)]
+pub enum ImportError {
+    Parse {
+        file: Option<Rc<str>>,
+        diagnostic: Box<Diagnostic>,
+    },
+    NotFound {
+        path: String,
+        at: SourcePos,
+    },
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    /// 循环导入的链, 首尾为同一个文件
+    Circular(Vec<PathBuf>),
+}
+impl Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { file: Some(file), diagnostic } => {
+                write!(f, "{file}: {diagnostic}")
+            },
+            Self::Parse { file: None, diagnostic } => {
+                write!(f, "{diagnostic}")
+            },
+            Self::NotFound { path, at } => {
+                write!(f, "{at} 未找到导入的文件: {path:?}")
+            },
+            Self::Io { path, err } => {
+                write!(f, "读取导入的文件 {} 失败: {err}", path.display())
+            },
+            Self::Circular(chain) => {
+                let chain = chain.iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "循环导入: {}", chain.join(" -> "))
+            },
+        }
+    }
+}
+impl std::error::Error for ImportError { }
+impl ImportError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Self::Parse { diagnostic, .. } => {
+                (**diagnostic).clone().with_rendered(self.to_string())
+            },
+            Self::NotFound { path, at } => {
+                Diagnostic::error(codes::IMPORT_NOT_FOUND, self.to_string())
+                    .with_primary(Span::from(at.clone())
+                        .with_label(format!("{path:?}")))
+                    .with_suggestion(format!("检查导入的路径, \
+                        或是使用环境变量{PATH_ENV}添加搜索路径"))
+            },
+            Self::Io { .. } => {
+                Diagnostic::error(codes::IMPORT_IO, self.to_string())
+            },
+            Self::Circular(_) => {
+                Diagnostic::error(codes::IMPORT_CIRCULAR, self.to_string())
+            },
+        }
+    }
+}
+
+/// 导入路径被解析到的源
+enum Resolved {
+    /// 嵌入的标准库模块, 以`std/name.mdtlbl`作为其标识
+    Std(PathBuf, &'static str),
+    File(PathBuf),
+}
+
+#[derive(Debug, Default)]
+pub struct Importer {
+    search_paths: Vec<PathBuf>,
+    source_map: SourceMap,
+    loaded: HashSet<PathBuf>,
+    stack: Vec<PathBuf>,
+    lines: Vec<LogicLine>,
+    uses_std: bool,
+}
+impl Importer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 使用[`PATH_ENV`]中的搜索路径进行创建
+    pub fn from_env() -> Self {
+        let mut importer = Self::new();
+        if let Some(paths) = env::var_os(PATH_ENV) {
+            importer.search_paths.extend(env::split_paths(&paths));
+        }
+        importer
+    }
+
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+        self.search_paths.push(path.into());
+    }
+
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    /// 已加载的源中是否导入了嵌入的标准库
+    pub fn uses_std(&self) -> bool {
+        self.uses_std
+    }
+
+    /// 加载根源码, 返回包含所有导入的代码与其位置映射
+    ///
+    /// 根源码没有路径时, 以当前目录进行相对导入
+    pub fn load(
+        &mut self,
+        meta: &mut Meta,
+        path: Option<&Path>,
+        src: Rc<String>,
+    ) -> Result<(Expand, SourceMap), ImportError> {
+        let (name, dir) = self.enter_root(path)?;
+        let root = self.load_source(meta, name, Some(&dir), src);
+        meta.set_source_base(0);
+        Ok(self.finish(root?))
+    }
+
+    /// 加载已经被解析的根源码中的导入, 根源码需要是以基址0解析的
+    ///
+    /// 仅返回被导入的代码, 需要由调用者放置于根源码之前,
+    /// 用于需要自行处理根源码解析结果的情况
+    pub fn load_parsed(
+        &mut self,
+        meta: &mut Meta,
+        path: Option<&Path>,
+        src: Rc<String>,
+    ) -> Result<(Expand, SourceMap), ImportError> {
+        let (name, dir) = self.enter_root(path)?;
+        let base = self.source_map.add_file(name.clone(), src.clone());
+        assert_eq!(base, 0, "root source must be the first file");
+        let result = self.load_imports(meta, name, Some(&dir), &src);
+        meta.set_source_base(0);
+        result?;
+        Ok(self.finish(Expand::default()))
+    }
+
+    fn enter_root(
+        &mut self,
+        path: Option<&Path>,
+    ) -> Result<(Option<Rc<str>>, PathBuf), ImportError> {
+        let name = path.map(|path| path.display().to_string().into());
+        let dir = path
+            .and_then(Path::parent)
+            .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+        if let Some(path) = path {
+            let path = fs::canonicalize(path).map_err(|err| {
+                ImportError::Io { path: path.into(), err }
+            })?;
+            self.loaded.insert(path.clone());
+            self.stack.push(path);
+        }
+        Ok((name, dir))
+    }
+
+    fn finish(&mut self, root: Expand) -> (Expand, SourceMap) {
+        let mut lines = mem::take(&mut self.lines);
+        lines.extend(root.0);
+        (Expand(lines), mem::take(&mut self.source_map))
+    }
+
+    /// `dir`为空时表示这是一个嵌入的标准库模块
+    fn load_source(
+        &mut self,
+        meta: &mut Meta,
+        name: Option<Rc<str>>,
+        dir: Option<&Path>,
+        src: Rc<String>,
+    ) -> Result<Expand, ImportError> {
+        let base = self.source_map.add_file(name.clone(), src.clone());
+        meta.set_source_base(base);
+        let expand = TopLevelParser::new().parse(meta, &src)
+            .map_err(|e| {
+                let mut diagnostic
+                    = parse_err_diagnostic::<MAX_INVALID_TOKEN_VIEW>(e, &src);
+                if let Some(name) = &name {
+                    diagnostic.fill_file(name);
+                }
+                ImportError::Parse { file: name.clone(), diagnostic: diagnostic.into() }
+            })?;
+        self.load_imports(meta, name, dir, &src)?;
+        Ok(expand)
+    }
+
+    fn load_imports(
+        &mut self,
+        meta: &mut Meta,
+        name: Option<Rc<str>>,
+        dir: Option<&Path>,
+        src: &str,
+    ) -> Result<(), ImportError> {
+        for IdxBox { index, value: import } in meta.take_imports() {
+            let import = import[1..import.len()-1].to_owned();
+            let Some(resolved) = self.resolve(&import, dir) else {
+                let (line, column) = IdxBox::new(index, ()).location(src);
+                let at = SourcePos { file: name.clone(), line, column };
+                return Err(ImportError::NotFound { path: import, at });
+            };
+            let key = match &resolved {
+                Resolved::Std(key, _) => key.clone(),
+                Resolved::File(path) => fs::canonicalize(path)
+                    .map_err(|err| ImportError::Io { path: path.clone(), err })?,
+            };
+
+            if let Some(i) = self.stack.iter().position(|p| *p == key) {
+                let mut chain = self.stack[i..].to_vec();
+                chain.push(key);
+                return Err(ImportError::Circular(chain));
+            }
+            if !self.loaded.insert(key.clone()) {
+                continue;
+            }
+
+            self.stack.push(key);
+            let lines = match resolved {
+                Resolved::Std(key, text) => {
+                    self.uses_std = true;
+                    let name = key.display().to_string().into();
+                    self.load_source(meta, Some(name), None, text.to_owned().into())?
+                },
+                Resolved::File(path) => {
+                    let text = fs::read_to_string(&path)
+                        .map_err(|err| ImportError::Io { path: path.clone(), err })?;
+                    let sub_dir = path.parent()
+                        .map(Path::to_path_buf)
+                        .or_else(|| dir.map(Path::to_path_buf))
+                        .unwrap_or_default();
+                    let name = path.display().to_string().into();
+                    self.load_source(meta, Some(name), Some(&sub_dir), text.into())?
+                },
+            };
+            self.stack.pop().unwrap();
+            self.lines.extend(lines.0);
+        }
+        Ok(())
+    }
+
+    /// 标准库命名空间只从嵌入的源码中寻找,
+    /// 其余的在导入者所在目录与搜索路径中依次寻找
+    ///
+    /// 嵌入的标准库模块中的相对导入也会在标准库中寻找
+    fn resolve(&self, import: &str, dir: Option<&Path>) -> Option<Resolved> {
+        let import: Cow<'_, str> = match dir {
+            None if std_lib::module_name(import).is_none() => {
+                format!("{}/{import}", std_lib::NAMESPACE).into()
+            },
+            _ => import.into(),
+        };
+        if let Some(module) = std_lib::module_name(&import) {
+            let text = std_lib::get(module)?;
+            let key = format!("{}/{module}.{EXTENSION}", std_lib::NAMESPACE);
+            return Some(Resolved::Std(key.into(), text));
+        }
+
+        let mut import = PathBuf::from(&*import);
+        if import.extension().is_none() {
+            import.set_extension(EXTENSION);
+        }
+        if import.is_absolute() {
+            return import.is_file().then_some(Resolved::File(import));
+        }
+        dir.into_iter()
+            .chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&import))
+            .find(|path| path.is_file())
+            .map(Resolved::File)
+    }
+}
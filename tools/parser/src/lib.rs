@@ -1,4 +1,6 @@
 mod parser;
+pub mod import;
+pub mod std_lib;
 pub use parser::*;
 pub use ::lalrpop_util;
 
@@ -21,6 +23,10 @@ use ::syntax::{
     Take,
     ConstKey,
     Meta,
+    codes,
+    Diagnostic,
+    Span,
+    Comment,
 };
 
 fn trim_complete(v: &Var) -> Var {
@@ -338,6 +344,14 @@ pub fn format_parse_err<const MAX_INVALID_TOKEN_VIEW: usize>(
     e: ParseError<usize, parser::Token<'_>, syntax::Error>,
     src: &str,
 ) -> String {
+    parse_err_diagnostic::<MAX_INVALID_TOKEN_VIEW>(e, src).to_string()
+}
+
+/// 将解析错误转换为诊断, 其位置没有文件, 需要时由调用者填充
+pub fn parse_err_diagnostic<const MAX_INVALID_TOKEN_VIEW: usize>(
+    e: ParseError<usize, parser::Token<'_>, syntax::Error>,
+    src: &str,
+) -> Diagnostic {
     use syntax::{Errors, Error};
     use std::ops::Deref;
     fn fmt_token<'a>(i: impl IntoIterator<Item = &'a str>)
@@ -345,29 +359,36 @@ pub fn format_parse_err<const MAX_INVALID_TOKEN_VIEW: usize>(
         i.into_iter()
             .map(|s| get_token_name(s).unwrap_or(s))
     }
+    fn span([start, end]: [[u32; 2]; 2]) -> Span {
+        Span::new(None, start.into(), end.into())
+    }
+    fn expected_note(expected: &[String]) -> String {
+        format!("预期: [{}]", fmt_token(expected.iter().map(Deref::deref))
+            .collect::<Vec<_>>()
+            .join(", "))
+    }
     match e {
         ParseError::UnrecognizedToken {
             token: (start, token, end),
             expected
         } => {
             let [start, end] = get_locations(src, [start, end]);
-            format!(
-                "在位置 {:?} 至 {:?} 处找到不应出现的令牌: {:?}\n\
-                预期: [{}]",
+            Diagnostic::error(codes::PARSE_UNRECOGNIZED_TOKEN, format!(
+                "在位置 {:?} 至 {:?} 处找到不应出现的令牌: {:?}",
                 start, end,
                 token.1,
-                fmt_token(expected.iter().map(Deref::deref))
-                    .collect::<Vec<_>>()
-                    .join(", "),
-            )
+            ))
+            .with_primary(span([start, end]))
+            .with_note(expected_note(&expected))
         },
         ParseError::ExtraToken { token: (start, token, end) } => {
             let [start, end] = get_locations(src, [start, end]);
-            format!(
+            Diagnostic::error(codes::PARSE_EXTRA_TOKEN, format!(
                 "在位置 {:?} 至 {:?} 处找到多余的令牌: {:?}",
                 start, end,
                 fmt_token(Some(token.1)).next().unwrap(),
-            )
+            ))
+            .with_primary(span([start, end]))
         },
         ParseError::InvalidToken { location } => {
             let [loc] = get_locations(src, [location]);
@@ -382,25 +403,24 @@ pub fn format_parse_err<const MAX_INVALID_TOKEN_VIEW: usize>(
                         .last()
                         .unwrap_or(location))
             ];
-            format!(
+            Diagnostic::error(codes::PARSE_INVALID_TOKEN, format!(
                 "在位置 {:?} 处找到无效的令牌: {:?}",
                 loc,
                 view.trim_end(),
-            )
+            ))
+            .with_primary(span([loc, loc]))
         },
         ParseError::UnrecognizedEof {
             location,
             expected
         } => {
             let [start] = get_locations(src, [location]);
-            format!(
-                "在位置 {:?} 处意外的结束\n\
-                预期: [{}]",
+            Diagnostic::error(codes::PARSE_UNRECOGNIZED_EOF, format!(
+                "在位置 {:?} 处意外的结束",
                 start,
-                fmt_token(expected.iter().map(Deref::deref))
-                    .collect::<Vec<_>>()
-                    .join(", "),
-            )
+            ))
+            .with_primary(span([start, start]))
+            .with_note(expected_note(&expected))
         },
         ParseError::User {
             error: Error {
@@ -411,29 +431,29 @@ pub fn format_parse_err<const MAX_INVALID_TOKEN_VIEW: usize>(
         } => {
             let [start, end]
                 = get_locations(src, [start, end]);
-            let out = |msg| format!(
+            let out = |code, msg| Diagnostic::error(code, format!(
                 "在位置 {:?} 至 {:?} 处的错误:\n{}",
                 start,
                 end,
                 msg
-            );
+            )).with_primary(span([start, end]));
             match err {
                 Errors::NotALiteralUInteger(str, err) => {
-                    out(format_args!(
+                    out(codes::PARSE_NOT_A_LITERAL_UINT, format_args!(
                         "{:?} is not a valided unsigned integer: {}",
                         str,
                         err,
                     ))
                 },
                 Errors::UnpairBranches(left, right) => {
-                    out(format_args!(
+                    out(codes::PARSE_UNPAIR_BRANCHES, format_args!(
                         "unpair branches, left: {}, right: {}",
                         left,
                         right,
                     ))
                 },
                 Errors::OpExprInvalidResult { found, right } => {
-                    out(format_args!(
+                    out(codes::PARSE_OP_EXPR_INVALID_RESULT, format_args!(
                         "{} op-expr can't pattern {} results, expected 1 or {}",
                         right,
                         found,
@@ -441,24 +461,69 @@ pub fn format_parse_err<const MAX_INVALID_TOKEN_VIEW: usize>(
                     ))
                 },
                 Errors::MultipleOpExpr => {
-                    out(format_args!(
+                    out(codes::PARSE_MULTIPLE_OP_EXPR, format_args!(
                         "此处不应展开多个 op-expr",
                     ))
                 },
                 Errors::Emulate => {
-                    out(format_args!(
+                    out(codes::PARSE_EMULATE, format_args!(
                         "用于语言服务器的错误",
                     ))
                 },
-                #[allow(unreachable_patterns)]
-                e => {
-                    out(format_args!("未被枚举的错误: {:?}", e))
-                },
             }
         },
     }
 }
 
+/// 按照词法规则扫描源码中的所有注释, 字符串与`'...'`中的`#`不会被视作注释
+pub fn comments(src: &str) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut chars = src.char_indices().peekable();
+    let mut trailing = false;
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\n' => {
+                trailing = false;
+                continue;
+            },
+            '"' => while let Some((_, ch)) = chars.next() {
+                match ch {
+                    '\\' => { chars.next(); },
+                    '"' => break,
+                    _ => (),
+                }
+            },
+            '\'' => {
+                let rest = &src[i+1..];
+                let len = rest.find(|ch: char| ch == '\'' || ch.is_whitespace())
+                    .filter(|&len| len != 0 && rest[len..].starts_with('\''));
+                if let Some(len) = len {
+                    let end = i + 1 + len + 1;
+                    while chars.next_if(|&(j, _)| j < end).is_some() {}
+                }
+            },
+            '#' => {
+                let end = if src[i..].starts_with("#*") {
+                    src[i+2..].find("*#").map_or(src.len(), |len| i + 2 + len + 2)
+                } else {
+                    src[i..].find('\n').map_or(src.len(), |len| i + len)
+                };
+                comments.push(Comment {
+                    index: i,
+                    text: src[i..end].trim_end().into(),
+                    trailing,
+                });
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+                continue;
+            },
+            ch if ch.is_whitespace() => continue,
+            _ => (),
+        }
+        trailing = true;
+    }
+    comments
+}
+
 fn get_token_name(s: &str) -> Option<&'static str> {
     match s {
         r###"r#"[_\\p{XID_Start}]\\p{XID_Continue}*|__lsp_hover__@"#"###
@@ -0,0 +1,88 @@
+//! 被嵌入编译器中的标准库
+//!
+//! 以`std/`开头的导入是保留的, 只会从此处嵌入的源码中加载,
+//! 而不会去寻找文件系统中的文件
+//!
+//! 源码位于该crate的`std`目录中, 以便随crate一同打包,
+//! `examples/std`是指向该目录的链接
+
+/// 标准库所占有的导入命名空间
+pub const NAMESPACE: &str = "std";
+
+macro_rules! modules {
+    ( $( $name:literal ),* $(,)? ) => {
+        &[ $(
+            ($name, include_str!(concat!(
+                "../std/", $name, ".mdtlbl",
+            ))),
+        )* ]
+    };
+}
+
+/// 所有被嵌入的模块名与其源码, 按名称排序
+pub const MODULES: &[(&str, &str)] = modules![
+    "alloc",
+    "array",
+    "count_loop",
+    "fmt",
+    "for_each",
+    "function",
+    "match_utils",
+    "math",
+    "mem",
+    "mempack",
+    "quick_sensor",
+    "select_table",
+    "stack",
+    "sync",
+    "timeit",
+];
+
+/// 获取模块的源码, 名称不包含命名空间与扩展名, 例如`function`
+pub fn get(name: &str) -> Option<&'static str> {
+    MODULES.binary_search_by_key(&name, |&(name, _)| name)
+        .ok()
+        .map(|i| MODULES[i].1)
+}
+
+/// 将`std/function`之类的导入路径分离出模块名, 扩展名可以省略
+pub fn module_name(import: &str) -> Option<&str> {
+    let name = import.strip_prefix(NAMESPACE)?.strip_prefix('/')?;
+    Some(name.strip_suffix(".mdtlbl").unwrap_or(name))
+}
+
+/// 标准库的修订号, 由所有模块的名称与源码计算而来
+///
+/// 用于在编译产物中记录其所使用的标准库
+pub fn revision() -> String {
+    // FNV-1a, 不使用std的Hasher是因为其结果在不同版本间并不保证稳定
+    let mut hash = 0xcbf29ce484222325_u64;
+    for (name, src) in MODULES {
+        for &byte in name.as_bytes().iter()
+            .chain(&[0])
+            .chain(src.as_bytes())
+            .chain(&[0])
+        {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modules_test() {
+        assert!(MODULES.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(get("function").unwrap().contains("const Function"));
+        assert_eq!(get("foo"), None);
+        assert_eq!(module_name("std/function"), Some("function"));
+        assert_eq!(module_name("std/function.mdtlbl"), Some("function"));
+        assert_eq!(module_name("stdx/function"), None);
+        assert_eq!(revision(), revision());
+        assert_eq!(revision().len(), 16);
+    }
+}
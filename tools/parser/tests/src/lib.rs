@@ -11045,3 +11045,127 @@ fn no_effect_hint_test() {
         "#
     }.hit_log(0);
 }
+
+#[test]
+fn import_test() {
+    use std::{fs, rc::Rc};
+    use parser::import::{Importer, ImportError};
+
+    let dir = std::env::temp_dir()
+        .join(format!("mdtlbl-import-test-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    let write = |name: &str, src: &str| {
+        fs::write(dir.join(name), src).unwrap();
+    };
+    let load = |src: &str| {
+        let mut importer = Importer::new();
+        importer.add_search_path(&dir);
+        importer.load(&mut Meta::new(), None, Rc::new(src.into()))
+    };
+    let compile = |src: &str| {
+        let (ast, source_map) = load(src).unwrap();
+        let mut meta = CompileMeta::new();
+        meta.set_source_map(source_map);
+        meta.compile(ast).compile().unwrap()
+    };
+
+    write("lib/a.mdtlbl", "const A = (print \"a\";);\n");
+    write("lib/b.mdtlbl", "import \"a\";\nconst B = (A!; A!;);\n");
+    assert_eq!(compile(r#"
+        import "lib/b";
+        import "lib/a";
+        B!;
+    "#), [r#"print "a""#, r#"print "a""#]);
+
+    // 文件只被加载一次
+    write("lib/c.mdtlbl", "print \"c\";\n");
+    assert_eq!(compile(r#"
+        import "lib/c";
+        import "lib/c.mdtlbl";
+        print "main";
+    "#), [r#"print "c""#, r#"print "main""#]);
+
+    write("lib/d.mdtlbl", "import \"e\";\n");
+    write("lib/e.mdtlbl", "import \"d\";\n");
+    let Err(ImportError::Circular(chain)) = load("import \"lib/d\";") else {
+        panic!("expected circular import")
+    };
+    assert_eq!(chain.len(), 3);
+    assert_eq!(chain.first(), chain.last());
+
+    let Err(ImportError::NotFound { path, at }) = load("\n import \"x\";")
+    else {
+        panic!("expected not found")
+    };
+    assert_eq!(path, "x");
+    assert_eq!(at.to_string(), "2:9");
+
+    // 位置指向被导入的文件
+    write("lib/f.mdtlbl", "const F = (\n  print $;\n);\n");
+    let (ast, source_map) = load("import \"lib/f\";\nF!;").unwrap();
+    let mut meta = CompileMeta::new();
+    meta.set_source_map(source_map);
+    let mut loc = None;
+    ast.iter().for_each(|line| {
+        let _ = walk::line(line, |line| {
+            if let LogicLine::Other(Args::Normal(args)) = line {
+                if let Some(Value::ResultHandle(Some(l))) = args.get(1) {
+                    loc = Some(meta.location(l));
+                }
+            }
+            std::ops::ControlFlow::Continue(())
+        });
+    });
+    let loc = loc.unwrap();
+    assert!(loc.file.as_ref().unwrap().ends_with("f.mdtlbl"));
+    assert_eq!(loc.pair(), (2, 9));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn std_import_test() {
+    use std::{fs, rc::Rc};
+    use parser::import::{Importer, ImportError};
+
+    let dir = std::env::temp_dir()
+        .join(format!("mdtlbl-std-import-test-{}", std::process::id()));
+    fs::create_dir_all(dir.join("std")).unwrap();
+    let load = |src: &str| {
+        let mut importer = Importer::new();
+        importer.add_search_path(&dir);
+        let result = importer.load(&mut Meta::new(), None, Rc::new(src.into()));
+        (result, importer.uses_std())
+    };
+
+    let (result, uses_std) = load(r#"
+        import "std/function";
+        import "std/function.mdtlbl";
+        print "main";
+    "#);
+    let (ast, source_map) = result.unwrap();
+    assert!(uses_std);
+    assert_eq!(source_map.files().len(), 2);
+    assert_eq!(
+        source_map.files()[1].name().map(|s| &**s),
+        Some("std/function.mdtlbl"),
+    );
+    let mut meta = CompileMeta::new();
+    meta.set_source_map(source_map);
+    assert_eq!(meta.compile(ast).compile().unwrap(), [r#"print "main""#]);
+
+    let (result, uses_std) = load("print 1;");
+    assert!(result.is_ok());
+    assert!(! uses_std);
+
+    // std命名空间是保留的, 不会从文件系统中寻找
+    fs::write(dir.join("std/local.mdtlbl"), "print 1;\n").unwrap();
+    let (Err(ImportError::NotFound { path, .. }), _)
+        = load("import \"std/local\";")
+    else {
+        panic!("expected not found")
+    };
+    assert_eq!(path, "std/local");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,67 @@
+//! 标准的带填充的base64, 与游戏中复制蓝图时使用的相同
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(ALPHABET[(n >> (18 - i * 6) & 0x3F) as usize].into());
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+/// 解码, 忽略其中的空白, 失败时返回首个非法字符的下标
+pub fn decode(s: &str) -> Result<Vec<u8>, usize> {
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+    let (mut n, mut bits) = (0u32, 0);
+    let mut padding = false;
+    for (i, ch) in s.char_indices() {
+        if ch.is_ascii_whitespace() { continue }
+        if ch == '=' {
+            padding = true;
+            continue;
+        }
+        let value = ALPHABET.iter()
+            .position(|&c| char::from(c) == ch)
+            .filter(|_| !padding)
+            .ok_or(i)?;
+        n = n << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_test() {
+        for (bytes, s) in [
+            (&b""[..], ""),
+            (b"m", "bQ=="),
+            (b"ms", "bXM="),
+            (b"msch", "bXNjaA=="),
+            (b"\xFF\x00\xFE", "/wD+"),
+        ] {
+            assert_eq!(encode(bytes), s);
+            assert_eq!(decode(s).unwrap(), bytes);
+        }
+        assert_eq!(decode("bXNj\naA==\n").unwrap(), b"msch");
+        assert_eq!(decode("bX*j"), Err(2));
+        assert_eq!(decode("bQ==bQ"), Err(4));
+    }
+}
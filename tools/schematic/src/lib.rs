@@ -0,0 +1,534 @@
+//! Mindustry的蓝图(`.msch`)格式
+//!
+//! 蓝图为`msch`头与版本号, 之后是zlib压缩的方块列表,
+//! 复制到剪贴板时为整个文件的base64, 所以总是以`bXNjaA`开头
+//!
+//! 所有整数都是大端序, 字符串为Java的`writeUTF`格式
+
+use std::{fmt::{self, Display}, str::FromStr};
+
+use miniz_oxide::{deflate::compress_to_vec_zlib, inflate::decompress_to_vec_zlib};
+
+pub mod base64;
+
+pub const HEADER: &[u8; 4] = b"msch";
+pub const VERSION: u8 = 1;
+/// 处理器配置的版本, 该版本的链接带有名称
+pub const CONFIG_VERSION: u8 = 1;
+const COMPRESS_LEVEL: u8 = 6;
+/// 配置为字节数组时的类型
+const BYTES_CONFIG: u8 = 14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// 不是以`msch`开头
+    Header,
+    /// 不支持的蓝图或处理器配置的版本
+    Version(u8),
+    /// 解压失败
+    Decompress,
+    /// 数据意外的结束
+    Eof,
+    /// 无法读取的方块配置的类型
+    ConfigType(u8),
+    Base64(usize),
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header => write!(f, "不是蓝图, 缺少 msch 头"),
+            Self::Version(version) => write!(f, "不支持的版本 {version}"),
+            Self::Decompress => write!(f, "解压失败"),
+            Self::Eof => write!(f, "数据意外的结束"),
+            Self::ConfigType(ty) => write!(f, "无法读取的方块配置类型 {ty}"),
+            Self::Base64(i) => write!(f, "base64 中第 {i} 字节处的非法字符"),
+        }
+    }
+}
+impl std::error::Error for Error { }
+
+/// 处理器的种类
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ProcessorType {
+    Micro,
+    #[default]
+    Logic,
+    Hyper,
+    World,
+}
+impl ProcessorType {
+    pub const ALL: [Self; 4] = [Self::Micro, Self::Logic, Self::Hyper, Self::World];
+
+    /// 方块的内部名称
+    pub fn block_name(&self) -> &'static str {
+        match self {
+            Self::Micro => "micro-processor",
+            Self::Logic => "logic-processor",
+            Self::Hyper => "hyper-processor",
+            Self::World => "world-processor",
+        }
+    }
+
+    pub fn size(&self) -> i16 {
+        match self {
+            Self::Micro | Self::World => 1,
+            Self::Logic => 2,
+            Self::Hyper => 3,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Micro => "micro",
+            Self::Logic => "logic",
+            Self::Hyper => "hyper",
+            Self::World => "world",
+        }
+    }
+}
+impl FromStr for ProcessorType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| format!("unknown processor type {s:?}, expected micro, logic, hyper or world"))
+    }
+}
+impl Display for ProcessorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 处理器的链接, 位置相对于处理器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub name: String,
+    pub x: i16,
+    pub y: i16,
+}
+
+/// 大端序的写入, 同Java的`DataOutputStream`
+#[derive(Debug, Default)]
+struct Writer(Vec<u8>);
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+
+    fn i16(&mut self, n: i16) {
+        self.0.extend(n.to_be_bytes());
+    }
+
+    fn i32(&mut self, n: i32) {
+        self.0.extend(n.to_be_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    /// Java的modified UTF-8, 空字符与辅助平面的字符编码方式与UTF-8不同
+    fn utf(&mut self, s: &str) {
+        let mut buf = vec![];
+        for unit in s.encode_utf16() {
+            match unit {
+                1..=0x7F => buf.push(unit as u8),
+                0 | 0x80..=0x7FF => buf.extend([
+                    0xC0 | (unit >> 6) as u8,
+                    0x80 | (unit & 0x3F) as u8,
+                ]),
+                _ => buf.extend([
+                    0xE0 | (unit >> 12) as u8,
+                    0x80 | (unit >> 6 & 0x3F) as u8,
+                    0x80 | (unit & 0x3F) as u8,
+                ]),
+            }
+        }
+        self.i16(buf.len() as i16);
+        self.bytes(&buf);
+    }
+}
+
+/// 大端序的读取, 同Java的`DataInputStream`
+#[derive(Debug)]
+struct Reader<'a>(&'a [u8]);
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.0.len() {
+            return Err(Error::Eof);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, Error> {
+        self.array().map(i16::from_be_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        self.array().map(i32::from_be_bytes)
+    }
+
+    fn len16(&mut self) -> Result<usize, Error> {
+        Ok(self.i16()? as u16 as usize)
+    }
+
+    fn len32(&mut self) -> Result<usize, Error> {
+        Ok(self.i32()?.max(0) as usize)
+    }
+
+    /// Java的modified UTF-8, 见[`Writer::utf`]
+    fn utf(&mut self) -> Result<String, Error> {
+        let len = self.len16()?;
+        let mut bytes = self.bytes(len)?.iter().map(|&b| u16::from(b));
+        let mut units = vec![];
+        while let Some(b) = bytes.next() {
+            let mut next = || bytes.next().map_or(0, |b| b & 0x3F);
+            units.push(match b {
+                0..=0x7F => b,
+                0xC0..=0xDF => (b & 0x1F) << 6 | next(),
+                _ => (b & 0x0F) << 12 | next() << 6 | next(),
+            });
+        }
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// 读取一个`TypeIO`的对象, 仅保留字节数组与字符串的值
+    fn config(&mut self) -> Result<Config, Error> {
+        let ty = self.u8()?;
+        let skip = |r: &mut Self, len: usize| r.bytes(len).map(|_| Config::Other(ty));
+        match ty {
+            0 => Ok(Config::Null),
+            4 => match self.u8()? {
+                0 => Ok(Config::String(None)),
+                _ => Ok(Config::String(Some(self.utf()?))),
+            },
+            BYTES_CONFIG => {
+                let len = self.len32()?;
+                Ok(Config::Bytes(self.bytes(len)?.to_vec()))
+            },
+            10 | 15 | 20 => skip(self, 1),
+            13 | 23 => skip(self, 2),
+            5 | 9 => skip(self, 3),
+            1 | 3 | 12 | 17 => skip(self, 4),
+            2 | 7 | 11 | 19 => skip(self, 8),
+            6 | 21 => {
+                let len = self.len16()?;
+                skip(self, len * 4)
+            },
+            8 => {
+                let len = self.u8()?;
+                skip(self, usize::from(len) * 4)
+            },
+            16 => {
+                let len = self.len32()?;
+                skip(self, len)
+            },
+            18 => {
+                let len = self.len16()?;
+                skip(self, len * 8)
+            },
+            22 => {
+                for _ in 0..self.len32()? {
+                    self.config()?;
+                }
+                Ok(Config::Other(ty))
+            },
+            _ => Err(Error::ConfigType(ty)),
+        }
+    }
+}
+
+/// 蓝图中方块的配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Config {
+    Null,
+    String(Option<String>),
+    /// 例如处理器的配置, 见[`encode_config`]
+    Bytes(Vec<u8>),
+    /// 其它类型的配置, 仅保留类型
+    Other(u8),
+}
+
+/// 蓝图中的一个方块, 位置为其所在的格子
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tile {
+    pub block: String,
+    pub x: i16,
+    pub y: i16,
+    pub config: Config,
+    pub rotation: u8,
+}
+impl Tile {
+    /// 是处理器时读取其代码与链接
+    pub fn processor(&self) -> Option<Result<(String, Vec<Link>), Error>> {
+        let Config::Bytes(config) = &self.config else { return None };
+        self.block.ends_with("-processor").then(|| decode_config(config))
+    }
+}
+
+/// 蓝图中的名称与方块等
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schematic {
+    pub width: i16,
+    pub height: i16,
+    pub tags: Vec<(String, String)>,
+    pub tiles: Vec<Tile>,
+}
+impl Schematic {
+    /// 从`.msch`文件的内容读取
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let body = bytes.strip_prefix(HEADER).ok_or(Error::Header)?;
+        let (&version, body) = body.split_first().ok_or(Error::Eof)?;
+        if version != VERSION {
+            return Err(Error::Version(version));
+        }
+        let body = decompress_to_vec_zlib(body).map_err(|_| Error::Decompress)?;
+        let mut r = Reader(&body);
+        let (width, height) = (r.i16()?, r.i16()?);
+        let tags = (0..r.u8()?)
+            .map(|_| Ok((r.utf()?, r.utf()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let blocks = (0..r.u8()?)
+            .map(|_| r.utf())
+            .collect::<Result<Vec<_>, Error>>()?;
+        let tiles = (0..r.len32()?)
+            .map(|_| {
+                let block = blocks.get(usize::from(r.u8()?)).ok_or(Error::Eof)?;
+                let pos = r.i32()?;
+                Ok(Tile {
+                    block: block.clone(),
+                    x: (pos >> 16) as i16,
+                    y: pos as i16,
+                    config: r.config()?,
+                    rotation: r.u8()?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { width, height, tags, tiles })
+    }
+
+    /// 从剪贴板中的base64文本读取
+    pub fn from_base64(s: &str) -> Result<Self, Error> {
+        Self::from_bytes(&base64::decode(s).map_err(Error::Base64)?)
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| &**value)
+    }
+}
+
+/// 从处理器的配置中读取代码与链接, 见[`encode_config`]
+///
+/// 旧版本的配置中链接没有名称, 会使用空的名称,
+/// 其位置为打包在一个`i32`中的坐标, 同方块的位置
+pub fn decode_config(config: &[u8]) -> Result<(String, Vec<Link>), Error> {
+    let config = decompress_to_vec_zlib(config).map_err(|_| Error::Decompress)?;
+    let mut r = Reader(&config);
+    let version = r.u8()?;
+    if version > CONFIG_VERSION {
+        return Err(Error::Version(version));
+    }
+    let len = r.len32()?;
+    let code = String::from_utf8_lossy(r.bytes(len)?).into_owned();
+    let links = (0..r.len32()?)
+        .map(|_| Ok(if version == 0 {
+            let pos = r.i32()?;
+            Link { name: String::new(), x: (pos >> 16) as i16, y: pos as i16 }
+        } else {
+            Link { name: r.utf()?, x: r.i16()?, y: r.i16()? }
+        }))
+        .collect::<Result<_, Error>>()?;
+    Ok((code, links))
+}
+
+/// 处理器的配置, 即压缩后的代码与链接
+pub fn encode_config(code: &str, links: &[Link]) -> Vec<u8> {
+    let mut w = Writer::default();
+    let code = code.replace('\r', "");
+    w.u8(CONFIG_VERSION);
+    w.i32(code.len() as i32);
+    w.bytes(code.as_bytes());
+    w.i32(links.len() as i32);
+    for link in links {
+        w.utf(&link.name);
+        w.i16(link.x);
+        w.i16(link.y);
+    }
+    compress_to_vec_zlib(&w.0, COMPRESS_LEVEL)
+}
+
+/// 仅包含一个处理器的蓝图
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessorSchematic {
+    pub name: String,
+    pub processor: ProcessorType,
+    pub code: String,
+    /// 链接的建筑的名称, 例如`cell1`
+    ///
+    /// 蓝图中仅有处理器, 所以链接会被依次放置在处理器右侧的一行上,
+    /// 在放置蓝图后在这些位置建造对应的建筑即可
+    pub links: Vec<String>,
+}
+impl ProcessorSchematic {
+    pub fn new(name: impl Into<String>, processor: ProcessorType, code: impl Into<String>) -> Self {
+        Self { name: name.into(), processor, code: code.into(), links: vec![] }
+    }
+
+    /// 链接及其相对于处理器的位置
+    pub fn links(&self) -> Vec<Link> {
+        let right = self.processor.size() / 2;
+        self.links.iter()
+            .zip(right+1..)
+            .map(|(name, x)| Link { name: name.clone(), x, y: 0 })
+            .collect()
+    }
+
+    /// `.msch`文件的内容
+    ///
+    /// 蓝图的宽度会包括链接的位置, 以便放置蓝图时为这些建筑留出空间
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let size = self.processor.size();
+        // 偶数大小的方块所在的格子位于中心的左下方
+        let offset = (size - 1) / 2;
+        let width = self.links().iter()
+            .map(|link| offset + link.x + 1)
+            .fold(size, i16::max);
+        let mut w = Writer::default();
+        w.i16(width);
+        w.i16(size);
+        w.u8(2);
+        w.utf("name");
+        w.utf(&self.name);
+        w.utf("labels");
+        w.utf("[]");
+        w.u8(1);
+        w.utf(self.processor.block_name());
+        w.i32(1);
+        w.u8(0);
+        w.i32(i32::from(offset) << 16 | i32::from(offset));
+        let config = encode_config(&self.code, &self.links());
+        w.u8(BYTES_CONFIG);
+        w.i32(config.len() as i32);
+        w.bytes(&config);
+        w.u8(0);
+
+        let mut bytes = HEADER.to_vec();
+        bytes.push(VERSION);
+        bytes.extend(compress_to_vec_zlib(&w.0, COMPRESS_LEVEL));
+        bytes
+    }
+
+    /// 可以在游戏中直接从剪贴板导入的文本
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf_test() {
+        let mut w = Writer::default();
+        w.utf("a\0é中😀");
+        assert_eq!(w.0, [
+            0, 14,
+            b'a',
+            0xC0, 0x80,
+            0xC3, 0xA9,
+            0xE4, 0xB8, 0xAD,
+            0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80,
+        ]);
+    }
+
+    #[test]
+    fn config_test() {
+        let links = [Link { name: "cell1".into(), x: 2, y: -1 }];
+        let config = decompress_to_vec_zlib(&encode_config("print 1\r\nend", &links)).unwrap();
+        assert_eq!(config, [
+            &[1, 0, 0, 0, 11][..],
+            b"print 1\nend",
+            &[0, 0, 0, 1, 0, 5],
+            b"cell1",
+            &[0, 2, 0xFF, 0xFF],
+        ].concat());
+    }
+
+    #[test]
+    fn config_v0_test() {
+        let mut w = Writer::default();
+        w.u8(0);
+        w.i32(3);
+        w.bytes(b"end");
+        w.i32(2);
+        w.i32(2 << 16 | 0xFFFF);
+        w.i32(-1 << 16 | 3);
+        let config = compress_to_vec_zlib(&w.0, COMPRESS_LEVEL);
+        assert_eq!(decode_config(&config).unwrap(), ("end".into(), vec![
+            Link { name: String::new(), x: 2, y: -1 },
+            Link { name: String::new(), x: -1, y: 3 },
+        ]));
+    }
+
+    #[test]
+    fn schematic_test() {
+        let mut schematic = ProcessorSchematic::new("test", ProcessorType::Hyper, "end");
+        schematic.links.push("message1".into());
+        assert_eq!(schematic.links(), [Link { name: "message1".into(), x: 2, y: 0 }]);
+        let bytes = schematic.to_bytes();
+        assert_eq!(&bytes[..5], b"msch\x01");
+        let body = decompress_to_vec_zlib(&bytes[5..]).unwrap();
+        assert_eq!(&body[..5], &[0, 4, 0, 3, 2]);
+        let block = b"\x01\x00\x0Fhyper-processor\x00\x00\x00\x01\x00\x00\x01\x00\x01\x0E";
+        assert!(body.windows(block.len()).any(|w| w == block));
+        assert!(schematic.to_base64().starts_with("bXNjaA"));
+    }
+
+    #[test]
+    fn read_test() {
+        let mut w = Writer::default();
+        w.utf("a\0é中😀");
+        assert_eq!(Reader(&w.0).utf().unwrap(), "a\0é中😀");
+
+        let mut schematic = ProcessorSchematic::new("test", ProcessorType::Logic, "print 1");
+        schematic.links.push("cell1".into());
+        let read = Schematic::from_base64(&schematic.to_base64()).unwrap();
+        assert_eq!((read.width, read.height), (3, 2));
+        assert_eq!(read.tag("name"), Some("test"));
+        assert_eq!(read.tiles.len(), 1);
+        assert_eq!(read.tiles[0].block, "logic-processor");
+        let (code, links) = read.tiles[0].processor().unwrap().unwrap();
+        assert_eq!(code, "print 1");
+        assert_eq!(links, [Link { name: "cell1".into(), x: 2, y: 0 }]);
+
+        // 其它方块的配置
+        let mut w = Writer::default();
+        for config in [&[0][..], &[4, 1, 0, 1, b'a'], &[6, 0, 1, 0, 0, 0, 1], &[22, 0, 0, 0, 1, 10, 1]] {
+            w.bytes(config);
+        }
+        let mut r = Reader(&w.0);
+        assert_eq!(r.config().unwrap(), Config::Null);
+        assert_eq!(r.config().unwrap(), Config::String(Some("a".into())));
+        assert_eq!(r.config().unwrap(), Config::Other(6));
+        assert_eq!(r.config().unwrap(), Config::Other(22));
+        assert!(r.0.is_empty());
+        assert_eq!(Reader(&[99]).config(), Err(Error::ConfigType(99)));
+        assert_eq!(Schematic::from_bytes(b"msch\x01"), Err(Error::Decompress));
+        assert_eq!(Schematic::from_bytes(b"abc"), Err(Error::Header));
+    }
+}
@@ -104,7 +104,7 @@ macro_rules! build_builtin_funcs {
                         var
                     },
                     Err((code, e)) => {
-                        $meta.log_err(format_args!(
+                        $meta.log(Diagnostic::error(codes::BUILTIN_ERROR, format!(
                             "\
                             Builtin Function Error:\n\
                             name: {}, argc: {}\n\
@@ -114,7 +114,7 @@ macro_rules! build_builtin_funcs {
                             this.name(),
                             0$($(+mutil!(@ignore($var) 1))*)?,
                             e,
-                        ));
+                        )));
                         $meta.set_last_builtin_exit_code(code);
                         "__".into()
                     },
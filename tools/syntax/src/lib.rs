@@ -1,5 +1,6 @@
 mod builtins;
 pub mod walk;
+pub mod source_map;
 #[cfg(test)]
 mod tests;
 
@@ -26,10 +27,12 @@ use tag_code::{
     logic_parser::{Args as LArgs, IdxBox, ParseLine, ParseLines},
 };
 use utils::counter::Counter;
-use var_utils::{string_unescape, AsVarType};
+use var_utils::{string_unescape, AsVarType, Target};
 
 pub use either;
 pub use var_utils::Var;
+pub use source_map::{SourceMap, SourcePos};
+pub use diagnostic::{codes, Diagnostic, Severity, Span};
 
 macro_rules! impl_enum_froms {
     (impl From for $ty:ty { $(
@@ -158,22 +161,57 @@ pub enum Errors {
     Emulate,
 }
 
-/// 带有错误前缀, 并且文本为红色的eprintln
+/// 报告一个编译错误, 其后应调用[`CompileMeta::exit`]
+///
+/// 代码为[`codes`]中的名称, 在`;`之后可以附加一些附注
 macro_rules! err {
-    ( $fmtter:expr $(, $args:expr)* $(,)? ) => {
-        err!(None => $fmtter $(, $args)*);
-    };
-    ( $loc:expr => $fmtter:expr $(, $args:expr)* $(,)? ) => {
-        let err = format_args!($fmtter, $($args),*);
-        eprintln!("\x1b[1;31mCompileError:\n{err}\x1b[22;39m");
-        $crate::LAST_ERR.replace(($loc.into(), format!("CompileError: {err}")));
+    (
+        $code:ident, $loc:expr => $fmtter:expr $(, $args:expr)*
+        $(; $note:expr)* $(,)?
+    ) => {
+        $crate::report_err($crate::Diagnostic::error(
+            $crate::codes::$code,
+            format!($fmtter $(, $args)*),
+        )
+        .with_primary(Option::<SourcePos>::from($loc).map(Span::from))
+        $(.with_note($note))*);
     };
 }
 thread_local! {
-    static LAST_ERR: Cell<(Option<(u32, u32)>, String)>
-        = Cell::new((None, String::new()));
+    static LAST_ERR: Cell<Option<Diagnostic>> = const { Cell::new(None) };
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 带有错误前缀, 并且文本为红色的输出编译错误, 并将其记录
+fn report_err(mut diagnostic: Diagnostic) {
+    // 例如在语言服务器中时, 已生成代码的附注会是空的
+    for note in &mut diagnostic.notes {
+        note.truncate(note.trim_end().len());
+    }
+    diagnostic.notes.retain(|note| !note.is_empty());
+    if !QUIET.get() {
+        eprintln!("\x1b[1;31mCompileError:\n{diagnostic}\x1b[22;39m");
+    }
+    LAST_ERR.set(Some(diagnostic));
+}
+
+/// 设置为真时, 编译错误与日志仅被记录而不会输出到标准错误,
+/// 并且编译错误产生的终止不会触发panic hook, 返回之前的设置
+///
+/// 此时终止的负载为[`CompileExit`], 日志见[`CompileMeta::logs`]
+pub fn set_quiet(quiet: bool) -> bool {
+    QUIET.replace(quiet)
+}
+
+/// 取出最后一个编译错误
+pub fn take_last_err() -> Option<Diagnostic> {
+    LAST_ERR.take()
 }
 
+/// 在[`set_quiet`]时编译错误所产生的unwind的负载, 其中为退出码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompileExit(pub i32);
+
 pub type Location = usize;
 pub type Float = f64;
 type Line = IdxBox<ParseLine<'static>>;
@@ -209,6 +247,7 @@ impl TakeHandle for Var {
     fn take_handle(mut self, meta: &mut CompileMeta) -> Var {
         meta.debug_expand_env_status(&mut self);
         meta.debug_hover_var_status(&mut self);
+        meta.emulate_reference(&self);
         if let Some(value) = meta.const_expand_enter(&self) {
             // 是一个常量
             let res = match value.clone() {
@@ -351,6 +390,9 @@ impl Value {
 impl TakeHandle for Value {
     fn take_handle(self, meta: &mut CompileMeta) -> Var {
         if let Some(var) = self.try_eval_const_num_to_var(meta) {
+            if let Self::Var(name) = &self {
+                meta.emulate_reference(name);
+            }
             return var;
         }
         // 改为使用空字符串代表空返回字符串
@@ -367,13 +409,12 @@ impl TakeHandle for Value {
                     .unwrap_or_else(|| UNNAMED_VAR.into())
             },
             ref cmp @ Self::Cmper(Cmper(ref loc)) => {
-                let loc = loc.location(&meta.source);
-                err!(loc =>
-                    "{}最终未被展开的 Cmper, 位于: {}:{}\n{}",
+                let loc = meta.location(loc);
+                err!(CMPER_NOT_EXPANDED, loc.clone() =>
+                    "最终未被展开的 Cmper, 位于: {}\n{}",
+                    loc,
+                    cmp.display_src(meta);
                     meta.err_info().join("\n"),
-                    loc.0,
-                    loc.1,
-                    cmp.display_src(meta),
                 );
                 meta.log_info(format_args!("Cmper 仅被用于比较时内联, 不允许进行 take"));
                 meta.exit(6)
@@ -720,9 +761,9 @@ impl ValueBindRef {
                     if meta.source.is_empty() {
                         meta.log_err(format_args!("meta.source is empty"));
                     }
-                    let (line, column) = loc.location(&meta.source);
-                    meta.log_info_at(line, column, format_args!(
-                            "{line}:{column} Misses binder ref",
+                    let loc = meta.location(&loc);
+                    meta.log_info_at(loc.clone(), format_args!(
+                            "{loc} Misses binder ref",
                     ));
                     meta.log_expand_stack::<false>();
                 }
@@ -1130,15 +1171,16 @@ impl TakeHandle for DExp {
                 } else if value.is_var() {
                     *result = value.as_var().unwrap().clone();
                 } else {
-                    let loc = result.location(&meta.source);
-                    err!(loc =>
-                        "{}{}:{} 尝试在`DExp`的返回句柄处使用值不为Var的const, \
+                    let loc = meta.location(&result);
+                    err!(DEXP_RESULT_NOT_VAR, loc.clone() =>
+                        "{} 尝试在`DExp`的返回句柄处使用值不为Var的const, \
                             此处仅允许使用`Var`\n\
                             值: {}\n\
                             名称: {}",
-                        meta.err_info().join("\n"), loc.0, loc.1,
+                        loc,
                         value.display_src(meta),
-                        Value::Var(result.value).display_src(meta),
+                        Value::Var(result.value).display_src(meta);
+                        meta.err_info().join("\n"),
                     );
                     meta.exit(5);
                 }
@@ -1173,6 +1215,24 @@ impl TakeHandle for ValueBind {
     }
 }
 
+/// 源码中的一个测试块, 例如`test "name" { ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    /// 测试的名称, 位置为其在位置空间中的位置
+    pub name: IdxBox<Var>,
+    pub body: Expand,
+}
+impl TestCase {
+    /// 去除了引号的名称
+    pub fn name(&self) -> &str {
+        if Value::is_string(&self.name) {
+            &self.name[1..self.name.len()-1]
+        } else {
+            &self.name
+        }
+    }
+}
+
 /// 进行`词法&语法`分析时所依赖的元数据
 #[derive(Debug)]
 pub struct Meta {
@@ -1186,7 +1246,19 @@ pub struct Meta {
     op_expr_refs: Vec<Var>,
     unnamed_var: Var,
     line_pack: Vec<(Vec<LogicLine>, Vec<LogicLine>)>,
+    /// 当前解析的源在位置空间中的基址, 参见[`SourceMap`]
+    source_base: usize,
+    /// 源顶部的导入, 位置为相对于源的位置
+    imports: Vec<IdxBox<Var>>,
+    /// 解析到的测试块, 不会被编译到普通的输出中
+    tests: Vec<TestCase>,
+    /// 每个断言在位置空间中的范围, 断言的编号即为其索引
+    assertions: Vec<(usize, usize)>,
     pub testing: bool,
+    /// 是否在语句前插入[`LogicLine::Location`], 用于生成源码映射
+    pub record_locations: bool,
+    /// 生成的逻辑所面向的游戏版本, 决定op-expr中的`select`是否使用同名指令
+    pub target: Target,
 }
 impl Default for Meta {
     fn default() -> Self {
@@ -1200,7 +1272,13 @@ impl Default for Meta {
             op_expr_refs: vec![unnamed_var.clone()],
             unnamed_var,
             line_pack: vec![],
+            source_base: 0,
+            imports: vec![],
+            tests: vec![],
+            assertions: vec![],
             testing: false,
+            record_locations: false,
+            target: Target::default(),
         }
     }
 }
@@ -1244,8 +1322,12 @@ impl Meta {
 
     /// 弹出一个标签作用域,
     /// 用于const定义完成收集信息
-    pub fn pop_label_scope(&mut self) -> HashSet<Var> {
-        self.defined_labels.pop().unwrap()
+    ///
+    /// 按名称排序, 使相同的源码总是解析为相同的语法树
+    pub fn pop_label_scope(&mut self) -> Vec<Var> {
+        let mut labels = Vec::from_iter(self.defined_labels.pop().unwrap());
+        labels.sort_unstable_by(|a, b| str::cmp(a, b));
+        labels
     }
 
     pub fn ext_label_scope(&mut self) {
@@ -1382,6 +1464,63 @@ impl Meta {
             .collect();
         InlineBlock(packed).into()
     }
+
+    /// 在每个语句前插入其位置, 以记录生成的行来自哪个语句
+    ///
+    /// 仅在[`Self::record_locations`]时进行插入,
+    /// 且仅有单个语句时不进行插入, 以免干扰对单语句块的处理
+    pub fn locate_lines(
+        &self,
+        lines: impl IntoIterator<Item = IdxBox<LogicLine>>,
+    ) -> Vec<LogicLine> {
+        let lines = lines.into_iter().collect::<Vec<_>>();
+        if !self.record_locations || lines.len() < 2 {
+            return lines.into_iter().map(|line| line.value).collect();
+        }
+        lines.into_iter()
+            .flat_map(|line| {
+                [LogicLine::Location(line.new_value(())), line.value]
+            })
+            .collect()
+    }
+
+    pub fn source_base(&self) -> usize {
+        self.source_base
+    }
+
+    /// 设置之后解析的源的基址, 被创建的位置都会加上该基址
+    pub fn set_source_base(&mut self, source_base: usize) {
+        self.source_base = source_base;
+    }
+
+    pub fn add_import(&mut self, path: IdxBox<Var>) {
+        self.imports.push(path);
+    }
+
+    /// 取出已解析的导入
+    pub fn take_imports(&mut self) -> Vec<IdxBox<Var>> {
+        mem::take(&mut self.imports)
+    }
+
+    pub fn add_test(&mut self, test: TestCase) {
+        self.tests.push(test);
+    }
+
+    /// 取出已解析的测试块
+    pub fn take_tests(&mut self) -> Vec<TestCase> {
+        mem::take(&mut self.tests)
+    }
+
+    /// 记录一个断言的范围, 返回其编号
+    pub fn add_assertion(&mut self, start: usize, end: usize) -> usize {
+        self.assertions.push((start, end));
+        self.assertions.len() - 1
+    }
+
+    /// 所有断言在位置空间中的范围, 以编号为索引
+    pub fn assertions(&self) -> &[(usize, usize)] {
+        &self.assertions
+    }
 }
 
 pub trait FromMdtArgs<'a>: Sized {
@@ -2338,7 +2477,7 @@ impl Op {
 impl Compile for Op {
     fn compile(self, meta: &mut CompileMeta) {
         let args = self.generate_args(meta);
-        meta.parse_lines.push((0, args.into()).into())
+        meta.push(args.into())
     }
 }
 impl FromMdtArgs<'_> for Op {
@@ -2463,11 +2602,13 @@ impl Compile for Goto {
 pub struct Expand(pub Vec<LogicLine>);
 impl Compile for Expand {
     fn compile(self, meta: &mut CompileMeta) {
+        let location = meta.line_location();
         meta.with_block_and_env_args(|meta| {
             for line in self.0 {
                 line.compile(meta)
             }
         });
+        meta.set_line_location(location);
     }
 }
 impl From<Vec<LogicLine>> for Expand {
@@ -2504,9 +2645,11 @@ impl_derefs!(impl for Expand => (self: self.0): Vec<LogicLine>);
 pub struct InlineBlock(pub Vec<LogicLine>);
 impl Compile for InlineBlock {
     fn compile(self, meta: &mut CompileMeta) {
+        let location = meta.line_location();
         for line in self.0 {
             line.compile(meta)
         }
+        meta.set_line_location(location);
     }
 }
 impl From<Vec<LogicLine>> for InlineBlock {
@@ -2675,7 +2818,8 @@ impl Select {
                 0 => continue,
                 insert_counts => {
                     let end_tag = meta.get_tmp_tag();
-                    case.push(Line::new(0, ParseLine::new_always(
+                    let source = meta.line_source_id();
+                    case.push(Line::new(source, ParseLine::new_always(
                         end_tag.to_string().into(),
                     )));
                     case.extend(
@@ -2685,7 +2829,7 @@ impl Select {
                             .take(insert_counts - 1)
                             .flatten()
                     );
-                    case.push((0, end_tag.to_string().into()).into());
+                    case.push((source, end_tag.to_string().into()).into());
                 },
             }
         }
@@ -2849,6 +2993,7 @@ impl Const {
             Value::Var(var) => {
                 meta.debug_expand_env_status(var);
                 meta.debug_hover_var_status(var);
+                meta.emulate_reference(var);
                 if let Some(data) = meta.get_const_value(var) {
                     return self.extend_data(data.clone());
                 }
@@ -2865,6 +3010,7 @@ impl Const {
                             value: var.into(),
                             labels: vec![],
                             binder: None,
+                            location: None,
                         });
                     },
                     Either::Right(data) => {
@@ -2880,7 +3026,7 @@ impl Const {
     /// 使右半部分继承某个const值, 如果目标有的话, 返回绑定者
     fn extend_data(
         &mut self,
-        ConstData { value, labels, binder }: ConstData,
+        ConstData { value, labels, binder, .. }: ConstData,
     ) -> Option<Var> {
         self.1 = value;
         self.2 = labels;
@@ -3130,31 +3276,31 @@ impl Compile for ArgsRepeat {
                     meta.debug_hover_var_status(var);
                 }
                 let Some((n, _)) = value.try_eval_const_num(meta) else {
-                    let loc = loc.location(&meta.source);
-                    err!(loc =>
-                        "{}重复块次数不是数字, 位于: {}:{}\n{}",
+                    let loc = meta.location(&loc);
+                    err!(REPEAT_COUNT_NOT_NUMBER, loc.clone() =>
+                        "重复块次数不是数字, 位于: {}\n{}",
+                        loc,
+                        value.display_src(meta);
                         meta.err_info().join("\n"),
-                        loc.0, loc.1,
-                        value.display_src(meta),
                     );
                     meta.exit(6)
                 };
                 let n = n.round();
                 if n < 0.0 || !n.is_finite() {
-                    let loc = loc.location(&meta.source);
-                    err!(loc =>
-                        "{}重复块次数必须不小于0 ({}), 位于: {}:{}",
+                    let loc = meta.location(&loc);
+                    err!(REPEAT_COUNT_NEGATIVE, loc.clone() =>
+                        "重复块次数必须不小于0 ({}), 位于: {}",
+                        n, loc;
                         meta.err_info().join("\n"),
-                        n, loc.0, loc.1
                     );
                     meta.exit(6)
                 }
                 if n > 512.0 {
-                    let loc = loc.location(&meta.source);
-                    err!(loc =>
-                        "{}重复块次数过大 ({}), 位于: {}:{}",
+                    let loc = meta.location(&loc);
+                    err!(REPEAT_COUNT_TOO_LARGE, loc.clone() =>
+                        "重复块次数过大 ({}), 位于: {}",
+                        n, loc;
                         meta.err_info().join("\n"),
-                        n, loc.0, loc.1
                     );
                     meta.exit(6)
                 }
@@ -3181,7 +3327,7 @@ impl Compile for ArgsRepeat {
 
                 if !meta.args_repeat_flags.last().unwrap() { break }
                 if i >= meta.args_repeat_limit {
-                    err!(loc.location(&meta.source) =>
+                    err!(REPEAT_LIMIT_EXCEEDED, meta.location(&loc) =>
                         "Maximum repeat limit exceeded ({})",
                         meta.args_repeat_limit,
                     );
@@ -3217,12 +3363,12 @@ impl Compile for Match {
             }
         }
         if meta.enable_misses_match_log_info {
-            let (line, column) = loc.location(&meta.source);
+            let loc = meta.location(&loc);
             let args = args.into_iter()
                 .map(|v| v.display_src(meta).into_owned())
                 .collect::<Vec<_>>();
-            meta.log_info_at(line, column, format_args!(
-                    "{line}:{column} Misses match, [{}]",
+            meta.log_info_at(loc.clone(), format_args!(
+                    "{loc} Misses match, [{}]",
                     args.join(" "),
             ));
             meta.log_expand_stack::<false>();
@@ -3421,12 +3567,12 @@ impl Compile for ConstMatch {
             }
         }
         if meta.enable_misses_match_log_info {
-            let (line, column) = loc.location(&meta.source);
+            let loc = meta.location(&loc);
             let args = args.into_iter()
                 .map(|v| v.display_src(meta).into_owned())
                 .collect::<Vec<_>>();
-            meta.log_info_at(line, column, format_args!(
-                    "{line}:{column} Misses const match, [{}]",
+            meta.log_info_at(loc.clone(), format_args!(
+                    "{loc} Misses const match, [{}]",
                     args.join(" "),
             ));
             meta.log_expand_stack::<false>();
@@ -3714,8 +3860,8 @@ impl GSwitch {
         let loc = ids.unit();
         macro_rules! err1 {
             ($f:tt, $($t:tt)*) => {{
-                let loc = loc.location(&meta.source);
-                err!(loc => $f, format!("{}:{}", loc.0, loc.1), $($t)*);
+                let loc = meta.location(&loc);
+                err!(INVALID_GSWITCH_ID, loc.clone() => $f, loc, $($t)*);
             }};
         }
 
@@ -3923,6 +4069,9 @@ pub enum LogicLine {
     ArgsRepeat(ArgsRepeat),
     Match(Match),
     ConstMatch(ConstMatch),
+    /// 其后语句在位置空间中的位置, 什么也不生成,
+    /// 仅用于记录生成的行来自哪个语句
+    Location(IdxBox<()>),
 }
 impl Compile for LogicLine {
     fn compile(self, meta: &mut CompileMeta) {
@@ -3988,6 +4137,7 @@ impl Compile for LogicLine {
             Self::ArgsRepeat(args_repeat) => args_repeat.compile(meta),
             Self::Match(r#match) => r#match.compile(meta),
             Self::ConstMatch(r#match) => r#match.compile(meta),
+            Self::Location(loc) => meta.set_line_location(loc.index),
             Self::Ignore => (),
         }
     }
@@ -4004,6 +4154,14 @@ impl LogicLine {
         Self::Label(meta.add_defined_label(lab))
     }
 
+    /// Returns `true` if the logic line is [`Location`].
+    ///
+    /// [`Location`]: LogicLine::Location
+    #[must_use]
+    pub fn is_location(&self) -> bool {
+        matches!(self, Self::Location(..))
+    }
+
     /// Returns `true` if the logic line is [`Op`].
     ///
     /// [`Op`]: LogicLine::Op
@@ -4172,10 +4330,11 @@ pub struct ConstData {
     value: Value,
     labels: Vec<Var>,
     binder: Option<Var>,
+    location: Option<usize>,
 }
 impl ConstData {
     pub fn new(value: Value, labels: Vec<Var>) -> Self {
-        Self { value, labels, binder: None }
+        Self { value, labels, binder: None, location: None }
     }
 
     pub fn new_nolabel(value: Value) -> Self {
@@ -4195,6 +4354,11 @@ impl ConstData {
         self.labels.as_ref()
     }
 
+    /// 定义该常量的语句在位置空间中的位置
+    pub fn location(&self) -> Option<usize> {
+        self.location
+    }
+
     pub fn binder(&self) -> Option<&Var> {
         self.binder.as_ref()
     }
@@ -4308,9 +4472,22 @@ pub struct EmulateInfo {
     /// ...needs arg system
     pub exist_vars: Option<Vec<(Emulate, Var, bool)>>,
     pub location: Option<(u32, u32)>,
+    /// 位置所处的文件, 仅在其可被确定时存在
+    pub file: Option<Rc<str>>,
     pub diagnostic: Option<String>,
     pub is_error: bool,
     pub hover_doc: Option<String>,
+    pub reference: Option<VarReference>,
+}
+
+/// 一次对名称的使用, 参见[`EmulateConfig::record_references`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VarReference {
+    pub name: Var,
+    /// 使用处的语句在位置空间中的位置
+    pub location: usize,
+    /// 名称所指的常量的定义语句在位置空间中的位置, 不为常量时为空
+    pub define: Option<usize>,
 }
 
 #[derive(Debug, Default)]
@@ -4319,6 +4496,8 @@ pub struct EmulateConfig {
     pub complete_filter: Option<fn(&str) -> bool>,
     pub abort: bool,
     pub record_free_info: bool,
+    /// 记录每次对名称的使用, 包括在const展开中产生的使用
+    pub record_references: bool,
 }
 
 #[derive(Debug)]
@@ -4397,6 +4576,26 @@ impl<'a> Drop for HoverGuard<'a> {
     }
 }
 
+/// 源码中的一个注释, 词法分析时注释会被丢弃, 需要单独扫描
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// 注释在源码中的起始位置
+    pub index: usize,
+    /// 包括`#`在内的注释文本, 不包括行尾换行
+    pub text: String,
+    /// 注释所在行中, 注释之前是否有代码
+    pub trailing: bool,
+}
+
+/// 一个生成行的来源, 参见[`CompileMeta::line_sources`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineSource {
+    /// 产生该行的语句在位置空间中的位置
+    pub index: usize,
+    /// 产生该行时的const展开栈, 由外至内, 同[`CompileMeta::debug_expand_stack`]
+    pub expand_stack: Vec<(Var, Option<Var>)>,
+}
+
 pub struct CompileMeta {
     extender: Option<Box<dyn CompileMetaExtends>>,
     parse_lines: ParseLines<'static>,
@@ -4427,8 +4626,13 @@ pub struct CompileMeta {
     noop_line: String,
     /// 保证不会是字符串
     bind_custom_sep: Option<Var>,
-    log_count: usize,
+    logs: Vec<Diagnostic>,
     source: Rc<String>,
+    source_map: SourceMap,
+    /// 正在编译的语句在位置空间中的位置
+    line_location: usize,
+    record_line_sources: bool,
+    line_sources: Vec<LineSource>,
     pub emutale_config: Option<EmulateConfig>,
     pub emulate_infos: Cell<Vec<EmulateInfo>>,
 }
@@ -4500,8 +4704,12 @@ impl CompileMeta {
             args_repeat_limit: 10000,
             args_repeat_flags: Vec::new(),
             bind_custom_sep: None,
-            log_count: 0,
+            logs: Vec::new(),
             source,
+            source_map: SourceMap::new(),
+            line_location: 0,
+            record_line_sources: false,
+            line_sources: Vec::new(),
             emutale_config: Default::default(),
             emulate_infos: vec![].into(),
         };
@@ -4624,7 +4832,25 @@ impl CompileMeta {
             | ParseLine::Jump(_, _)
             | ParseLine::Args(_) => (),
         }
-        self.parse_lines.push((0, data).into())
+        let source = self.line_source_id();
+        self.parse_lines.push((source, data).into())
+    }
+
+    /// 当前生成行的来源在[`Self::line_sources`]中的编号, 未记录时为0
+    fn line_source_id(&mut self) -> usize {
+        if !self.record_line_sources {
+            return 0;
+        }
+        let index = self.line_location;
+        let same = self.line_sources.last().is_some_and(|last| {
+            last.index == index
+                && last.expand_stack.iter().cloned().eq(self.debug_expand_stack())
+        });
+        if !same {
+            let expand_stack = self.debug_expand_stack().collect();
+            self.line_sources.push(LineSource { index, expand_stack });
+        }
+        self.line_sources.len() - 1
     }
 
     /// 向已生成代码`pop`
@@ -4659,21 +4885,40 @@ impl CompileMeta {
         }
     }
 
+    /// 记录一次对名称的使用, 仅在[`EmulateConfig::record_references`]时记录
+    fn emulate_reference(&self, name: &Var) {
+        if !self.emutale_config.as_ref().is_some_and(|it| it.record_references) {
+            return;
+        }
+        let define = self.get_const_value(name).and_then(ConstData::location);
+        self.emulate(EmulateInfo {
+            reference: Some(VarReference {
+                name: name.clone(),
+                location: self.line_location,
+                define,
+            }),
+            ..Default::default()
+        });
+    }
+
     fn exit(&self, code: i32) -> ! {
-        if self.emutale_config.is_some() {
-            let (location, error) = LAST_ERR.take();
-            if !error.is_empty() {
-                self.emulate(EmulateInfo {
-                    location,
-                    diagnostic: Some(error),
-                    is_error: true,
-                    ..Default::default()
-                });
-            }
+        if self.emutale_config.is_some()
+            && let Some(error) = LAST_ERR.take()
+        {
+            let location = error.primary.as_ref();
+            self.emulate(EmulateInfo {
+                location: location.map(|span| span.start),
+                file: location.and_then(|span| span.file.clone()),
+                diagnostic: Some(format!("CompileError: {error}")),
+                is_error: true,
+                ..Default::default()
+            });
         }
 
         if let Some(EmulateConfig { abort: true, .. }) = self.emutale_config {
             std::process::exit(code)
+        } else if QUIET.get() {
+            std::panic::resume_unwind(Box::new(CompileExit(code)))
         } else {
             panic!("exit code {code}")
         }
@@ -4892,6 +5137,7 @@ impl CompileMeta {
             {
                 self.debug_expand_env_status(&mut key);
                 let mut data = ConstData::new(value, labels);
+                data.location = Some(self.line_location);
                 if let Some(extra_binder) = extra_binder {
                     data = data.set_binder(extra_binder)
                 }
@@ -4903,6 +5149,7 @@ impl CompileMeta {
                 // FIXME 这似乎不起作用
                 self.debug_binds_status(&binder_handle, &mut name);
                 let mut data = ConstData::new(value, labels);
+                data.location = Some(self.line_location);
                 if let Some(extra_binder) = extra_binder
                     .or((binder_handle != GLOBAL_VAR)
                         .then_some(binder_handle.clone()))
@@ -4991,11 +5238,11 @@ impl CompileMeta {
 
     /// 对在DExp外部使用某些东西进行报错
     fn do_out_of_dexp_err(&self, value: &str, loc: IdxBox) -> ! {
-        let (line, col) = loc.location(&self.source);
-        err!((line, col) =>
-            "{}{line}:{col} 尝试在`DExp`的外部使用{}",
+        let loc = self.location(&loc);
+        err!(OUT_OF_DEXP, loc.clone() =>
+            "{loc} 尝试在`DExp`的外部使用{}",
+            value;
             self.err_info().join("\n"),
-            value,
         );
         self.exit(6)
     }
@@ -5028,7 +5275,7 @@ impl CompileMeta {
         let label_count = self.get_const_value(name)?.labels().len();
         if self.const_expand_names.len() >= self.const_expand_max_depth {
             self.log_expand_stack::<true>();
-            err!(
+            err!(RECURSION_LIMIT_EXCEEDED, None =>
                 "Maximum recursion depth exceeded ({})",
                 self.const_expand_max_depth,
             );
@@ -5038,7 +5285,7 @@ impl CompileMeta {
         tmp_tags.extend(repeat_with(|| self.get_tmp_tag())
                         .take(label_count));
 
-        let ConstData { value: _, labels, binder }
+        let ConstData { labels, binder, .. }
             = self.get_const_value(name).unwrap();
         let mut labels_map = HashMap::with_capacity(labels.len());
         for (tmp_tag, label) in zip(tmp_tags, labels.iter().cloned()) {
@@ -5185,48 +5432,64 @@ impl CompileMeta {
         self.env_args.pop().unwrap()
     }
 
-    pub fn log_info_at(&mut self, line: u32, column: u32, s: impl std::fmt::Display) {
+    pub fn log_info_at(&mut self, loc: SourcePos, s: impl std::fmt::Display) {
         if self.emutale_config.as_ref().is_some_and(|it| it.diagnostics) {
             self.emulate(EmulateInfo {
-                location: Some((line, column)),
+                location: Some(loc.pair()),
+                file: loc.file.clone(),
                 diagnostic: Some(s.to_string()),
                 ..Default::default()
             });
         }
-        self.log_info_naked(false, format_args!("{line}:{column} {s}"));
+        let message = format!("{loc} {s}");
+        self.log(Diagnostic::info(codes::LOG_INFO, message).with_primary(Span::from(loc)));
     }
 
     pub fn log_info(&mut self, s: impl std::fmt::Display) {
-        self.log_info_naked(true, s);
+        self.log(Diagnostic::info(codes::LOG_INFO, s.to_string()));
     }
 
-    fn log_info_naked(&mut self, record: bool, s: impl std::fmt::Display) {
-        self.log_count += 1;
-        eprintln!("{}", csi!(1; 22; "[I] {}",
-                s.to_string().trim_end().replace('\n', "\n    ")));
-        if record && self.emutale_config.as_ref().is_some_and(|it| it.record_free_info) {
-            self.emulate(EmulateInfo {
-                diagnostic: Some(format!("[I] {s}")),
-                ..Default::default()
-            });
-        }
+    pub fn log_err(&mut self, s: impl std::fmt::Display) {
+        self.log(Diagnostic::error(codes::LOG_ERROR, s.to_string()));
     }
 
-    pub fn log_err(&mut self, s: impl std::fmt::Display) {
-        self.log_count += 1;
-        eprintln!("{}", csi!(1, 91; 22, 39; "[E] {}",
-                s.to_string().trim_end().replace('\n', "\n    ")));
-        if self.emutale_config.as_ref().is_some_and(|it| it.record_free_info) {
+    /// 输出并记录一条日志, 其不会导致编译终止
+    ///
+    /// 没有位置的日志也会被记录至语言服务器的模拟信息中
+    pub fn log(&mut self, diagnostic: Diagnostic) {
+        let message = diagnostic.to_string();
+        let message = message.trim_end().replace('\n', "\n    ");
+        if !QUIET.get() {
+            match diagnostic.severity {
+                Severity::Error => eprintln!("{}", csi!(1, 91; 22, 39; "[E] {}", message)),
+                Severity::Warning => eprintln!("{}", csi!(1, 93; 22, 39; "[W] {}", message)),
+                Severity::Info => eprintln!("{}", csi!(1; 22; "[I] {}", message)),
+            }
+        }
+        if diagnostic.primary.is_none()
+            && self.emutale_config.as_ref().is_some_and(|it| it.record_free_info)
+        {
+            let prefix = match diagnostic.severity {
+                Severity::Error => "[E]",
+                Severity::Warning => "[W]",
+                Severity::Info => "[I]",
+            };
             self.emulate(EmulateInfo {
-                diagnostic: Some(format!("[E] {s}")),
-                is_error: true,
+                diagnostic: Some(format!("{prefix} {diagnostic}")),
+                is_error: diagnostic.is_error(),
                 ..Default::default()
             });
         }
+        self.logs.push(diagnostic);
     }
 
     pub fn log_count(&self) -> usize {
-        self.log_count
+        self.logs.len()
+    }
+
+    /// 编译期间输出的所有日志, 不包括导致终止的编译错误
+    pub fn logs(&self) -> &[Diagnostic] {
+        &self.logs
     }
 
     pub fn debug_expand_stack(&self) -> impl Iterator<
@@ -5301,6 +5564,44 @@ impl CompileMeta {
         self.source = source;
     }
 
+    /// 设置多个源文件的位置映射, 设置后位置将按照映射查询所在文件
+    pub fn set_source_map(&mut self, source_map: SourceMap) {
+        self.source_map = source_map;
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn line_location(&self) -> usize {
+        self.line_location
+    }
+
+    /// 设置之后生成的行所来自的语句位置
+    pub fn set_line_location(&mut self, index: usize) {
+        self.line_location = index;
+    }
+
+    /// 设置是否记录每个生成行的来源
+    ///
+    /// 记录时生成行的位置不再是0, 而是其来源在[`Self::line_sources`]中的编号
+    pub fn set_record_line_sources(&mut self, record: bool) {
+        self.record_line_sources = record;
+    }
+
+    /// 所有被记录的生成行来源
+    pub fn line_sources(&self) -> &[LineSource] {
+        &self.line_sources
+    }
+
+    /// 获取位置所在的文件与行列
+    pub fn location<T>(&self, loc: &IdxBox<T>) -> SourcePos {
+        self.source_map.location(loc.index).unwrap_or_else(|| {
+            let (line, column) = loc.location(&self.source);
+            SourcePos { file: None, line, column }
+        })
+    }
+
     pub fn extender(&self) -> Option<&dyn CompileMetaExtends> {
         self.extender.as_deref()
     }
@@ -5377,13 +5678,13 @@ impl CompileMeta {
             return;
         };
         if let Some(no_effect) = self.has_no_effect(&value.value) {
-            let (line, column) = value.location(&self.source);
+            let loc = self.location(&value);
             let no_effect = if let Some(ext) = self.extender() {
                 ext.display_value(&no_effect).into_owned()
             } else {
                 format!("{no_effect:#?}")
             };
-            self.log_info_at(line, column, format_args!("Take no effect: {no_effect}"));
+            self.log_info_at(loc, format_args!("Take no effect: {no_effect}"));
         }
     }
 }
@@ -5531,6 +5832,10 @@ impl OpExprType {
         true_value: Self,
         false_value: Self,
     ) -> Self {
+        // 目标版本中没有`select`指令时使用跳转实现
+        if !meta.target.is_v8() {
+            return Self::new_if_else(meta, cond.into(), true_value, false_value);
+        }
         Self::Select {
             cond,
             true_value: true_value.into_value(meta),
@@ -0,0 +1,134 @@
+//! 多个源文件共享同一个位置空间时的位置映射
+//!
+//! 被导入的文件在解析时会被分配一个基址, 其中产生的所有位置都加上了该基址,
+//! 所以需要通过该映射来找回其所在的文件与文件内的行列
+
+use std::{fmt::{self, Display}, rc::Rc};
+
+use diagnostic::Span;
+use tag_code::logic_parser::IdxBox;
+
+/// 一个被加入位置空间的源文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    name: Option<Rc<str>>,
+    base: usize,
+    text: Rc<String>,
+}
+impl SourceFile {
+    /// 文件名, 对于例如标准输入之类的没有名称的源则为空
+    pub fn name(&self) -> Option<&Rc<str>> {
+        self.name.as_ref()
+    }
+
+    /// 该文件在位置空间中的起始位置
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn text(&self) -> &Rc<String> {
+        &self.text
+    }
+
+    /// 该文件在位置空间中所占有的位置, 包括末尾位置
+    pub fn contains(&self, index: usize) -> bool {
+        (self.base..=self.base+self.text.len()).contains(&index)
+    }
+}
+
+/// 一个已经映射回具体文件的位置, 行列都从1开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcePos {
+    pub file: Option<Rc<str>>,
+    pub line: u32,
+    pub column: u32,
+}
+impl SourcePos {
+    pub fn pair(&self) -> (u32, u32) {
+        (self.line, self.column)
+    }
+}
+impl Display for SourcePos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl From<SourcePos> for Span {
+    fn from(pos: SourcePos) -> Self {
+        Span::point(pos.file, pos.line, pos.column)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 加入一个文件, 并返回它被分配的基址
+    ///
+    /// 每个文件之间至少间隔一个位置, 以使文件末尾的位置不会产生歧义
+    pub fn add_file(
+        &mut self,
+        name: Option<Rc<str>>,
+        text: Rc<String>,
+    ) -> usize {
+        let base = self.files.last()
+            .map_or(0, |file| file.base + file.text.len() + 1);
+        self.files.push(SourceFile { name, base, text });
+        base
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// 找到位置所处的文件
+    pub fn file_of(&self, index: usize) -> Option<&SourceFile> {
+        let i = self.files.partition_point(|file| file.base <= index);
+        self.files[..i].last()
+            .filter(|file| file.contains(index))
+    }
+
+    /// 获取位置所处文件与其在文件内的行列
+    pub fn location(&self, index: usize) -> Option<SourcePos> {
+        let file = self.file_of(index)?;
+        let (line, column) = IdxBox::new(index - file.base, ())
+            .location(&file.text);
+        Some(SourcePos { file: file.name.clone(), line, column })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_test() {
+        let mut map = SourceMap::new();
+        let root = map.add_file(None, Rc::new("a\nbc".into()));
+        let lib = map.add_file(Some("lib".into()), Rc::new("x\ny".into()));
+        assert_eq!(root, 0);
+        assert_eq!(lib, 5);
+
+        let pos = |index| map.location(index).unwrap().to_string();
+        assert_eq!(pos(0), "1:1");
+        assert_eq!(pos(3), "2:2");
+        assert_eq!(pos(4), "2:3");
+        assert_eq!(pos(5), "lib:1:1");
+        assert_eq!(pos(7), "lib:2:1");
+        assert_eq!(pos(8), "lib:2:2");
+        assert_eq!(map.location(9), None);
+    }
+}
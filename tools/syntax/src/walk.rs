@@ -8,7 +8,7 @@ use crate::{
 };
 
 pub fn line(line: &LogicLine, mut f: impl FnMut(&LogicLine) -> ControlFlow<()>) -> ControlFlow<()> {
-    walk_internal(line, &mut |elem| {
+    walk_internal(line, &mut |elem: Node<'_>| {
         match elem {
             Node::Line(logic_line) => f(logic_line),
             _ => ControlFlow::Continue(()),
@@ -30,6 +30,28 @@ pub fn nodes<'a>(nodes: impl IntoIterator<Item = impl Into<Node<'a>> + 'a>, mut
     ControlFlow::Continue(())
 }
 
+/// 同[`nodes`], 但是会在离开产生新的const作用域的节点时通知访问者,
+/// 即[`LogicLine::Expand`]与[`Value::DExp`]
+pub fn scoped_nodes<'a>(nodes: impl IntoIterator<Item = impl Into<Node<'a>> + 'a>, visitor: &mut impl ScopedVisitor) -> ControlFlow<()> {
+    for node in nodes {
+        walk_internal(node.into(), visitor)?;
+    }
+    ControlFlow::Continue(())
+}
+
+/// 访问每个节点, 并在离开作用域时得到通知
+pub trait ScopedVisitor {
+    fn visit(&mut self, node: Node<'_>) -> ControlFlow<()>;
+
+    /// 离开作用域, 对应的进入即访问到该节点时
+    fn leave_scope(&mut self) {}
+}
+impl<F: FnMut(Node<'_>) -> ControlFlow<()>> ScopedVisitor for F {
+    fn visit(&mut self, node: Node<'_>) -> ControlFlow<()> {
+        self(node)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Node<'a> {
     Value(&'a Value),
@@ -86,9 +108,9 @@ impl<'a> From<&'a LogicLine> for Node<'a> {
     }
 }
 
-fn walk_internal<'a>(elem: impl Into<Node<'a>>, f: &mut impl FnMut(Node<'_>) -> ControlFlow<()>) -> ControlFlow<()> {
+fn walk_internal<'a>(elem: impl Into<Node<'a>>, f: &mut impl ScopedVisitor) -> ControlFlow<()> {
     let elem = elem.into();
-    f(elem)?;
+    f.visit(elem)?;
     match elem {
         Node::Value(value) => match value {
             Value::Var(_) | Value::ReprVar(_) | Value::ResultHandle(_) | Value::Binder |
@@ -99,7 +121,10 @@ fn walk_internal<'a>(elem: impl Into<Node<'a>>, f: &mut impl FnMut(Node<'_>) ->
                 ClosuredValue::Uninit { value, .. } => walk_internal(value, f)?,
                 ClosuredValue::Inited { .. } | ClosuredValue::Empty => (),
             },
-            Value::DExp(dexp) => walk_lines_internal(dexp.lines.iter(), f)?,
+            Value::DExp(dexp) => {
+                walk_lines_internal(dexp.lines.iter(), f)?;
+                f.leave_scope();
+            },
             Value::Cmper(Cmper(cmp)) => walk_internal(cmp.as_ref().value, f)?,
         },
         Node::Line(logic_line) => match logic_line {
@@ -111,10 +136,13 @@ fn walk_internal<'a>(elem: impl Into<Node<'a>>, f: &mut impl FnMut(Node<'_>) ->
                     walk_internal(arg2, f)?;
                 }
             },
-            LogicLine::Label(_) | LogicLine::NoOp | LogicLine::Ignore | LogicLine::ConstLeak(_) => (),
+            LogicLine::Label(_) | LogicLine::NoOp | LogicLine::Ignore | LogicLine::ConstLeak(_) | LogicLine::Location(_) => (),
             LogicLine::Goto(Goto(_var, cmp)) => walk_internal(cmp, f)?,
             LogicLine::Other(args) => walk_args_internal(args, f)?,
-            LogicLine::Expand(expand) => walk_lines_internal(expand.iter(), f)?,
+            LogicLine::Expand(expand) => {
+                walk_lines_internal(expand.iter(), f)?;
+                f.leave_scope();
+            },
             LogicLine::InlineBlock(inline_block) => walk_lines_internal(inline_block.iter(), f)?,
             LogicLine::Select(select) => {
                 walk_internal(&select.0, f)?;
@@ -206,25 +234,25 @@ fn walk_internal<'a>(elem: impl Into<Node<'a>>, f: &mut impl FnMut(Node<'_>) ->
     ControlFlow::Continue(())
 }
 
-fn walk_lines_internal<'a>(lines: impl IntoIterator<Item = &'a LogicLine>, f: &mut impl FnMut(Node<'_>) -> ControlFlow<()>) -> ControlFlow<()> {
+fn walk_lines_internal<'a>(lines: impl IntoIterator<Item = &'a LogicLine>, f: &mut impl ScopedVisitor) -> ControlFlow<()> {
     lines.into_iter().try_for_each(|line| walk_internal(line, f))
 }
 
-fn walk_args_internal(args: &Args, f: &mut impl FnMut(Node<'_>) -> ControlFlow<()>) -> ControlFlow<()> {
+fn walk_args_internal(args: &Args, f: &mut impl ScopedVisitor) -> ControlFlow<()> {
     match args {
         Args::Normal(values) => values.iter().chain(const { &Vec::new() }),
         Args::Expanded(values, values1) => values.iter().chain(values1),
     }.try_for_each(|value| walk_internal(value, f))
 }
 
-fn walk_matchpat_internal(pats: &MatchPat, f: &mut impl FnMut(Node<'_>) -> ControlFlow<()>) -> ControlFlow<()> {
+fn walk_matchpat_internal(pats: &MatchPat, f: &mut impl ScopedVisitor) -> ControlFlow<()> {
     match pats {
         MatchPat::Normal(match_pat_atoms) => match_pat_atoms.iter().chain(const { &Vec::new() }),
         MatchPat::Expanded(match_pat_atoms, match_pat_atoms1) => match_pat_atoms.iter().chain(match_pat_atoms1),
     }.try_for_each(|value| walk_internal(value, f))
 }
 
-fn walk_cmatchpat_internal(pats: &ConstMatchPat, f: &mut impl FnMut(Node<'_>) -> ControlFlow<()>) -> ControlFlow<()> {
+fn walk_cmatchpat_internal(pats: &ConstMatchPat, f: &mut impl ScopedVisitor) -> ControlFlow<()> {
     match pats {
         ConstMatchPat::Normal(match_pat_atoms) => match_pat_atoms.iter().chain(const { &Vec::new() }),
         ConstMatchPat::Expanded(match_pat_atoms, _, match_pat_atoms1) => match_pat_atoms.iter().chain(match_pat_atoms1),
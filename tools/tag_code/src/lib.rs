@@ -8,9 +8,11 @@ use std::{
 };
 
 use logic_parser::{IdxBox, ParseLine, ParseLines};
+use var_utils::AsVarType;
 
 pub mod expr_builder;
 pub mod logic_parser;
+pub mod pass;
 
 pub type Tag = usize;
 pub type TagsTable = Vec<usize>;
@@ -33,6 +35,8 @@ pub trait Compile {
 pub struct TagBox<T> {
     tag: Option<Tag>,
     data: T,
+    /// 产生该行的位置, 例如构建自[`ParseLines`]时为其行的位置
+    source: Option<usize>,
 }
 impl<T> Deref for TagBox<T> {
     type Target = T;
@@ -48,7 +52,16 @@ impl<T> DerefMut for TagBox<T> {
 }
 impl<T> TagBox<T> {
     pub fn new(tag: Option<Tag>, data: T) -> Self {
-        Self { tag, data }
+        Self { tag, data, source: None }
+    }
+
+    pub fn with_source(mut self, source: Option<usize>) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn source(&self) -> Option<usize> {
+        self.source
     }
 
     pub fn tag(&self) -> Option<Tag> {
@@ -73,7 +86,7 @@ impl<T> TagBox<T> {
 }
 impl<T> From<(Option<Tag>, T)> for TagBox<T> {
     fn from((tag, data): (Option<Tag>, T)) -> Self {
-        Self { tag, data }
+        Self::new(tag, data)
     }
 }
 impl<T> From<(Tag, T)> for TagBox<T> {
@@ -165,6 +178,24 @@ impl Jump {
         matches!(jump_args[..], ["always", ..])
     }
 
+    /// 获取条件反转后的跳转, 与`JumpCmp::reverse`的对应关系相同
+    ///
+    /// 逻辑中没有`strictNotEqual`, 所以`strictEqual`与无条件跳转无法反转
+    pub fn reverse(&self) -> Option<Self> {
+        let args = mdt_logic_split_unwraped(&self.1);
+        let [cond, a, b] = args[..] else { return None };
+        let cond = match cond {
+            "equal" => "notEqual",
+            "notEqual" => "equal",
+            "lessThan" => "greaterThanEq",
+            "lessThanEq" => "greaterThan",
+            "greaterThan" => "lessThanEq",
+            "greaterThanEq" => "lessThan",
+            _ => return None,
+        };
+        Some(Self(self.0, format!("{cond} {a} {b}")))
+    }
+
     /// 校验跳转目标是否在行表中, 如果不在则进行恐慌
     pub fn check_target_unwrap(&self, tags_table: &TagsTable) {
         if self.0 >= tags_table.len() || tags_table[self.0] == UNINIT_TAG_TARGET {
@@ -355,6 +386,17 @@ impl TagLine {
         matches!(self, Self::TagDown(..))
     }
 
+    /// 产生该行的位置, [`TagDown`]没有位置
+    ///
+    /// [`TagDown`]: `Self::TagDown`
+    pub fn source(&self) -> Option<usize> {
+        match self {
+            Self::Jump(jump) => jump.source(),
+            Self::Line(line) => line.source(),
+            Self::TagDown(_) => None,
+        }
+    }
+
     /// 返回是否生成行, 用于未生成时预测生成后长度
     #[must_use]
     pub fn can_generate_line(&self) -> bool {
@@ -514,10 +556,13 @@ impl<'a> TryFrom<ParseLines<'a>> for TagCodes {
                 },
                 ParseLine::Jump(tgt, args) => {
                     let tag = get(line.new_value(tgt.as_ref()))?;
-                    Ok(TagLine::Jump(Jump(tag, args.join(" ")).into()))
+                    let jump = TagBox::from(Jump(tag, args.join(" ")));
+                    Ok(TagLine::Jump(jump.with_source(Some(line.index))))
                 },
                 ParseLine::Args(args) => {
-                    Ok(TagLine::Line(args.join(" ").into()))
+                    let line = TagBox::from(args.join(" "))
+                        .with_source(Some(line.index));
+                    Ok(TagLine::Line(line))
                 },
             }
         }).collect::<Result<_, IdxBox<ParseTagCodesError>>>()?;
@@ -672,11 +717,13 @@ impl TagCodes {
             let tag_refs: Vec<&mut Tag> = match &mut line {
                 TagLine::Jump(TagBox {
                     tag: Some(tag),
-                    data: Jump(j_dst, ..)
+                    data: Jump(j_dst, ..),
+                    ..
                 }) => vec![tag, j_dst],
                 TagLine::Jump(TagBox {
                     tag: None,
-                    data: Jump(j_dst, ..)
+                    data: Jump(j_dst, ..),
+                    ..
                 }) => vec![j_dst],
                 TagLine::Line(TagBox {
                     tag: Some(tag),
@@ -727,7 +774,8 @@ impl TagCodes {
             loop {
                 let line @ &TagLine::Jump(TagBox {
                     tag: self_tag,
-                    data: ref jump @ Jump(target_tag, _)
+                    data: ref jump @ Jump(target_tag, _),
+                    ..
                 }) = &lines[line_idx] else { break };
                 assert!(line.as_tag_down().is_none());
                 if Some(target_tag) == self_tag || ! jump.is_always_jump() { break }
@@ -751,6 +799,220 @@ impl TagCodes {
         }
     }
 
+    /// 删除无法到达的行, 返回被删除的行数
+    ///
+    /// 从首行开始, 沿着顺序执行与跳转目标进行可达性分析,
+    /// 无条件跳转, `end`与`stop`之后的行不会被顺序执行到,
+    /// 仍然被可达的跳转引用的标记所在的行总是会被保留
+    ///
+    /// 对`@counter`的使用无法进行分析, 所以其之后的所有行都会被视作可达,
+    /// 以保证其依赖的相对行偏移不会被改变,
+    /// 向`@counter`写入了数字时不会删除任何行, 见[`first_fixed_line`]
+    ///
+    /// 如果有重复的`Tag`, 返回其行下标及重复`Tag`
+    /// 会调用[`build_tagdown`]来改变源码
+    ///
+    /// [`build_tagdown`]: `TagCodes::build_tagdown`
+    /// [`first_fixed_line`]: `TagCodes::first_fixed_line`
+    pub fn eliminate_dead_code(&mut self) -> Result<usize, (usize, Tag)> {
+        self.build_tagdown()?;
+        if self.first_fixed_line() == 0 {
+            return Ok(0);
+        }
+
+        let mut tag_lines: HashMap<Tag, usize> = HashMap::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(tag) = line.tag() {
+                tag_lines.insert(tag, i);
+            }
+        }
+
+        let len = self.lines.len();
+        let mut reachable = vec![false; len];
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            if i >= len || reachable[i] { continue }
+            reachable[i] = true;
+            match &self.lines[i] {
+                TagLine::Jump(jump) => {
+                    // 跳转至不存在的标记将在编译时报错, 此处不处理
+                    stack.extend(tag_lines.get(&jump.0));
+                    if !jump.is_always_jump() {
+                        stack.push(i+1)
+                    }
+                },
+                TagLine::Line(line) => {
+                    let args = mdt_logic_split_unwraped(line);
+                    if args.contains(&"@counter") {
+                        stack.extend(i+1..len);
+                    } else if !matches!(args[..], ["end" | "stop", ..]) {
+                        stack.push(i+1)
+                    }
+                },
+                TagLine::TagDown(_) => unreachable!(),
+            }
+        }
+
+        let mut reachable = reachable.into_iter();
+        self.lines.retain(|_| reachable.next().unwrap());
+        Ok(len - self.lines.len())
+    }
+
+    /// 优化跳转, 返回进行的优化次数
+    ///
+    /// - 跳转至条件相同的条件跳转时, 直接跳转至其目标,
+    ///   条件相反时跳转至其下一行
+    /// - 将跳过一个无条件跳转的条件跳转反转为一个跳转
+    /// - 删除跳转至下一行的跳转
+    ///
+    /// 条件中带有`@`开头的变量时不进行跟踪, 因为其值可能在两行之间改变
+    ///
+    /// [`first_fixed_line`]及其之后的行依赖行号, 不会被删除
+    ///
+    /// 如果有重复的`Tag`, 返回其行下标及重复`Tag`
+    /// 会调用[`build_tagdown`]来改变源码
+    ///
+    /// [`build_tagdown`]: `TagCodes::build_tagdown`
+    /// [`first_fixed_line`]: `TagCodes::first_fixed_line`
+    pub fn optimize_jumps(&mut self) -> Result<usize, (usize, Tag)> {
+        let mut changes = 0;
+        loop {
+            self.build_tagdown()?;
+            let threaded = self.thread_jumps();
+            let removed = self.remove_redundant_jump();
+            if threaded + removed == 0 { break }
+            changes += threaded + removed;
+        }
+        Ok(changes)
+    }
+
+    fn tag_lines(&self) -> HashMap<Tag, usize> {
+        self.lines.iter()
+            .enumerate()
+            .filter_map(|(i, line)| Some((line.tag()?, i)))
+            .collect()
+    }
+
+    /// 首个不能被删除的行, 删除其之前的行不会改变依赖行号的执行
+    ///
+    /// 通常为首个使用了`@counter`的行, 其之后的行依赖相对的行偏移,
+    /// 但是向`@counter`写入数字时, 例如`set @counter 12`,
+    /// 所有行都依赖绝对的行号, 此时为0
+    pub fn first_fixed_line(&self) -> usize {
+        let mut first_counter_line = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            let args = match line {
+                TagLine::Jump(jump) => mdt_logic_split_unwraped(&jump.1),
+                TagLine::Line(line) => mdt_logic_split_unwraped(line),
+                TagLine::TagDown(_) => continue,
+            };
+            if let ["set", "@counter", value] = args[..] {
+                if value.as_var_type().is_number() {
+                    return 0;
+                }
+            }
+            if first_counter_line.is_none() && args.contains(&"@counter") {
+                first_counter_line = Some(i);
+            }
+        }
+        first_counter_line.unwrap_or(self.lines.len())
+    }
+
+    /// 跟踪条件跳转的目标, 返回改变了目标的跳转数
+    fn thread_jumps(&mut self) -> usize {
+        let tag_lines = self.tag_lines();
+        let len = self.lines.len();
+        let mut next_tag = self.lines.iter()
+            .flat_map(|line| [line.tag(), line.as_jump().map(|jump| jump.0)])
+            .flatten()
+            .max()
+            .map_or(0, |tag| tag + 1);
+        let mut threaded = 0;
+        for i in 0..len {
+            let Some(jump) = self.lines[i].as_jump() else { continue };
+            let args = mdt_logic_split_unwraped(&jump.1);
+            if jump.is_always_jump()
+                || args.iter().skip(1).any(|arg| arg.starts_with('@'))
+            {
+                continue;
+            }
+            let reversed = jump.reverse();
+            let mut visited = vec![i];
+            let mut target = jump.0;
+            // 条件相反时的目标行
+            let mut fallthrough = None;
+            while let Some(&line) = tag_lines.get(&target) {
+                let Some(next) = self.lines[line].as_jump() else { break };
+                if visited.contains(&line) {
+                    target = jump.0;
+                    break;
+                }
+                visited.push(line);
+                if next.1 == jump.1 {
+                    target = next.0;
+                } else if reversed.as_ref().is_some_and(|rev| rev.1 == next.1) {
+                    fallthrough = Some((line + 1) % len);
+                    break;
+                } else {
+                    break;
+                }
+            }
+            if let Some(line) = fallthrough {
+                target = match self.lines[line].tag() {
+                    Some(tag) => tag,
+                    None => {
+                        *self.lines[line].tag_mut() = Some(next_tag);
+                        next_tag += 1;
+                        next_tag - 1
+                    },
+                };
+            }
+            let jump = self.lines[i].as_jump_mut().unwrap();
+            if target != jump.0 {
+                jump.0 = target;
+                threaded += 1;
+            }
+        }
+        threaded
+    }
+
+    /// 反转跳过无条件跳转的条件跳转, 或者删除跳转至下一行的跳转,
+    /// 每次仅进行一处, 返回进行的次数
+    fn remove_redundant_jump(&mut self) -> usize {
+        let tag_lines = self.tag_lines();
+        let len = self.lines.len();
+        let counter_line = self.first_fixed_line();
+        let target_of = |jump: &Jump| tag_lines.get(&jump.0).copied();
+        let targeted = |line: &TagLine| line.tag().is_some_and(|tag| {
+            self.lines.iter()
+                .filter_map(TagLine::as_jump)
+                .any(|jump| jump.0 == tag)
+        });
+        for i in 0..counter_line {
+            let Some(jump) = self.lines[i].as_jump() else { continue };
+            if target_of(jump) == Some((i + 1) % len) {
+                let line = self.lines.remove(i);
+                if let Some(tag) = line.tag() {
+                    self.lines.insert(i, TagLine::TagDown(tag));
+                }
+                return 1;
+            }
+            if i + 1 >= counter_line || jump.is_always_jump() { continue }
+            let Some(TagLine::Jump(skipped)) = self.lines.get(i + 1) else { continue };
+            if skipped.is_always_jump()
+                && target_of(jump) == Some((i + 2) % len)
+                && !targeted(&self.lines[i + 1])
+            {
+                let Some(reversed) = jump.reverse() else { continue };
+                let reversed = Jump(skipped.0, reversed.1);
+                **self.lines[i].as_jump_mut().unwrap() = reversed;
+                self.lines.remove(i + 1);
+                return 1;
+            }
+        }
+        0
+    }
+
     /// 编译为逻辑行码
     /// 如果有重复的`Tag`, 返回其行下标及重复`Tag`
     /// 会调用[`build_tagdown`]来改变源码
@@ -814,6 +1076,17 @@ impl TagCodes {
         self.lines.len()
     }
 
+    /// 每个会生成逻辑行的行的来源,
+    /// 在[`build_tagdown`]之后与[`compile`]生成的逻辑行一一对应
+    ///
+    /// [`build_tagdown`]: `TagCodes::build_tagdown`
+    /// [`compile`]: `TagCodes::compile`
+    pub fn sources(&self) -> impl Iterator<Item = Option<usize>> + '_ {
+        self.lines.iter()
+            .filter(|line| line.can_generate_line())
+            .map(TagLine::source)
+    }
+
     /// 获取不是[`TagDown`]的代码条数
     ///
     /// [`TagDown`]: `TagLine::TagDown`
@@ -1244,6 +1517,159 @@ mod tests {
         }
     }
 
+    #[test]
+    fn eliminate_dead_code_test() {
+        let mut tag_codes = tag_lines! {
+            ["a"];
+            [jump 1 "always 0 0"];
+            ["dead"];
+            [:1 "b"];
+            [jump 0 "lessThan a b"];
+            ["end"];
+            [jump 2 "always 0 0"];
+            [:2 "dead"];
+            [:0 "c"];
+            ["stop"];
+            ["dead"];
+        };
+        assert_eq!(tag_codes.eliminate_dead_code(), Ok(4));
+        assert_eq!(tag_codes, tag_lines! {
+            ["a"];
+            [jump 1 "always 0 0"];
+            [:1 "b"];
+            [jump 0 "lessThan a b"];
+            ["end"];
+            [:0 "c"];
+            ["stop"];
+        });
+        assert_eq!(tag_codes.compile().unwrap(), [
+            "a",
+            "jump 2 always 0 0",
+            "b",
+            "jump 5 lessThan a b",
+            "end",
+            "c",
+            "stop",
+        ]);
+
+        let mut tag_codes = tag_lines! {
+            ["a"];
+            [jump 0 "always 0 0"];
+            [:1];
+            ["b"];
+            [:0 "c"];
+            [jump 1 "always 0 0"];
+        };
+        assert_eq!(tag_codes.eliminate_dead_code(), Ok(0));
+
+        let mut tag_codes = tag_lines! {
+            ["end"];
+            ["dead"];
+            ["op add @counter @counter i"];
+            ["end"];
+            ["a"];
+            ["b"];
+        };
+        assert_eq!(tag_codes.eliminate_dead_code(), Ok(5));
+        assert_eq!(tag_codes, tag_lines! { ["end"] });
+
+        let mut tag_codes = tag_lines! {
+            ["op add @counter @counter i"];
+            [jump 0 "always 0 0"];
+            ["a"];
+            [jump 0 "always 0 0"];
+            ["b"];
+            [:0 "end"];
+            ["dead"];
+        };
+        assert_eq!(tag_codes.eliminate_dead_code(), Ok(0));
+
+        // 写入@counter的绝对行号会因为删除而失效
+        let mut tag_codes = tag_lines! {
+            ["set @counter 3"];
+            [jump 0 "always 0 0"];
+            ["dead"];
+            [:0 "a"];
+        };
+        assert_eq!(tag_codes.eliminate_dead_code(), Ok(0));
+
+        assert_eq!(tag_lines! { }.eliminate_dead_code(), Ok(0));
+    }
+
+    #[test]
+    fn optimize_jumps_test() {
+        // 跳转至条件相同与相反的跳转
+        let mut tag_codes = tag_lines! {
+            [jump 0 "lessThan a b"];
+            ["x"];
+            [:0 jump 1 "lessThan a b"];
+            [jump 2 "greaterThanEq a b"];
+            ["y"];
+            [:2 jump 3 "lessThan a b"];
+            [:1 "z"];
+            [:3 "w"];
+        };
+        assert_eq!(tag_codes.optimize_jumps(), Ok(2));
+        assert_eq!(tag_codes.compile().unwrap(), [
+            "jump 6 lessThan a b",
+            "x",
+            "jump 6 lessThan a b",
+            "jump 6 greaterThanEq a b",
+            "y",
+            "jump 7 lessThan a b",
+            "z",
+            "w",
+        ]);
+
+        // 反转跳过无条件跳转的条件跳转, 并删除跳转至下一行的跳转
+        let mut tag_codes = tag_lines! {
+            [jump 0 "equal a b"];
+            [jump 1 "always 0 0"];
+            [:0 "x"];
+            [jump 2 "always 0 0"];
+            [:2];
+            [:1 "y"];
+        };
+        assert_eq!(tag_codes.optimize_jumps(), Ok(2));
+        assert_eq!(tag_codes.compile().unwrap(), [
+            "jump 2 notEqual a b",
+            "x",
+            "y",
+        ]);
+
+        // 无法反转的条件与被跳转的无条件跳转
+        let mut tag_codes = tag_lines! {
+            [jump 0 "strictEqual a b"];
+            [jump 1 "always 0 0"];
+            [:0 "x"];
+            [jump 2 "lessThan a b"];
+            [:3 jump 1 "always 0 0"];
+            [:2 "y"];
+            [jump 3 "always 0 0"];
+            [:1 "z"];
+        };
+        assert_eq!(tag_codes.optimize_jumps(), Ok(0));
+
+        // 使用了@counter之后的行, 与可能改变的变量
+        let mut tag_codes = tag_lines! {
+            ["op add @counter @counter i"];
+            [jump 0 "always 0 0"];
+            [:0 jump 1 "lessThan @time 3"];
+            [:1 jump 1 "lessThan @time 3"];
+        };
+        assert_eq!(tag_codes.optimize_jumps(), Ok(0));
+
+        // 写入了@counter的绝对行号
+        let mut tag_codes = tag_lines! {
+            [jump 0 "equal a b"];
+            [:0 "set @counter 3"];
+            ["x"];
+            ["y"];
+        };
+        assert_eq!(tag_codes.optimize_jumps(), Ok(0));
+        assert_eq!(tag_codes.first_fixed_line(), 0);
+    }
+
     #[test]
     fn empty_compile_test() {
         assert_eq!(tag_lines! { }.compile().unwrap(), Vec::<&str>::new());
@@ -0,0 +1,178 @@
+//! 对[`TagCodes`]进行的优化的管理
+//!
+//! 各个优化由使用者注册, 按照注册的顺序依次运行,
+//! 并重复运行直到一轮中所有优化都没有进行任何改变
+
+use std::fmt::{self, Display};
+
+use crate::{Tag, TagCodes};
+
+/// 进行优化, 返回进行的优化次数,
+/// 如果有重复的`Tag`, 返回其行下标及重复`Tag`
+pub type PassFn = fn(&mut TagCodes) -> Result<usize, (usize, Tag)>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Pass {
+    pub name: &'static str,
+    /// 默认启用该优化所需的最低优化等级,
+    /// 为[`Pass::MANUAL`]时仅在被单独启用时运行
+    pub level: u8,
+    pub run: PassFn,
+}
+impl Pass {
+    pub const MANUAL: u8 = u8::MAX;
+
+    pub fn new(name: &'static str, level: u8, run: PassFn) -> Self {
+        Self { name, level, run }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PassManager {
+    passes: Vec<(Pass, bool)>,
+}
+impl PassManager {
+    /// 最多重复运行的轮数, 防止优化之间互相抵消导致无法停止
+    pub const MAX_ROUNDS: usize = 16;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册一个优化, 初始时是未启用的
+    pub fn with(mut self, pass: Pass) -> Self {
+        self.passes.push((pass, false));
+        self
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = &Pass> {
+        self.passes.iter().map(|(pass, _)| pass)
+    }
+
+    /// 仅启用所有等级不高于`level`的优化
+    pub fn set_level(&mut self, level: u8) {
+        for (pass, enabled) in &mut self.passes {
+            *enabled = pass.level != Pass::MANUAL && pass.level <= level;
+        }
+    }
+
+    /// 启用或禁用一个优化, 如果没有该优化则返回`false`
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        self.passes.iter_mut()
+            .find(|(pass, _)| pass.name == name)
+            .map(|(_, pass_enabled)| *pass_enabled = enabled)
+            .is_some()
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.passes.iter().any(|(pass, enabled)| *enabled && pass.name == name)
+    }
+
+    /// 依次运行启用的优化, 直到一轮中没有进行任何改变
+    pub fn run(&self, tag_codes: &mut TagCodes) -> Result<PassReport, (usize, Tag)> {
+        let mut report = PassReport {
+            before: tag_codes.count_no_tag(),
+            ..Default::default()
+        };
+        for round in 0..Self::MAX_ROUNDS {
+            let mut changed = false;
+            for (pass, _) in self.passes.iter().filter(|(_, enabled)| *enabled) {
+                let before = tag_codes.count_no_tag();
+                let changes = (pass.run)(tag_codes)?;
+                let after = tag_codes.count_no_tag();
+                changed |= changes != 0;
+                report.runs.push(PassRun { name: pass.name, round, before, after, changes });
+            }
+            if !changed { break }
+        }
+        report.after = tag_codes.count_no_tag();
+        Ok(report)
+    }
+}
+
+/// 一次优化的运行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassRun {
+    pub name: &'static str,
+    /// 所在的轮数, 从0开始
+    pub round: usize,
+    /// 运行前后的行数
+    pub before: usize,
+    pub after: usize,
+    pub changes: usize,
+}
+
+/// 每个优化运行前后的行数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PassReport {
+    pub before: usize,
+    pub after: usize,
+    pub runs: Vec<PassRun>,
+}
+impl PassReport {
+    /// 优化在所有轮中进行的优化次数
+    pub fn changes(&self, name: &str) -> usize {
+        self.runs.iter()
+            .filter(|run| run.name == name)
+            .map(|run| run.changes)
+            .sum()
+    }
+}
+/// 第一轮之后仅显示进行了改变的运行
+impl Display for PassReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<6}{:<20}{:>8}{:>8}{:>9}", "round", "pass", "before", "after", "changes")?;
+        for run in &self.runs {
+            if run.round != 0 && run.changes == 0 { continue }
+            let PassRun { name, round, before, after, changes } = run;
+            writeln!(f, "{round:<6}{name:<20}{before:>8}{after:>8}{changes:>9}")?;
+        }
+        writeln!(f, "total: {} -> {}", self.before, self.after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TagLine;
+
+    fn remove_one(tag_codes: &mut TagCodes) -> Result<usize, (usize, Tag)> {
+        let lines = tag_codes.lines_mut();
+        let pos = lines.iter().position(|line| {
+            line.as_line().is_some_and(|line| ***line == *"dead")
+        });
+        Ok(pos.map(|i| lines.remove(i)).map_or(0, |_| 1))
+    }
+
+    fn nothing(_: &mut TagCodes) -> Result<usize, (usize, Tag)> {
+        Ok(0)
+    }
+
+    #[test]
+    fn pass_manager_test() {
+        let mut manager = PassManager::new()
+            .with(Pass::new("remove", 1, remove_one))
+            .with(Pass::new("nothing", 2, nothing))
+            .with(Pass::new("manual", Pass::MANUAL, nothing));
+        manager.set_level(2);
+        assert!(manager.is_enabled("remove") && manager.is_enabled("nothing"));
+        assert!(!manager.is_enabled("manual"));
+        assert!(manager.set_enabled("manual", true));
+        assert!(!manager.set_enabled("foo", true));
+
+        let mut tag_codes = TagCodes::from_tag_lines("a\ndead\ndead\nb");
+        let report = manager.run(&mut tag_codes).unwrap();
+        assert_eq!((report.before, report.after), (4, 2));
+        assert_eq!(report.changes("remove"), 2);
+        assert_eq!(report.runs.len(), 9);
+        assert_eq!(report.runs[3], PassRun {
+            name: "remove", round: 1, before: 3, after: 2, changes: 1,
+        });
+
+        manager.set_level(0);
+        let mut tag_codes = TagCodes::from_tag_lines("dead");
+        let report = manager.run(&mut tag_codes).unwrap();
+        assert_eq!(report.changes("remove"), 0);
+        assert!(matches!(tag_codes.lines()[..], [TagLine::Line(_)]));
+    }
+}
@@ -1,3 +1,4 @@
+mod target;
 mod var;
 
 use lazy_regex::{regex, Lazy, Regex};
@@ -7,6 +8,7 @@ use std::{
     thread_local,
 };
 
+pub use target::Target;
 pub use var::Var;
 
 /// 判断是否是一个标识符(包括数字)
@@ -0,0 +1,49 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// 生成的逻辑所面向的游戏版本, 决定了可用的指令与操作符
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Target {
+    V7,
+    V8,
+    /// v8的世界处理器, 包含所有指令
+    #[default]
+    World,
+}
+impl Target {
+    pub const ALL: [Self; 3] = [Self::V7, Self::V8, Self::World];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::V7 => "v7",
+            Self::V8 => "v8",
+            Self::World => "world",
+        }
+    }
+
+    /// 是否包含v8中新加入的指令与操作符, 例如`select`
+    pub fn is_v8(self) -> bool {
+        !matches!(self, Self::V7)
+    }
+
+    /// 是否可以使用仅世界处理器可用的指令
+    pub fn is_world(self) -> bool {
+        matches!(self, Self::World)
+    }
+}
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter()
+            .find(|target| target.name() == s)
+            .ok_or_else(|| format!("unknown target {s:?}, expected v7, v8 or world"))
+    }
+}
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
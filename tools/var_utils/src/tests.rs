@@ -214,3 +214,14 @@ fn escape_doublequote_test() {
         assert_eq!(escaped.as_deref(), Ok(dst), "src: {src}");
     }
 }
+
+#[test]
+fn target_test() {
+    for target in Target::ALL {
+        assert_eq!(target.name().parse(), Ok(target));
+    }
+    assert!("v9".parse::<Target>().is_err());
+    assert_eq!(Target::default(), Target::World);
+    assert!(!Target::V7.is_v8() && Target::V8.is_v8());
+    assert!(!Target::V8.is_world() && Target::World.is_world());
+}